@@ -1,14 +1,15 @@
 use std::collections::HashMap;
-use std::process::Stdio;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{debug, error, info, warn};
 
+use super::recorder::{AcpRecorder, FrameDirection};
+use super::transport::{AgentTransport, StdioTransport, TransportConnection};
 use super::types::*;
 
 #[derive(Debug, thiserror::Error)]
@@ -33,173 +34,221 @@ pub enum AcpError {
 
     #[error("Not connected")]
     NotConnected,
+
+    #[error("Not supported by agent: {0}")]
+    Unsupported(String),
+
+    #[error("Sandbox error: {0}")]
+    Sandbox(String),
+
+    #[error("Agent environment error: {0}")]
+    Environment(String),
+
+    #[error("Agent speaks ACP protocol version {0}, client supports up to {PROTOCOL_VERSION}")]
+    ProtocolVersion(u16),
+
+    #[error("Unknown agent: {0}")]
+    UnknownAgent(String),
+
+    #[error("Agent process exited: {0}")]
+    AgentExited(String),
+
+    #[error("Authentication required by agent {0}")]
+    AuthenticationRequired(String),
+}
+
+impl AcpError {
+    /// Whether the agent refused the request until the user authenticates
+    pub fn is_auth_required(&self) -> bool {
+        matches!(self, AcpError::Rpc { code, .. } if *code == AUTH_REQUIRED_ERROR_CODE)
+    }
+}
+
+/// How the agent process ended
+#[derive(Debug, Clone)]
+pub struct AgentExit {
+    /// Whether the exit was requested through `disconnect`
+    pub requested: bool,
+    pub status: String,
 }
 
 pub type Result<T> = std::result::Result<T, AcpError>;
 
 type PendingRequest = oneshot::Sender<std::result::Result<serde_json::Value, AcpError>>;
 
+/// Timeout for requests without a more specific one
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+/// The handshake should answer quickly; a hung agent fails fast
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the response to `method`, `None` waits until answered.
+/// Prompt turns can run for a long time and are ended with `session/cancel`;
+/// authentication may wait on the user finishing a login flow.
+fn request_timeout(method: &str) -> Option<Duration> {
+    match method {
+        "initialize" => Some(INITIALIZE_TIMEOUT),
+        "session/prompt" | "authenticate" => None,
+        _ => Some(DEFAULT_REQUEST_TIMEOUT),
+    }
+}
+
+/// Removes a request from the pending map when its caller stops waiting
+/// (timeout or dropped future) and tells the agent with `$/cancel_request`
+struct PendingGuard {
+    id: u64,
+    pending_requests: Arc<RwLock<HashMap<u64, PendingRequest>>>,
+    write_tx: mpsc::Sender<String>,
+    /// Whether the request reached the agent, otherwise there is nothing to cancel
+    sent: bool,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        // Already answered (or failed on agent exit)
+        if self.pending_requests.write().remove(&self.id).is_none() || !self.sent {
+            return;
+        }
+
+        debug!("Cancelling request {}", self.id);
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "$/cancel_request".to_string(),
+            params: Some(serde_json::json!({ "requestId": self.id })),
+        };
+        if let Ok(json) = serde_json::to_string(&notification) {
+            // Best effort, can't await in drop
+            let _ = self.write_tx.try_send(json);
+        }
+    }
+}
+
 pub struct AcpClient {
-    child: Option<Child>,
+    /// Tells the exit watcher to kill the child (also fires when dropped)
+    kill_tx: Mutex<Option<oneshot::Sender<()>>>,
+    /// Set before a requested shutdown so the exit isn't reported as a crash
+    stopping: Arc<AtomicBool>,
+    exit_rx: RwLock<Option<watch::Receiver<Option<AgentExit>>>>,
     request_id: AtomicU64,
     pending_requests: Arc<RwLock<HashMap<u64, PendingRequest>>>,
-    write_tx: Option<mpsc::Sender<String>>,
+    write_tx: Arc<RwLock<Option<mpsc::Sender<String>>>>,
     notification_tx: mpsc::Sender<SessionNotification>,
     permission_tx: mpsc::Sender<PermissionRequest>,
+    agent_request_tx: mpsc::Sender<TaggedAgentRequest>,
+    /// Agent instance this client talks to, tagged onto the agent's requests
+    agent_id: String,
+    /// Receives the agent's stderr line by line
+    stderr_tx: Option<mpsc::Sender<String>>,
+    /// Records every frame sent and received (opt-in)
+    recorder: Option<Arc<AcpRecorder>>,
+    /// Agent's answer to `initialize` (capabilities, auth methods)
+    initialize_response: RwLock<Option<InitializeResponse>>,
 }
 
 impl AcpClient {
     pub fn new(
         notification_tx: mpsc::Sender<SessionNotification>,
         permission_tx: mpsc::Sender<PermissionRequest>,
+        agent_request_tx: mpsc::Sender<TaggedAgentRequest>,
     ) -> Self {
         Self {
-            child: None,
+            kill_tx: Mutex::new(None),
+            stopping: Arc::new(AtomicBool::new(false)),
+            exit_rx: RwLock::new(None),
             request_id: AtomicU64::new(1),
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
-            write_tx: None,
+            write_tx: Arc::new(RwLock::new(None)),
             notification_tx,
             permission_tx,
+            agent_request_tx,
+            agent_id: String::new(),
+            stderr_tx: None,
+            recorder: None,
+            initialize_response: RwLock::new(None),
         }
     }
 
+    /// Tag the agent's requests with the instance they come from
+    pub fn with_agent_id(mut self, agent_id: impl Into<String>) -> Self {
+        self.agent_id = agent_id.into();
+        self
+    }
+
+    /// Forward the agent's stderr lines to a channel instead of only tracing them
+    pub fn with_stderr_sink(mut self, stderr_tx: mpsc::Sender<String>) -> Self {
+        self.stderr_tx = Some(stderr_tx);
+        self
+    }
+
+    /// Record all JSON-RPC traffic with the agent
+    pub fn with_recorder(mut self, recorder: Arc<AcpRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Spawn the agent process and talk to it over stdio
     pub async fn connect(
-        &mut self,
+        &self,
         command: &str,
         args: &[&str],
         env_vars: Option<Vec<(String, String)>>,
+        cwd: Option<&str>,
     ) -> Result<()> {
-        info!("Starting ACP agent: {} {:?}", command, args);
-
-        let mut cmd = Command::new(command);
-        cmd.args(args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        // Apply any custom environment variables (e.g., CLAUDE_CODE_EXECUTABLE for bundled CLI)
-        if let Some(vars) = env_vars {
-            for (key, value) in vars {
-                info!("Setting env var: {}={}", key, value);
-                cmd.env(key, value);
-            }
-        }
-
-        // On macOS, when launched via .app bundle (double-click), the environment is minimal.
-        // We need to load the user's shell environment to find npx and other tools.
-        #[cfg(target_os = "macos")]
-        {
-            // Try to get full shell environment by running a login shell
-            let shell_env = get_shell_environment();
-
-            // Apply shell environment variables (except a few we want to control)
-            for (key, value) in &shell_env {
-                // Skip PATH - we'll handle it specially
-                if key != "PATH" && key != "PWD" && key != "_" {
-                    cmd.env(key, value);
-                }
-            }
-
-            // Build comprehensive PATH
-            let shell_path = shell_env.get("PATH").map(|s| s.as_str()).unwrap_or("");
-            let current_path = std::env::var("PATH").unwrap_or_default();
-            let home = std::env::var("HOME").unwrap_or_default();
-
-            let mut additional_paths = vec![
-                "/usr/local/bin".to_string(),
-                "/opt/homebrew/bin".to_string(),
-                "/opt/local/bin".to_string(),
-                format!("{}/.local/bin", home),
-                format!("{}/Library/pnpm", home),
-                format!("{}/.bun/bin", home),
-                format!("{}/.cargo/bin", home),
-            ];
-
-            // Find nvm node versions
-            let nvm_versions_dir = format!("{}/.nvm/versions/node", home);
-            if let Ok(entries) = std::fs::read_dir(&nvm_versions_dir) {
-                for entry in entries.flatten() {
-                    let bin_path = entry.path().join("bin");
-                    if bin_path.exists() {
-                        additional_paths.push(bin_path.to_string_lossy().to_string());
-                    }
-                }
-            }
-
-            // Combine: additional paths + shell PATH + current PATH
-            let new_path = format!("{}:{}:{}", additional_paths.join(":"), shell_path, current_path);
-            cmd.env("PATH", new_path);
-        }
-
-        // On Linux, also ensure common paths are included
-        #[cfg(target_os = "linux")]
-        {
-            let current_path = std::env::var("PATH").unwrap_or_default();
-            let home = std::env::var("HOME").unwrap_or_default();
-
-            let additional_paths = vec![
-                "/usr/local/bin".to_string(),
-                format!("{}/.local/bin", home),
-                format!("{}/.bun/bin", home),
-                format!("{}/.cargo/bin", home),
-                format!("{}/.nvm/versions/node/*/bin", home), // Won't work as glob, but nvm sets PATH anyway
-            ];
-
-            // Find nvm node versions
-            let nvm_versions_dir = format!("{}/.nvm/versions/node", home);
-            if let Ok(entries) = std::fs::read_dir(&nvm_versions_dir) {
-                for entry in entries.flatten() {
-                    let bin_path = entry.path().join("bin");
-                    if bin_path.exists() {
-                        cmd.env("PATH", format!("{}:{}", bin_path.to_string_lossy(), current_path.clone()));
-                    }
-                }
-            }
-
-            let new_path = format!("{}:{}", additional_paths.join(":"), current_path);
-            cmd.env("PATH", new_path);
-        }
-
-        let mut child = cmd.spawn()?;
-
-        let stdin = child.stdin.take().ok_or_else(|| {
-            AcpError::Process("Failed to get stdin handle".to_string())
-        })?;
+        let transport = StdioTransport::new(command, args.iter().map(|s| s.to_string()).collect())
+            .with_env(env_vars.unwrap_or_default())
+            .with_cwd(cwd.map(String::from));
+        self.connect_transport(&transport).await
+    }
 
-        let stdout = child.stdout.take().ok_or_else(|| {
-            AcpError::Process("Failed to get stdout handle".to_string())
-        })?;
+    pub async fn connect_transport(&self, transport: &dyn AgentTransport) -> Result<()> {
+        info!("Starting ACP agent: {}", transport.describe());
+        let connection = transport.open().await?;
+        self.attach(connection);
+        Ok(())
+    }
 
-        let stderr = child.stderr.take().ok_or_else(|| {
-            AcpError::Process("Failed to get stderr handle".to_string())
-        })?;
+    /// Run the client over an open connection: framing, dispatch and exit watching
+    pub fn attach(&self, connection: TransportConnection) {
+        let TransportConnection {
+            reader,
+            mut writer,
+            stderr,
+            child,
+        } = connection;
 
         let (write_tx, mut write_rx) = mpsc::channel::<String>(100);
 
-        let mut stdin = stdin;
+        let recorder = self.recorder.clone();
         tokio::spawn(async move {
             while let Some(msg) = write_rx.recv().await {
-                if let Err(e) = stdin.write_all(msg.as_bytes()).await {
-                    error!("Failed to write to stdin: {}", e);
+                if let Some(ref recorder) = recorder {
+                    recorder.record(FrameDirection::Outbound, &msg);
+                }
+                if let Err(e) = writer.write_all(msg.as_bytes()).await {
+                    error!("Failed to write to agent: {}", e);
                     break;
                 }
-                if let Err(e) = stdin.write_all(b"\n").await {
+                if let Err(e) = writer.write_all(b"\n").await {
                     error!("Failed to write newline: {}", e);
                     break;
                 }
-                if let Err(e) = stdin.flush().await {
-                    error!("Failed to flush stdin: {}", e);
+                if let Err(e) = writer.flush().await {
+                    error!("Failed to flush agent connection: {}", e);
                     break;
                 }
             }
+            let _ = writer.shutdown().await;
         });
 
         let pending_requests = self.pending_requests.clone();
         let notification_tx = self.notification_tx.clone();
         let permission_tx = self.permission_tx.clone();
+        let agent_request_tx = self.agent_request_tx.clone();
+        let agent_id = self.agent_id.clone();
+        let reply_tx = write_tx.clone();
+        let recorder = self.recorder.clone();
 
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
+        let reader_task = tokio::spawn(async move {
+            let reader = BufReader::new(reader);
             let mut lines = reader.lines();
 
             while let Ok(Some(line)) = lines.next_line().await {
@@ -208,6 +257,9 @@ impl AcpClient {
                 }
 
                 debug!("Received: {}", line);
+                if let Some(ref recorder) = recorder {
+                    recorder.record(FrameDirection::Inbound, &line);
+                }
 
                 // Try to parse as a generic JSON object to determine message type
                 let Ok(msg) = serde_json::from_str::<serde_json::Value>(&line) else {
@@ -265,6 +317,7 @@ impl AcpClient {
                                                 session_id: params.session_id,
                                                 tool_call: params.tool_call,
                                                 options: params.options,
+                                                agent_id: agent_id.clone(),
                                             };
                                             let _ = permission_tx.send(permission_request).await;
                                         }
@@ -274,8 +327,30 @@ impl AcpClient {
                                     }
                                 }
                             }
-                            _ => {
-                                warn!("Unhandled agent request: {}", request.method);
+                            method => {
+                                let params = request.params.unwrap_or_default();
+                                match AgentRequest::parse(method, request.id.clone(), params) {
+                                    Ok(Some(request)) => {
+                                        debug!("Received agent request: {}", method);
+                                        let agent_id = agent_id.clone();
+                                        let _ = agent_request_tx.send(TaggedAgentRequest { agent_id, request }).await;
+                                    }
+                                    Ok(None) => {
+                                        warn!("Unhandled agent request: {}", method);
+                                        // Answer so the agent doesn't wait forever on a method we don't support
+                                        send_error_response(
+                                            &reply_tx,
+                                            request.id,
+                                            -32601,
+                                            format!("Method not found: {}", method),
+                                        )
+                                        .await;
+                                    }
+                                    Err(e) => {
+                                        error!("Failed to parse {} params: {}", method, e);
+                                        send_error_response(&reply_tx, request.id, -32602, e.to_string()).await;
+                                    }
+                                }
                             }
                         }
                     }
@@ -309,32 +384,119 @@ impl AcpClient {
             }
         });
 
+        if let Some(stderr) = stderr {
+            let stderr_tx = self.stderr_tx.clone();
+            tokio::spawn(async move {
+                let reader = BufReader::new(stderr);
+                let mut lines = reader.lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    match stderr_tx {
+                        Some(ref tx) => {
+                            debug!("Agent stderr: {}", line);
+                            let _ = tx.send(line).await;
+                        }
+                        None => warn!("Agent stderr: {}", line),
+                    }
+                }
+            });
+        }
+
+        *self.write_tx.write() = Some(write_tx);
+
+        // Watch the agent: when it exits, stop accepting requests and fail the pending ones.
+        // A spawned process ends when the child exits, a socket when the agent closes it.
+        let (kill_tx, kill_rx) = oneshot::channel::<()>();
+        let (exit_tx, exit_rx) = watch::channel(None);
+        let write_slot = self.write_tx.clone();
+        let pending_requests = self.pending_requests.clone();
+        let stopping = self.stopping.clone();
         tokio::spawn(async move {
-            let reader = BufReader::new(stderr);
-            let mut lines = reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                warn!("Agent stderr: {}", line);
+            let status = match child {
+                Some(mut child) => {
+                    let status = tokio::select! {
+                        status = child.wait() => status,
+                        _ = kill_rx => {
+                            let _ = child.kill().await;
+                            child.wait().await
+                        }
+                    };
+                    match status {
+                        Ok(status) => status.to_string(),
+                        Err(e) => e.to_string(),
+                    }
+                }
+                None => {
+                    let mut reader_task = reader_task;
+                    tokio::select! {
+                        _ = &mut reader_task => "connection closed".to_string(),
+                        _ = kill_rx => {
+                            reader_task.abort();
+                            "disconnected".to_string()
+                        }
+                    }
+                }
+            };
+
+            let requested = stopping.load(Ordering::SeqCst);
+            if requested {
+                info!("ACP agent stopped: {}", status);
+            } else {
+                error!("ACP agent exited unexpectedly: {}", status);
             }
-        });
 
-        self.child = Some(child);
-        self.write_tx = Some(write_tx);
+            *write_slot.write() = None;
+            let pending: Vec<_> = pending_requests.write().drain().collect();
+            for (_, sender) in pending {
+                let _ = sender.send(Err(AcpError::AgentExited(status.clone())));
+            }
 
-        Ok(())
+            let _ = exit_tx.send(Some(AgentExit { requested, status }));
+        });
+
+        *self.kill_tx.lock() = Some(kill_tx);
+        *self.exit_rx.write() = Some(exit_rx);
     }
 
-    pub async fn disconnect(&mut self) -> Result<()> {
-        self.write_tx = None;
+    pub async fn disconnect(&self) -> Result<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        *self.write_tx.write() = None;
 
-        if let Some(mut child) = self.child.take() {
-            let _ = child.kill().await;
+        let kill_tx = self.kill_tx.lock().take();
+        if let Some(kill_tx) = kill_tx {
+            let _ = kill_tx.send(());
+            self.wait_for_exit().await;
         }
 
         Ok(())
     }
 
+    /// Wait until the agent process has exited
+    pub async fn wait_for_exit(&self) -> AgentExit {
+        let exit_rx = self.exit_rx.read().clone();
+        let mut exit_rx = match exit_rx {
+            Some(exit_rx) => exit_rx,
+            None => {
+                return AgentExit {
+                    requested: true,
+                    status: "not started".to_string(),
+                }
+            }
+        };
+
+        let exit = exit_rx.wait_for(|exit| exit.is_some()).await.ok().and_then(|exit| exit.clone());
+        exit.unwrap_or(AgentExit {
+            requested: self.stopping.load(Ordering::SeqCst),
+            status: "exit watcher dropped".to_string(),
+        })
+    }
+
     pub fn is_connected(&self) -> bool {
-        self.write_tx.is_some()
+        self.write_tx.read().is_some()
+    }
+
+    /// Sender for outgoing messages, cloned so no lock is held across awaits
+    fn writer(&self) -> Result<mpsc::Sender<String>> {
+        self.write_tx.read().clone().ok_or(AcpError::NotConnected)
     }
 
     async fn send_request<T: serde::de::DeserializeOwned>(
@@ -342,7 +504,7 @@ impl AcpClient {
         method: &str,
         params: Option<serde_json::Value>,
     ) -> Result<T> {
-        let write_tx = self.write_tx.as_ref().ok_or(AcpError::NotConnected)?;
+        let write_tx = self.writer()?;
 
         let id = self.request_id.fetch_add(1, Ordering::SeqCst);
         let request = JsonRpcRequest {
@@ -354,30 +516,47 @@ impl AcpClient {
 
         let (response_tx, response_rx) = oneshot::channel();
 
+        let json = serde_json::to_string(&request)?;
+
         {
             let mut pending = self.pending_requests.write();
             pending.insert(id, response_tx);
         }
+        let mut guard = PendingGuard {
+            id,
+            pending_requests: self.pending_requests.clone(),
+            write_tx: write_tx.clone(),
+            sent: false,
+        };
+
+        // The agent may have exited after we took the writer; its pending requests were already failed
+        if !self.is_connected() {
+            return Err(AcpError::NotConnected);
+        }
 
-        let json = serde_json::to_string(&request)?;
         debug!("Sending: {}", json);
 
         write_tx
             .send(json)
             .await
             .map_err(|_| AcpError::ChannelClosed)?;
-
-        let result = tokio::time::timeout(std::time::Duration::from_secs(300), response_rx)
-            .await
-            .map_err(|_| AcpError::Timeout)?
-            .map_err(|_| AcpError::ChannelClosed)??;
+        guard.sent = true;
+
+        let response = match request_timeout(method) {
+            Some(timeout) => tokio::time::timeout(timeout, response_rx).await.map_err(|_| {
+                warn!("Request {} ({}) timed out after {:?}", id, method, timeout);
+                AcpError::Timeout
+            })?,
+            None => response_rx.await,
+        };
+        let result = response.map_err(|_| AcpError::ChannelClosed)??;
 
         let value: T = serde_json::from_value(result)?;
         Ok(value)
     }
 
     async fn send_notification(&self, method: &str, params: Option<serde_json::Value>) -> Result<()> {
-        let write_tx = self.write_tx.as_ref().ok_or(AcpError::NotConnected)?;
+        let write_tx = self.writer()?;
 
         let notification = JsonRpcNotification {
             jsonrpc: "2.0".to_string(),
@@ -398,41 +577,128 @@ impl AcpClient {
 
     pub async fn initialize(&self) -> Result<InitializeResponse> {
         let params = InitializeRequest {
-            protocol_version: 1,
+            protocol_version: PROTOCOL_VERSION,
             client_info: Some(Implementation {
                 name: "aero-work".to_string(),
                 title: Some("Aero Work".to_string()),
                 version: env!("CARGO_PKG_VERSION").to_string(),
             }),
             client_capabilities: Some(ClientCapabilities {
-                fs: None,      // Not implemented yet
-                terminal: None, // Not implemented yet
+                fs: Some(FileSystemCapability {
+                    read_text_file: Some(true),
+                    write_text_file: Some(true),
+                }),
+                terminal: Some(true),
             }),
         };
 
-        self.send_request("initialize", Some(serde_json::to_value(params)?))
-            .await
+        let response: InitializeResponse = self
+            .send_request("initialize", Some(serde_json::to_value(params)?))
+            .await?;
+
+        // The agent answers with our version or, if it can't speak it, its own latest
+        if response.protocol_version == 0 || response.protocol_version > PROTOCOL_VERSION {
+            return Err(AcpError::ProtocolVersion(response.protocol_version));
+        }
+
+        *self.initialize_response.write() = Some(response.clone());
+        Ok(response)
+    }
+
+    /// The agent's answer to the last `initialize` call
+    pub fn initialize_response(&self) -> Option<InitializeResponse> {
+        self.initialize_response.read().clone()
+    }
+
+    /// Capabilities advertised by the agent in `initialize`
+    pub fn agent_capabilities(&self) -> Option<AgentCapabilities> {
+        self.initialize_response
+            .read()
+            .as_ref()
+            .and_then(|r| r.agent_capabilities.clone())
     }
 
-    pub async fn create_session(&self, cwd: &str) -> Result<NewSessionResponse> {
+    /// Fail fast when the agent did not advertise an operation
+    fn require(&self, supported: fn(&AgentCapabilities) -> bool, operation: &str) -> Result<()> {
+        let caps = self.agent_capabilities().unwrap_or_default();
+        if supported(&caps) {
+            Ok(())
+        } else {
+            Err(AcpError::Unsupported(operation.to_string()))
+        }
+    }
+
+    /// Reject MCP transports the agent did not advertise (stdio is always supported)
+    fn check_mcp_servers(&self, mcp_servers: &[McpServer]) -> Result<()> {
+        let mcp = self
+            .agent_capabilities()
+            .and_then(|c| c.mcp_capabilities)
+            .unwrap_or_default();
+
+        for server in mcp_servers {
+            let (transport, supported) = match server {
+                McpServer::Http { .. } => ("http", mcp.http.unwrap_or(false)),
+                McpServer::Sse { .. } => ("sse", mcp.sse.unwrap_or(false)),
+                McpServer::Stdio { .. } => continue,
+            };
+            if !supported {
+                return Err(AcpError::Unsupported(format!(
+                    "MCP server '{}' uses {} transport",
+                    server.name(),
+                    transport
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn create_session(&self, cwd: &str, mcp_servers: Vec<McpServer>) -> Result<NewSessionResponse> {
+        self.check_mcp_servers(&mcp_servers)?;
         let params = NewSessionRequest {
             cwd: cwd.to_string(),
-            mcp_servers: vec![],
+            mcp_servers,
         };
 
         self.send_request("session/new", Some(serde_json::to_value(params)?))
             .await
     }
 
+    /// Load an existing session; the agent replays its history as `session/update` notifications
+    pub async fn load_session(
+        &self,
+        session_id: &str,
+        cwd: &str,
+        mcp_servers: Vec<McpServer>,
+    ) -> Result<LoadSessionResponse> {
+        self.require(AgentCapabilities::supports_load_session, "session/load")?;
+        self.check_mcp_servers(&mcp_servers)?;
+        let params = LoadSessionRequest {
+            session_id: session_id.to_string(),
+            cwd: cwd.to_string(),
+            mcp_servers,
+        };
+
+        info!("Loading session {} in {}", session_id, cwd);
+        self.send_request("session/load", Some(serde_json::to_value(params)?))
+            .await
+    }
+
     /// Resume an existing session (unstable API)
     ///
     /// This reattaches to an existing session without replaying history.
     /// The session must exist in ~/.claude/projects/{path_key}/{session_id}.jsonl
-    pub async fn resume_session(&self, session_id: &str, cwd: &str) -> Result<NewSessionResponse> {
+    pub async fn resume_session(
+        &self,
+        session_id: &str,
+        cwd: &str,
+        mcp_servers: Vec<McpServer>,
+    ) -> Result<NewSessionResponse> {
+        self.require(AgentCapabilities::supports_resume, "session/resume")?;
+        self.check_mcp_servers(&mcp_servers)?;
         let params = ResumeSessionRequest {
             session_id: session_id.to_string(),
             cwd: cwd.to_string(),
-            mcp_servers: vec![],
+            mcp_servers,
         };
 
         info!("Resuming session {} in {}", session_id, cwd);
@@ -443,11 +709,18 @@ impl AcpClient {
     /// Fork an existing session (unstable API)
     ///
     /// This creates a new session based on an existing one with a new ID.
-    pub async fn fork_session(&self, session_id: &str, cwd: &str) -> Result<NewSessionResponse> {
+    pub async fn fork_session(
+        &self,
+        session_id: &str,
+        cwd: &str,
+        mcp_servers: Vec<McpServer>,
+    ) -> Result<NewSessionResponse> {
+        self.require(AgentCapabilities::supports_fork, "session/fork")?;
+        self.check_mcp_servers(&mcp_servers)?;
         let params = ForkSessionRequest {
             session_id: session_id.to_string(),
             cwd: cwd.to_string(),
-            mcp_servers: vec![],
+            mcp_servers,
         };
 
         info!("Forking session {} in {}", session_id, cwd);
@@ -455,12 +728,33 @@ impl AcpClient {
             .await
     }
 
-    pub async fn prompt(&self, session_id: &str, content: &str) -> Result<PromptResponse> {
+    /// Reject content blocks the agent did not advertise in `PromptCapabilities`
+    /// (text and resource links are always supported)
+    pub fn check_prompt_content(&self, prompt: &[ContentBlock]) -> Result<()> {
+        let caps = self
+            .agent_capabilities()
+            .and_then(|c| c.prompt_capabilities)
+            .unwrap_or_default();
+
+        for block in prompt {
+            let (kind, supported) = match block {
+                ContentBlock::Image { .. } => ("image", caps.image.unwrap_or(false)),
+                ContentBlock::Audio { .. } => ("audio", caps.audio.unwrap_or(false)),
+                ContentBlock::Resource { .. } => ("embedded context", caps.embedded_context.unwrap_or(false)),
+                ContentBlock::Text { .. } | ContentBlock::ResourceLink { .. } => continue,
+            };
+            if !supported {
+                return Err(AcpError::Unsupported(format!("{} prompt content", kind)));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn prompt(&self, session_id: &str, prompt: Vec<ContentBlock>) -> Result<PromptResponse> {
+        self.check_prompt_content(&prompt)?;
         let params = PromptRequest {
             session_id: session_id.to_string(),
-            prompt: vec![ContentBlock::Text {
-                text: content.to_string(),
-            }],
+            prompt,
         };
 
         self.send_request("session/prompt", Some(serde_json::to_value(params)?))
@@ -476,6 +770,19 @@ impl AcpClient {
             .await
     }
 
+    /// Log in with one of the `authMethods` from `initialize`
+    pub async fn authenticate(&self, method_id: &str) -> Result<()> {
+        let params = AuthenticateRequest {
+            method_id: method_id.to_string(),
+        };
+
+        info!("Authenticating with method {}", method_id);
+        self.send_request::<serde_json::Value>("authenticate", Some(serde_json::to_value(params)?))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn set_session_mode(&self, session_id: &str, mode_id: &str) -> Result<()> {
         let params = SetSessionModeRequest {
             session_id: session_id.to_string(),
@@ -488,13 +795,24 @@ impl AcpClient {
         Ok(())
     }
 
+    /// Switch the session to one of the `availableModels` from its `models` state
+    pub async fn set_session_model(&self, session_id: &str, model_id: &str) -> Result<()> {
+        let params = SetSessionModelRequest {
+            session_id: session_id.to_string(),
+            model_id: model_id.to_string(),
+        };
+
+        self.send_request::<serde_json::Value>("session/set_model", Some(serde_json::to_value(params)?))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn respond_permission(
         &self,
         request_id: RequestId,
         outcome: PermissionOutcome,
     ) -> Result<()> {
-        let write_tx = self.write_tx.as_ref().ok_or(AcpError::NotConnected)?;
-
         // The result must be wrapped in RequestPermissionResult format
         // which has an "outcome" field containing the PermissionOutcome
         #[derive(serde::Serialize)]
@@ -504,16 +822,41 @@ impl AcpClient {
 
         let result = RequestPermissionResult { outcome };
 
-        // Send a JSON-RPC response to the agent's request
+        info!("Sending permission response for request {:?}", request_id);
+        self.respond(request_id, serde_json::to_value(result)?).await
+    }
+
+    /// Send a successful JSON-RPC response to a request from the agent
+    pub async fn respond(&self, request_id: RequestId, result: serde_json::Value) -> Result<()> {
         let response = JsonRpcResponse {
             jsonrpc: "2.0".to_string(),
             id: request_id,
-            result: Some(serde_json::to_value(result)?),
+            result: Some(result),
             error: None,
         };
+        self.send_response(response).await
+    }
+
+    /// Send a JSON-RPC error response to a request from the agent
+    pub async fn respond_error(&self, request_id: RequestId, code: i32, message: String) -> Result<()> {
+        let response = JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: request_id,
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message,
+                data: None,
+            }),
+        };
+        self.send_response(response).await
+    }
+
+    async fn send_response(&self, response: JsonRpcResponse) -> Result<()> {
+        let write_tx = self.writer()?;
 
         let json = serde_json::to_string(&response)?;
-        info!("Sending permission response: {}", json);
+        debug!("Sending response: {}", json);
 
         write_tx
             .send(json)
@@ -524,48 +867,107 @@ impl AcpClient {
     }
 }
 
+/// Reply to an agent request with a JSON-RPC error straight from the reader loop
+async fn send_error_response(write_tx: &mpsc::Sender<String>, id: RequestId, code: i32, message: String) {
+    let response = JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(JsonRpcError {
+            code,
+            message,
+            data: None,
+        }),
+    };
+    if let Ok(json) = serde_json::to_string(&response) {
+        let _ = write_tx.send(json).await;
+    }
+}
+
 impl Drop for AcpClient {
     fn drop(&mut self) {
-        if let Some(mut child) = self.child.take() {
-            let _ = child.start_kill();
+        // The exit watcher owns the child or connection; ask it to shut it down
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(kill_tx) = self.kill_tx.get_mut().take() {
+            let _ = kill_tx.send(());
         }
     }
 }
 
-/// Get environment variables from user's login shell.
-/// This is important on macOS where GUI apps don't inherit shell environment.
-#[cfg(target_os = "macos")]
-fn get_shell_environment() -> HashMap<String, String> {
-    use std::process::Command as StdCommand;
-
-    let mut env_map = HashMap::new();
-
-    // Try to get the user's default shell
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/zsh".to_string());
-
-    // Run a login shell to get environment variables
-    // Using -l for login shell and -c to execute a command
-    let result = StdCommand::new(&shell)
-        .args(["-l", "-c", "env"])
-        .output();
-
-    match result {
-        Ok(output) if output.status.success() => {
-            let env_output = String::from_utf8_lossy(&output.stdout);
-            for line in env_output.lines() {
-                if let Some((key, value)) = line.split_once('=') {
-                    env_map.insert(key.to_string(), value.to_string());
-                }
-            }
-            info!("Loaded {} environment variables from login shell", env_map.len());
-        }
-        Ok(output) => {
-            warn!("Login shell returned error: {:?}", String::from_utf8_lossy(&output.stderr));
-        }
-        Err(e) => {
-            warn!("Failed to run login shell {}: {}", shell, e);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{DuplexStream, Lines};
+
+    type Frames = Lines<BufReader<DuplexStream>>;
+
+    /// A client attached to an agent that reads every frame and never answers
+    fn silent_agent() -> (Arc<AcpClient>, Frames) {
+        let (client_end, agent_end) = tokio::io::duplex(64 * 1024);
+        let (notification_tx, _) = mpsc::channel(1);
+        let (permission_tx, _) = mpsc::channel(1);
+        let (agent_request_tx, _) = mpsc::channel(1);
+        let client = AcpClient::new(notification_tx, permission_tx, agent_request_tx);
+        client.attach(TransportConnection::from_stream(client_end));
+        (Arc::new(client), BufReader::new(agent_end).lines())
+    }
+
+    async fn next_frame(frames: &mut Frames) -> serde_json::Value {
+        let line = frames.next_line().await.unwrap().expect("client closed the connection");
+        serde_json::from_str(&line).unwrap()
     }
 
-    env_map
+    #[test]
+    fn test_request_timeout_per_method() {
+        assert_eq!(request_timeout("initialize"), Some(INITIALIZE_TIMEOUT));
+        assert_eq!(request_timeout("session/set_mode"), Some(DEFAULT_REQUEST_TIMEOUT));
+        assert_eq!(request_timeout("session/prompt"), None);
+        assert_eq!(request_timeout("authenticate"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_out_request_is_cancelled() {
+        let (client, mut frames) = silent_agent();
+
+        let started = tokio::time::Instant::now();
+        let result = client.set_session_mode("s1", "plan").await;
+        assert!(matches!(result, Err(AcpError::Timeout)));
+        assert!(started.elapsed() >= DEFAULT_REQUEST_TIMEOUT);
+        assert!(client.pending_requests.read().is_empty());
+
+        let request = next_frame(&mut frames).await;
+        assert_eq!(request["method"], "session/set_mode");
+        let cancel = next_frame(&mut frames).await;
+        assert_eq!(cancel["method"], "$/cancel_request");
+        assert_eq!(cancel["params"]["requestId"], request["id"]);
+        assert!(cancel.get("id").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prompt_waits_until_dropped() {
+        let (client, mut frames) = silent_agent();
+
+        let turn = tokio::spawn({
+            let client = client.clone();
+            async move {
+                let prompt = vec![ContentBlock::Text { text: "Hello".to_string() }];
+                client.prompt("s1", prompt).await
+            }
+        });
+        let request = next_frame(&mut frames).await;
+        assert_eq!(request["method"], "session/prompt");
+
+        // No timeout applies to a turn
+        tokio::time::sleep(DEFAULT_REQUEST_TIMEOUT * 2).await;
+        assert!(!turn.is_finished());
+        assert_eq!(client.pending_requests.read().len(), 1);
+
+        // Dropping the caller's future removes the request and cancels it
+        turn.abort();
+        assert!(turn.await.unwrap_err().is_cancelled());
+        assert!(client.pending_requests.read().is_empty());
+        let cancel = next_frame(&mut frames).await;
+        assert_eq!(cancel["method"], "$/cancel_request");
+        assert_eq!(cancel["params"]["requestId"], request["id"]);
+    }
 }
@@ -1,5 +1,16 @@
 pub mod types;
 pub mod client;
+pub mod recorder;
+pub mod sandbox;
+pub mod transport;
 
 pub use types::*;
-pub use client::{AcpClient, AcpError};
+pub use client::{AcpClient, AcpError, AgentExit};
+pub use recorder::{read_recording, recording_path, AcpRecorder, FrameDirection, RecordedFrame};
+pub use sandbox::SandboxProfile;
+pub use transport::{
+    transport_for_address, AgentTransport, MemoryTransport, StdioTransport, TcpTransport, TransportConnection,
+    WebSocketTransport,
+};
+#[cfg(unix)]
+pub use transport::UnixTransport;
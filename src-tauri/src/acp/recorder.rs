@@ -0,0 +1,343 @@
+//! ACP Recorder Module
+//!
+//! Opt-in recording of the JSON-RPC traffic between the client and its agents.
+//! Every frame is timestamped and appended to `<dir>/<session id>.ndjson`, so a
+//! session can be inspected or replayed later. Frames that belong to no session
+//! (initialize, authenticate) go to `unscoped.ndjson`, and so do requests that may
+//! create one (`session/new`), which are copied to the session file once the response
+//! names it. Credentials and the contents of files read by the agent are redacted
+//! before anything is written.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// File for frames that belong to no session
+const UNSCOPED_FILE: &str = "unscoped";
+/// Written in place of redacted values
+const REDACTED: &str = "[redacted]";
+/// Most requests kept for copying into the file of the session their response names;
+/// the oldest are dropped first when responses never come (e.g. the agent crashed)
+const MAX_HELD_REQUESTS: usize = 256;
+
+/// Which way a frame travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDirection {
+    /// Client to agent
+    Outbound,
+    /// Agent to client
+    Inbound,
+}
+
+impl FrameDirection {
+    fn reverse(self) -> Self {
+        match self {
+            Self::Outbound => Self::Inbound,
+            Self::Inbound => Self::Outbound,
+        }
+    }
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// ISO 8601 timestamp
+    pub timestamp: String,
+    pub direction: FrameDirection,
+    pub frame: serde_json::Value,
+}
+
+/// Request key: who sent it and its JSON-RPC ID
+type RequestKey = (FrameDirection, String);
+
+#[derive(Default)]
+struct RecorderState {
+    files: HashMap<String, File>,
+    /// Session of each request, to file its response with it
+    request_sessions: HashMap<RequestKey, Option<String>>,
+    /// Requests without a session ID (`session/new`), already in the unscoped file, kept
+    /// until the response names the session
+    held: HashMap<RequestKey, RecordedFrame>,
+    /// `fs/read_text_file` requests whose responses carry file contents
+    file_reads: HashSet<RequestKey>,
+}
+
+pub struct AcpRecorder {
+    dir: PathBuf,
+    state: Mutex<RecorderState>,
+}
+
+impl AcpRecorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            state: Mutex::new(RecorderState::default()),
+        }
+    }
+
+    /// Record one frame as sent or received; failures are logged, never returned
+    pub fn record(&self, direction: FrameDirection, frame: &str) {
+        let Ok(mut frame) = serde_json::from_str::<serde_json::Value>(frame) else {
+            return;
+        };
+
+        let mut state = self.state.lock();
+        state.redact(direction, &mut frame);
+        let recorded = RecordedFrame {
+            timestamp: Utc::now().to_rfc3339(),
+            direction,
+            frame,
+        };
+        for (session_id, recorded) in state.route(recorded) {
+            if let Err(e) = self.append(&mut state, session_id.as_deref(), &recorded) {
+                warn!("Failed to record ACP frame: {}", e);
+            }
+        }
+    }
+
+    fn append(
+        &self,
+        state: &mut RecorderState,
+        session_id: Option<&str>,
+        recorded: &RecordedFrame,
+    ) -> std::io::Result<()> {
+        let name = session_id.unwrap_or(UNSCOPED_FILE).to_string();
+        if !state.files.contains_key(&name) {
+            std::fs::create_dir_all(&self.dir)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(recording_path(&self.dir, &name))?;
+            state.files.insert(name.clone(), file);
+        }
+
+        let mut line = serde_json::to_string(recorded)?;
+        line.push('\n');
+        // One write per line keeps concurrent recordings of a file line-aligned
+        state.files.get_mut(&name).unwrap().write_all(line.as_bytes())
+    }
+}
+
+impl RecorderState {
+    /// Blank out what must not end up on disk: MCP server env and headers, `authenticate`
+    /// params (except the method ID) and the contents of files read by the agent
+    fn redact(&mut self, direction: FrameDirection, frame: &mut serde_json::Value) {
+        let id = frame.get("id").filter(|id| !id.is_null()).map(|id| id.to_string());
+        let method = frame.get("method").and_then(|m| m.as_str()).map(String::from);
+
+        match method.as_deref() {
+            Some("authenticate") => {
+                if let Some(params) = frame.get_mut("params").and_then(|p| p.as_object_mut()) {
+                    for (_, value) in params.iter_mut().filter(|(key, _)| *key != "methodId") {
+                        *value = REDACTED.into();
+                    }
+                }
+            }
+            Some("fs/read_text_file") => {
+                if let Some(id) = id {
+                    self.file_reads.insert((direction, id));
+                }
+            }
+            Some(_) => {
+                let servers = frame.pointer_mut("/params/mcpServers").and_then(|s| s.as_array_mut());
+                for server in servers.into_iter().flatten() {
+                    for field in ["env", "headers"] {
+                        let entries = server.get_mut(field).and_then(|e| e.as_array_mut());
+                        for value in entries.into_iter().flatten().filter_map(|e| e.get_mut("value")) {
+                            *value = REDACTED.into();
+                        }
+                    }
+                }
+            }
+            None => {
+                let is_file_read = id.is_some_and(|id| self.file_reads.remove(&(direction.reverse(), id)));
+                if let Some(content) = frame.pointer_mut("/result/content").filter(|_| is_file_read) {
+                    *content = REDACTED.into();
+                }
+            }
+        }
+    }
+
+    /// Decide which session file(s) a frame goes to
+    fn route(&mut self, recorded: RecordedFrame) -> Vec<(Option<String>, RecordedFrame)> {
+        let frame = &recorded.frame;
+        let id = frame.get("id").filter(|id| !id.is_null()).map(|id| id.to_string());
+        let session_id = frame
+            .get("params")
+            .and_then(|p| p.get("sessionId"))
+            .and_then(|s| s.as_str())
+            .map(String::from);
+
+        if frame.get("method").is_some() {
+            match (id, session_id) {
+                // Request for a session
+                (Some(id), Some(session_id)) => {
+                    self.request_sessions
+                        .insert((recorded.direction, id), Some(session_id.clone()));
+                    vec![(Some(session_id), recorded)]
+                }
+                // Request that may create a session: recorded now, in case no response
+                // comes, and copied to the session once the response names it
+                (Some(id), None) => {
+                    if self.held.len() >= MAX_HELD_REQUESTS {
+                        let oldest = self
+                            .held
+                            .iter()
+                            .min_by(|a, b| a.1.timestamp.cmp(&b.1.timestamp))
+                            .map(|(key, _)| key.clone());
+                        if let Some(oldest) = oldest {
+                            self.held.remove(&oldest);
+                        }
+                    }
+                    self.held.insert((recorded.direction, id), recorded.clone());
+                    vec![(None, recorded)]
+                }
+                // Notification
+                (None, session_id) => vec![(session_id, recorded)],
+            }
+        } else {
+            // Response: goes with its request
+            let Some(id) = id else {
+                return vec![(None, recorded)];
+            };
+            let key = (recorded.direction.reverse(), id);
+            let result_session = frame
+                .get("result")
+                .and_then(|r| r.get("sessionId"))
+                .and_then(|s| s.as_str())
+                .map(String::from);
+
+            if let Some(request) = self.held.remove(&key) {
+                return match result_session {
+                    Some(session_id) => vec![(Some(session_id.clone()), request), (Some(session_id), recorded)],
+                    None => vec![(None, recorded)],
+                };
+            }
+            let session_id = self.request_sessions.remove(&key).flatten().or(result_session);
+            vec![(session_id, recorded)]
+        }
+    }
+}
+
+/// Recording file of a session in `dir`
+pub fn recording_path(dir: &Path, session_id: &str) -> PathBuf {
+    // Session IDs come from the agent; keep them from escaping `dir`
+    let name: String = session_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    dir.join(format!("{}.ndjson", name))
+}
+
+/// Read a recording, skipping lines that don't parse
+pub fn read_recording(path: &Path) -> std::io::Result<Vec<RecordedFrame>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut frames = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(frame) => frames.push(frame),
+            Err(e) => warn!("Skipping malformed recording line: {}", e),
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_secrets_and_file_contents_redacted() {
+        let dir = std::env::temp_dir().join(format!("aero-work-recorder-{}", uuid::Uuid::new_v4()));
+        let recorder = AcpRecorder::new(&dir);
+
+        for (direction, frame) in [
+            (FrameDirection::Outbound, json!({ "jsonrpc": "2.0", "id": 1, "method": "authenticate", "params": { "methodId": "api-key", "apiKey": "sk-secret-1" } })),
+            (FrameDirection::Inbound, json!({ "jsonrpc": "2.0", "id": 1, "result": {} })),
+            (FrameDirection::Outbound, json!({ "jsonrpc": "2.0", "id": 2, "method": "session/new", "params": {
+                "cwd": "/work",
+                "mcpServers": [
+                    { "name": "db", "command": "db-mcp", "args": [], "env": [{ "name": "DB_PASSWORD", "value": "secret-2" }] },
+                    { "type": "http", "name": "api", "url": "https://mcp.example.com", "headers": [{ "name": "Authorization", "value": "Bearer secret-3" }] },
+                ],
+            } })),
+            (FrameDirection::Inbound, json!({ "jsonrpc": "2.0", "id": 2, "result": { "sessionId": "s1" } })),
+            (FrameDirection::Inbound, json!({ "jsonrpc": "2.0", "id": 5, "method": "fs/read_text_file", "params": { "sessionId": "s1", "path": "/work/.env" } })),
+            (FrameDirection::Outbound, json!({ "jsonrpc": "2.0", "id": 5, "result": { "content": "TOKEN=secret-4" } })),
+            // Same ID in the other direction is not a file read
+            (FrameDirection::Inbound, json!({ "jsonrpc": "2.0", "id": 5, "result": { "content": "kept" } })),
+        ] {
+            recorder.record(direction, &frame.to_string());
+        }
+
+        let unscoped = std::fs::read_to_string(recording_path(&dir, UNSCOPED_FILE)).unwrap();
+        let session = std::fs::read_to_string(recording_path(&dir, "s1")).unwrap();
+        for secret in ["sk-secret-1", "secret-2", "secret-3", "secret-4"] {
+            assert!(!unscoped.contains(secret) && !session.contains(secret), "{} was recorded", secret);
+        }
+
+        let frames = read_recording(&recording_path(&dir, UNSCOPED_FILE)).unwrap();
+        assert_eq!(frames[0].frame["params"], json!({ "methodId": "api-key", "apiKey": REDACTED }));
+        assert_eq!(frames[2].frame["method"], "session/new");
+        assert_eq!(frames[3].frame["result"]["content"], "kept");
+        let frames = read_recording(&recording_path(&dir, "s1")).unwrap();
+        let servers = &frames[0].frame["params"]["mcpServers"];
+        assert_eq!(servers[0]["env"][0], json!({ "name": "DB_PASSWORD", "value": REDACTED }));
+        assert_eq!(servers[1]["headers"][0]["name"], "Authorization");
+        assert_eq!(frames[2].frame["params"]["path"], "/work/.env");
+        assert_eq!(frames[3].frame["result"]["content"], REDACTED);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_requests_without_session_recorded_at_once() {
+        let dir = std::env::temp_dir().join(format!("aero-work-recorder-{}", uuid::Uuid::new_v4()));
+        let recorder = AcpRecorder::new(&dir);
+        let unscoped = || read_recording(&recording_path(&dir, UNSCOPED_FILE)).unwrap();
+
+        // The agent never answers: the request is on disk anyway
+        recorder.record(
+            FrameDirection::Outbound,
+            &json!({ "jsonrpc": "2.0", "id": 1, "method": "session/new", "params": { "cwd": "/work" } }).to_string(),
+        );
+        assert_eq!(unscoped().len(), 1);
+
+        // Answered: copied to the session file, with its response
+        recorder.record(
+            FrameDirection::Outbound,
+            &json!({ "jsonrpc": "2.0", "id": 2, "method": "session/new", "params": { "cwd": "/work" } }).to_string(),
+        );
+        recorder.record(
+            FrameDirection::Inbound,
+            &json!({ "jsonrpc": "2.0", "id": 2, "result": { "sessionId": "s1" } }).to_string(),
+        );
+        assert_eq!(unscoped().len(), 2);
+        let session = read_recording(&recording_path(&dir, "s1")).unwrap();
+        assert_eq!(session[0].frame["id"], 2);
+        assert_eq!(session[1].frame["result"]["sessionId"], "s1");
+
+        // Unanswered requests don't pile up
+        for id in 100..100 + MAX_HELD_REQUESTS as u64 * 2 {
+            recorder.record(
+                FrameDirection::Outbound,
+                &json!({ "jsonrpc": "2.0", "id": id, "method": "session/new", "params": {} }).to_string(),
+            );
+        }
+        assert!(recorder.state.lock().held.len() <= MAX_HELD_REQUESTS);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
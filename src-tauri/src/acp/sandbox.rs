@@ -0,0 +1,112 @@
+//! ACP Sandbox Module
+//!
+//! Runs spawned agents inside a bubblewrap (`bwrap`) sandbox on Linux: the whole
+//! filesystem is mounted read-only except for the profile's writable paths, and
+//! the network namespace can be unshared to block network access.
+//! Sandboxing fails closed: if it was requested but can't be set up, the agent isn't started.
+
+use std::path::{Path, PathBuf};
+
+use super::client::{AcpError, Result};
+
+/// Restrictions for one agent process
+#[derive(Debug, Clone, Default)]
+pub struct SandboxProfile {
+    /// Directories and files the agent may write; everything else is read-only
+    pub writable_paths: Vec<PathBuf>,
+    /// Paths kept read-only even inside a writable path
+    pub read_only_paths: Vec<PathBuf>,
+    pub allow_network: bool,
+}
+
+impl SandboxProfile {
+    /// Command line that runs `command` inside the sandbox
+    #[cfg(target_os = "linux")]
+    pub fn wrap(&self, command: &str, args: &[String], cwd: Option<&str>) -> Result<(String, Vec<String>)> {
+        let bwrap = find_in_path("bwrap").ok_or_else(|| {
+            AcpError::Sandbox("bubblewrap (bwrap) is not installed".to_string())
+        })?;
+
+        let mut wrapped: Vec<String> = ["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        for path in &self.writable_paths {
+            // bwrap can't bind paths that don't exist
+            if !path.exists() {
+                continue;
+            }
+            let path = path.to_string_lossy().to_string();
+            wrapped.extend(["--bind".to_string(), path.clone(), path]);
+        }
+        // Bound after the writable paths so they win; created so there is something to bind
+        for path in &self.read_only_paths {
+            let _ = std::fs::create_dir_all(path);
+            if !path.exists() {
+                continue;
+            }
+            let path = path.to_string_lossy().to_string();
+            wrapped.extend(["--ro-bind".to_string(), path.clone(), path]);
+        }
+
+        if !self.allow_network {
+            wrapped.push("--unshare-net".to_string());
+        }
+        wrapped.extend(["--die-with-parent".to_string(), "--new-session".to_string()]);
+        if let Some(cwd) = cwd {
+            wrapped.extend(["--chdir".to_string(), cwd.to_string()]);
+        }
+
+        wrapped.push("--".to_string());
+        wrapped.push(command.to_string());
+        wrapped.extend(args.iter().cloned());
+
+        Ok((bwrap.to_string_lossy().to_string(), wrapped))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn wrap(&self, _command: &str, _args: &[String], _cwd: Option<&str>) -> Result<(String, Vec<String>)> {
+        Err(AcpError::Sandbox("agent sandboxing is only supported on Linux".to_string()))
+    }
+
+    /// Whether `path` may be written inside the sandbox
+    /// `..` and symlinks are resolved first, so neither can lead out of a writable path.
+    pub fn allows_write(&self, path: &str) -> bool {
+        let Some(path) = resolve(Path::new(path)) else {
+            return false;
+        };
+        let under = |roots: &[PathBuf]| {
+            roots
+                .iter()
+                .filter_map(|root| resolve(root))
+                .any(|root| path.starts_with(root))
+        };
+        under(&self.writable_paths) && !under(&self.read_only_paths)
+    }
+}
+
+/// `path` with its existing part canonicalized; `None` if it isn't absolute or the
+/// part that doesn't exist yet contains `..`
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+    let mut existing = path;
+    let mut missing = Vec::new();
+    while !existing.exists() {
+        missing.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+    let mut resolved = existing.canonicalize().ok()?;
+    resolved.extend(missing.iter().rev());
+    Some(resolved)
+}
+
+#[cfg(target_os = "linux")]
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,608 @@
+//! ACP Transport Module
+//!
+//! Byte streams an ACP agent is reached over. The client speaks newline-delimited
+//! JSON-RPC on top of any of them: the stdio of a spawned process, a TCP or
+//! Unix-domain socket, a WebSocket (one message per frame) or an in-memory stream.
+
+#[cfg(target_os = "macos")]
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use super::client::{AcpError, Result};
+use super::sandbox::SandboxProfile;
+
+pub type TransportReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type TransportWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Buffer between a WebSocket and the newline-delimited stream the client reads
+const WEBSOCKET_BUFFER_SIZE: usize = 256 * 1024;
+/// Buffer of each direction of an in-memory connection
+const MEMORY_BUFFER_SIZE: usize = 256 * 1024;
+
+/// An open connection to an agent
+pub struct TransportConnection {
+    pub reader: TransportReader,
+    pub writer: TransportWriter,
+    /// Diagnostic output of the agent (stderr of a spawned process)
+    pub stderr: Option<TransportReader>,
+    /// Process behind the connection, when the transport spawned one
+    pub child: Option<Child>,
+}
+
+impl TransportConnection {
+    /// Connection over any bidirectional stream (sockets, `tokio::io::duplex` in tests)
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            stderr: None,
+            child: None,
+        }
+    }
+}
+
+/// A way to reach an agent; opened again when the agent is restarted
+#[async_trait]
+pub trait AgentTransport: Send + Sync {
+    async fn open(&self) -> Result<TransportConnection>;
+
+    /// Short description for logs
+    fn describe(&self) -> String;
+}
+
+/// Transport for an agent address from the config:
+/// `tcp://host:port`, `unix:///path/to/socket`, `ws://host:port/path` or
+/// `wss://host:port/path`; `wss` servers are verified against the Mozilla root
+/// certificates bundled with the app, not the system store
+pub fn transport_for_address(address: &str) -> Result<Box<dyn AgentTransport>> {
+    if let Some(addr) = address.strip_prefix("tcp://") {
+        return Ok(Box::new(TcpTransport::new(addr)));
+    }
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix://") {
+        return Ok(Box::new(UnixTransport::new(path)));
+    }
+    if address.starts_with("ws://") || address.starts_with("wss://") {
+        return Ok(Box::new(WebSocketTransport::new(address)));
+    }
+    Err(AcpError::Process(format!("Unsupported agent address: {}", address)))
+}
+
+/// Spawns the agent and talks to it over stdin/stdout
+pub struct StdioTransport {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Extra environment variables for the agent process, applied in order
+    pub env: Vec<(String, String)>,
+    /// Directories put in front of PATH
+    pub path_prefix: Vec<String>,
+    pub cwd: Option<String>,
+    /// Run the process inside this sandbox
+    pub sandbox: Option<SandboxProfile>,
+}
+
+impl StdioTransport {
+    pub fn new(command: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            command: command.into(),
+            args,
+            env: Vec::new(),
+            path_prefix: Vec::new(),
+            cwd: None,
+            sandbox: None,
+        }
+    }
+
+    pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_path_prefix(mut self, path_prefix: Vec<String>) -> Self {
+        self.path_prefix = path_prefix;
+        self
+    }
+
+    pub fn with_cwd(mut self, cwd: Option<String>) -> Self {
+        self.cwd = cwd;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox: Option<SandboxProfile>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+}
+
+#[async_trait]
+impl AgentTransport for StdioTransport {
+    async fn open(&self) -> Result<TransportConnection> {
+        let (program, args) = match &self.sandbox {
+            Some(sandbox) => sandbox.wrap(&self.command, &self.args, self.cwd.as_deref())?,
+            None => (self.command.clone(), self.args.clone()),
+        };
+
+        let mut cmd = Command::new(&program);
+        cmd.args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+
+        // On macOS, when launched via .app bundle (double-click), the environment is minimal.
+        // We need to load the user's shell environment to find npx and other tools.
+        #[cfg(target_os = "macos")]
+        {
+            // Try to get full shell environment by running a login shell
+            let shell_env = get_shell_environment();
+
+            // Apply shell environment variables (except a few we want to control)
+            for (key, value) in &shell_env {
+                // Skip PATH - we'll handle it specially
+                if key != "PATH" && key != "PWD" && key != "_" {
+                    cmd.env(key, value);
+                }
+            }
+
+            // Build comprehensive PATH
+            let shell_path = shell_env.get("PATH").map(|s| s.as_str()).unwrap_or("");
+            let current_path = std::env::var("PATH").unwrap_or_default();
+            let home = std::env::var("HOME").unwrap_or_default();
+
+            let mut additional_paths = vec![
+                "/usr/local/bin".to_string(),
+                "/opt/homebrew/bin".to_string(),
+                "/opt/local/bin".to_string(),
+                format!("{}/.local/bin", home),
+                format!("{}/Library/pnpm", home),
+                format!("{}/.bun/bin", home),
+                format!("{}/.cargo/bin", home),
+            ];
+
+            // Find nvm node versions
+            let nvm_versions_dir = format!("{}/.nvm/versions/node", home);
+            if let Ok(entries) = std::fs::read_dir(&nvm_versions_dir) {
+                for entry in entries.flatten() {
+                    let bin_path = entry.path().join("bin");
+                    if bin_path.exists() {
+                        additional_paths.push(bin_path.to_string_lossy().to_string());
+                    }
+                }
+            }
+
+            // Combine: additional paths + shell PATH + current PATH
+            let new_path = format!("{}:{}:{}", additional_paths.join(":"), shell_path, current_path);
+            cmd.env("PATH", new_path);
+        }
+
+        // On Linux, also ensure common paths are included
+        #[cfg(target_os = "linux")]
+        {
+            let current_path = std::env::var("PATH").unwrap_or_default();
+            let home = std::env::var("HOME").unwrap_or_default();
+
+            let additional_paths = vec![
+                "/usr/local/bin".to_string(),
+                format!("{}/.local/bin", home),
+                format!("{}/.bun/bin", home),
+                format!("{}/.cargo/bin", home),
+                format!("{}/.nvm/versions/node/*/bin", home), // Won't work as glob, but nvm sets PATH anyway
+            ];
+
+            // Find nvm node versions
+            let nvm_versions_dir = format!("{}/.nvm/versions/node", home);
+            if let Ok(entries) = std::fs::read_dir(&nvm_versions_dir) {
+                for entry in entries.flatten() {
+                    let bin_path = entry.path().join("bin");
+                    if bin_path.exists() {
+                        cmd.env("PATH", format!("{}:{}", bin_path.to_string_lossy(), current_path.clone()));
+                    }
+                }
+            }
+
+            let new_path = format!("{}:{}", additional_paths.join(":"), current_path);
+            cmd.env("PATH", new_path);
+        }
+
+        // Apply any custom environment variables (e.g., CLAUDE_CODE_EXECUTABLE for bundled CLI)
+        // after the shell environment, so they win
+        for (key, value) in &self.env {
+            info!("Setting env var: {}", key);
+            cmd.env(key, value);
+        }
+
+        if !self.path_prefix.is_empty() {
+            let base_path = cmd
+                .as_std()
+                .get_envs()
+                .find(|(key, _)| *key == "PATH")
+                .and_then(|(_, value)| value.map(|v| v.to_os_string()))
+                .or_else(|| std::env::var_os("PATH"))
+                .unwrap_or_default();
+            let paths = self
+                .path_prefix
+                .iter()
+                .map(PathBuf::from)
+                .chain(std::env::split_paths(&base_path));
+            let path = std::env::join_paths(paths)
+                .map_err(|e| AcpError::Process(format!("Invalid PATH prefix: {}", e)))?;
+            cmd.env("PATH", path);
+        }
+
+        let mut child = cmd.spawn()?;
+
+        let stdin = child.stdin.take().ok_or_else(|| {
+            AcpError::Process("Failed to get stdin handle".to_string())
+        })?;
+
+        let stdout = child.stdout.take().ok_or_else(|| {
+            AcpError::Process("Failed to get stdout handle".to_string())
+        })?;
+
+        let stderr = child.stderr.take().ok_or_else(|| {
+            AcpError::Process("Failed to get stderr handle".to_string())
+        })?;
+
+        Ok(TransportConnection {
+            reader: Box::new(stdout),
+            writer: Box::new(stdin),
+            stderr: Some(Box::new(stderr)),
+            child: Some(child),
+        })
+    }
+
+    fn describe(&self) -> String {
+        let sandboxed = if self.sandbox.is_some() { " (sandboxed)" } else { "" };
+        format!("{} {}{}", self.command, self.args.join(" "), sandboxed)
+    }
+}
+
+/// Connects to an agent listening on a TCP port
+pub struct TcpTransport {
+    pub addr: String,
+}
+
+impl TcpTransport {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into() }
+    }
+}
+
+#[async_trait]
+impl AgentTransport for TcpTransport {
+    async fn open(&self) -> Result<TransportConnection> {
+        let stream = tokio::net::TcpStream::connect(&self.addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(TransportConnection::from_stream(stream))
+    }
+
+    fn describe(&self) -> String {
+        format!("tcp://{}", self.addr)
+    }
+}
+
+/// Connects to an agent listening on a Unix-domain socket
+#[cfg(unix)]
+pub struct UnixTransport {
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl AgentTransport for UnixTransport {
+    async fn open(&self) -> Result<TransportConnection> {
+        let stream = tokio::net::UnixStream::connect(&self.path).await?;
+        Ok(TransportConnection::from_stream(stream))
+    }
+
+    fn describe(&self) -> String {
+        format!("unix://{}", self.path.display())
+    }
+}
+
+/// Connects to an agent served over WebSocket, one JSON-RPC message per text frame
+pub struct WebSocketTransport {
+    pub url: String,
+}
+
+impl WebSocketTransport {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+#[async_trait]
+impl AgentTransport for WebSocketTransport {
+    async fn open(&self) -> Result<TransportConnection> {
+        if self.url.starts_with("wss://") {
+            // TLS uses ring; only the first install takes, later ones are no-ops
+            let _ = rustls::crypto::ring::default_provider().install_default();
+        }
+        let (ws, _) = tokio_tungstenite::connect_async(self.url.as_str())
+            .await
+            .map_err(|e| AcpError::Process(format!("Failed to connect to {}: {}", self.url, e)))?;
+
+        // Bridge frames to lines so the client reads every transport the same way
+        let (client_side, bridge_side) = tokio::io::duplex(WEBSOCKET_BUFFER_SIZE);
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let (mut ws_tx, mut ws_rx) = ws.split();
+            let (bridge_rx, mut bridge_tx) = tokio::io::split(bridge_side);
+
+            let outgoing = async {
+                let mut lines = BufReader::new(bridge_rx).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if ws_tx.send(Message::Text(line)).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = ws_tx.close().await;
+            };
+
+            let incoming = async {
+                while let Some(message) = ws_rx.next().await {
+                    let payload = match message {
+                        Ok(Message::Text(text)) => text.into_bytes(),
+                        Ok(Message::Binary(bytes)) => bytes,
+                        Ok(Message::Close(_)) => break,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            warn!("WebSocket agent {} error: {}", url, e);
+                            break;
+                        }
+                    };
+                    if bridge_tx.write_all(&payload).await.is_err() || bridge_tx.write_all(b"\n").await.is_err() {
+                        break;
+                    }
+                }
+            };
+
+            // Either side ending closes the connection for the other
+            tokio::select! {
+                _ = outgoing => {}
+                _ = incoming => {}
+            }
+            debug!("WebSocket agent {} closed", url);
+        });
+
+        Ok(TransportConnection::from_stream(client_side))
+    }
+
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// Connects to an agent in this process over `tokio::io::duplex`; every `open`
+/// hands the agent its end of a new stream
+pub struct MemoryTransport {
+    agent_tx: mpsc::UnboundedSender<DuplexStream>,
+}
+
+impl MemoryTransport {
+    /// The transport and where the agent receives its end of each connection
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<DuplexStream>) {
+        let (agent_tx, agent_rx) = mpsc::unbounded_channel();
+        (Self { agent_tx }, agent_rx)
+    }
+}
+
+#[async_trait]
+impl AgentTransport for MemoryTransport {
+    async fn open(&self) -> Result<TransportConnection> {
+        let (client_side, agent_side) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+        self.agent_tx
+            .send(agent_side)
+            .map_err(|_| AcpError::Process("In-memory agent is gone".to_string()))?;
+        Ok(TransportConnection::from_stream(client_side))
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+/// Get environment variables from user's login shell.
+/// This is important on macOS where GUI apps don't inherit shell environment.
+#[cfg(target_os = "macos")]
+fn get_shell_environment() -> HashMap<String, String> {
+    use std::process::Command as StdCommand;
+
+    let mut env_map = HashMap::new();
+
+    // Try to get the user's default shell
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/zsh".to_string());
+
+    // Run a login shell to get environment variables
+    // Using -l for login shell and -c to execute a command
+    let result = StdCommand::new(&shell)
+        .args(["-l", "-c", "env"])
+        .output();
+
+    match result {
+        Ok(output) if output.status.success() => {
+            let env_output = String::from_utf8_lossy(&output.stdout);
+            for line in env_output.lines() {
+                if let Some((key, value)) = line.split_once('=') {
+                    env_map.insert(key.to_string(), value.to_string());
+                }
+            }
+            info!("Loaded {} environment variables from login shell", env_map.len());
+        }
+        Ok(output) => {
+            warn!("Login shell returned error: {:?}", String::from_utf8_lossy(&output.stderr));
+        }
+        Err(e) => {
+            warn!("Failed to run login shell {}: {}", shell, e);
+        }
+    }
+
+    env_map
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tokio::io::{ReadHalf, WriteHalf};
+
+    use super::*;
+    use crate::acp::{AcpClient, AgentRequest, SessionNotification, SessionUpdate, TaggedAgentRequest};
+
+    /// The agent's end of a connection: frames from the client and a writer for replies
+    struct Agent {
+        frames: tokio::io::Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl Agent {
+        async fn accept(agent_rx: &mut mpsc::UnboundedReceiver<DuplexStream>) -> Self {
+            let stream = agent_rx.recv().await.expect("transport was not opened");
+            let (reader, writer) = tokio::io::split(stream);
+            Self {
+                frames: BufReader::new(reader).lines(),
+                writer,
+            }
+        }
+
+        async fn next_frame(&mut self) -> serde_json::Value {
+            let line = self.frames.next_line().await.unwrap().expect("client closed the connection");
+            serde_json::from_str(&line).unwrap()
+        }
+
+        async fn write(&mut self, bytes: &str) {
+            self.writer.write_all(bytes.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn new_client() -> (Arc<AcpClient>, mpsc::Receiver<SessionNotification>, mpsc::Receiver<TaggedAgentRequest>) {
+        let (notification_tx, notification_rx) = mpsc::channel(8);
+        let (permission_tx, _) = mpsc::channel(8);
+        let (agent_request_tx, agent_request_rx) = mpsc::channel(8);
+        let client = AcpClient::new(notification_tx, permission_tx, agent_request_tx);
+        (Arc::new(client), notification_rx, agent_request_rx)
+    }
+
+    fn set_mode(client: &Arc<AcpClient>) -> tokio::task::JoinHandle<Result<()>> {
+        let client = client.clone();
+        tokio::spawn(async move { client.set_session_mode("s1", "plan").await })
+    }
+
+    #[tokio::test]
+    async fn test_framing_and_dispatch() {
+        let (transport, mut agent_rx) = MemoryTransport::new();
+        let (client, mut notification_rx, mut agent_request_rx) = new_client();
+        client.connect_transport(&transport).await.unwrap();
+        let mut agent = Agent::accept(&mut agent_rx).await;
+
+        let request = set_mode(&client);
+        let frame = agent.next_frame().await;
+        assert_eq!(frame["method"], "session/set_mode");
+        assert_eq!(frame["params"]["modeId"], "plan");
+
+        // Several messages in one write, a blank line, and a response split across writes
+        let update = json!({
+            "jsonrpc": "2.0",
+            "method": "session/update",
+            "params": {
+                "sessionId": "s1",
+                "update": { "sessionUpdate": "agent_message_chunk", "content": { "type": "text", "text": "Hi" } },
+            },
+        });
+        let read = json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "fs/read_text_file",
+            "params": { "sessionId": "s1", "path": "/project/notes.txt" },
+        });
+        let unknown = json!({ "jsonrpc": "2.0", "id": "x-1", "method": "x/unknown", "params": {} });
+        let response = json!({ "jsonrpc": "2.0", "id": frame["id"], "result": null }).to_string();
+        let (head, tail) = response.split_at(response.len() / 2);
+        agent.write(&format!("{}\n\n{}\n{}\n{}", update, read, unknown, head)).await;
+        agent.write(&format!("{}\n", tail)).await;
+
+        request.await.unwrap().unwrap();
+        let notification = notification_rx.recv().await.unwrap();
+        assert_eq!(notification.session_id, "s1");
+        assert!(matches!(notification.update, SessionUpdate::AgentMessageChunk { .. }));
+        match agent_request_rx.recv().await.unwrap().request {
+            AgentRequest::ReadTextFile { params, .. } => assert_eq!(params.path, "/project/notes.txt"),
+            other => panic!("unexpected agent request: {:?}", other),
+        }
+        // Methods the client doesn't handle are answered instead of left waiting
+        let reply = agent.next_frame().await;
+        assert_eq!(reply["id"], "x-1");
+        assert_eq!(reply["error"]["code"], -32601);
+    }
+
+    #[tokio::test]
+    async fn test_wss_address_opens_over_tls() {
+        // A server that doesn't speak TLS: the handshake fails with an error
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+            }
+        });
+
+        let transport = transport_for_address(&format!("wss://127.0.0.1:{}/agent", port)).unwrap();
+        let error = match transport.open().await {
+            Ok(_) => panic!("opened a wss connection without TLS"),
+            Err(e) => e.to_string(),
+        };
+        assert!(error.contains("Failed to connect"), "unexpected error: {}", error);
+        assert!(transport_for_address("http://127.0.0.1/agent").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_agent_closing_fails_pending_and_reopens() {
+        let (transport, mut agent_rx) = MemoryTransport::new();
+        let (client, _notification_rx, _agent_request_rx) = new_client();
+        client.connect_transport(&transport).await.unwrap();
+        let mut agent = Agent::accept(&mut agent_rx).await;
+
+        let request = set_mode(&client);
+        agent.next_frame().await;
+        drop(agent);
+        assert!(matches!(request.await.unwrap(), Err(AcpError::AgentExited(_))));
+        let exit = client.wait_for_exit().await;
+        assert!(!exit.requested);
+        assert_eq!(exit.status, "connection closed");
+        assert!(!client.is_connected());
+
+        // A restarted client gets a fresh connection from the same transport
+        let (client, _notification_rx, _agent_request_rx) = new_client();
+        client.connect_transport(&transport).await.unwrap();
+        let mut agent = Agent::accept(&mut agent_rx).await;
+        let _request = set_mode(&client);
+        assert_eq!(agent.next_frame().await["method"], "session/set_mode");
+
+        drop(agent_rx);
+        assert!(transport.open().await.is_err());
+    }
+}
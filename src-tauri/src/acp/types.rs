@@ -45,6 +45,29 @@ pub struct AgentCapabilities {
     pub load_session: Option<bool>,
 }
 
+impl AgentCapabilities {
+    /// `session/fork` (unstable API)
+    pub fn supports_fork(&self) -> bool {
+        self.session_capabilities
+            .as_ref()
+            .map(|c| c.fork.as_ref().map(|v| !v.is_null()).unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// `session/resume` (unstable API)
+    pub fn supports_resume(&self) -> bool {
+        self.session_capabilities
+            .as_ref()
+            .map(|c| c.resume.as_ref().map(|v| !v.is_null()).unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// `session/load`
+    pub fn supports_load_session(&self) -> bool {
+        self.load_session.unwrap_or(false)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct PromptCapabilities {
@@ -80,6 +103,15 @@ pub struct AuthMethod {
     pub description: Option<String>,
 }
 
+/// JSON-RPC error code agents return when the user must log in first
+pub const AUTH_REQUIRED_ERROR_CODE: i32 = -32000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticateRequest {
+    pub method_id: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionMode {
     pub id: SessionModeId,
@@ -111,6 +143,9 @@ pub struct SessionModelState {
     pub available_models: Vec<SessionModel>,
 }
 
+/// Latest ACP protocol version this client speaks
+pub const PROTOCOL_VERSION: u16 = 1;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InitializeRequest {
@@ -133,11 +168,55 @@ pub struct InitializeResponse {
     pub auth_methods: Option<Vec<AuthMethod>>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// MCP server the agent should connect to for a session
+///
+/// HTTP and SSE transports carry a `type` field; stdio servers have none.
+/// HTTP and SSE are only sent when advertised in the agent's `McpCapabilities`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum McpServer {
+    Http {
+        name: String,
+        url: String,
+        #[serde(default)]
+        headers: Vec<HttpHeader>,
+    },
+    Sse {
+        name: String,
+        url: String,
+        #[serde(default)]
+        headers: Vec<HttpHeader>,
+    },
+    #[serde(untagged)]
+    Stdio {
+        name: String,
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: Vec<EnvVariable>,
+    },
+}
+
+impl McpServer {
+    pub fn name(&self) -> &str {
+        match self {
+            McpServer::Http { name, .. } | McpServer::Sse { name, .. } | McpServer::Stdio { name, .. } => name,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NewSessionRequest {
     pub cwd: String,
-    pub mcp_servers: Vec<serde_json::Value>,
+    pub mcp_servers: Vec<McpServer>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -150,13 +229,31 @@ pub struct NewSessionResponse {
     pub models: Option<SessionModelState>,
 }
 
+/// Request to load an existing session, replaying its history as `session/update`s
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadSessionRequest {
+    pub session_id: SessionId,
+    pub cwd: String,
+    pub mcp_servers: Vec<McpServer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadSessionResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modes: Option<SessionModeState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<SessionModelState>,
+}
+
 /// Request to resume an existing session (unstable API)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ResumeSessionRequest {
     pub session_id: SessionId,
     pub cwd: String,
-    pub mcp_servers: Vec<serde_json::Value>,
+    pub mcp_servers: Vec<McpServer>,
 }
 
 /// Request to fork an existing session (unstable API)
@@ -165,7 +262,7 @@ pub struct ResumeSessionRequest {
 pub struct ForkSessionRequest {
     pub session_id: SessionId,
     pub cwd: String,
-    pub mcp_servers: Vec<serde_json::Value>,
+    pub mcp_servers: Vec<McpServer>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -195,16 +292,51 @@ pub enum StopReason {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ContentBlock {
     Text { text: String },
-    Image { data: String, mime_type: String },
-    ResourceLink { uri: String, name: String },
+    /// Requires the `image` prompt capability
+    #[serde(rename_all = "camelCase")]
+    Image {
+        data: String,
+        mime_type: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        uri: Option<String>,
+    },
+    /// Requires the `audio` prompt capability
+    #[serde(rename_all = "camelCase")]
+    Audio { data: String, mime_type: String },
+    #[serde(rename_all = "camelCase")]
+    ResourceLink {
+        uri: String,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+    },
+    /// Requires the `embeddedContext` prompt capability
     Resource { resource: ResourceContents },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ResourceContents {
-    Text { uri: String, text: String },
-    Blob { uri: String, blob: String },
+    #[serde(rename_all = "camelCase")]
+    Text {
+        uri: String,
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Blob {
+        uri: String,
+        blob: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -220,6 +352,14 @@ pub struct SetSessionModeRequest {
     pub mode_id: SessionModeId,
 }
 
+/// Switch the model of a session (unstable API)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetSessionModelRequest {
+    pub session_id: SessionId,
+    pub model_id: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ToolCallStatus {
@@ -359,6 +499,9 @@ pub struct PermissionRequest {
     pub session_id: SessionId,
     pub tool_call: ToolCallUpdate,
     pub options: Vec<PermissionOption>,
+    /// Agent instance that asked; the answer goes back to it
+    #[serde(default)]
+    pub agent_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -373,6 +516,198 @@ pub enum PermissionOutcome {
     },
 }
 
+/// Request from the agent to read a text file (`fs/read_text_file`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadTextFileRequest {
+    pub session_id: SessionId,
+    pub path: String,
+    /// 1-based line number to start reading from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    /// Maximum number of lines to read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadTextFileResponse {
+    pub content: String,
+}
+
+/// Request from the agent to write a text file (`fs/write_text_file`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteTextFileRequest {
+    pub session_id: SessionId,
+    pub path: String,
+    pub content: String,
+}
+
+/// Environment variable passed to a command spawned for the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvVariable {
+    pub name: String,
+    pub value: String,
+}
+
+/// Request from the agent to run a command in a new terminal (`terminal/create`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTerminalRequest {
+    pub session_id: SessionId,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<EnvVariable>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Maximum number of output bytes to retain, older output is dropped first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_byte_limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTerminalResponse {
+    pub terminal_id: String,
+}
+
+/// Request addressing an existing terminal
+/// (`terminal/output`, `terminal/wait_for_exit`, `terminal/kill`, `terminal/release`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalRequest {
+    pub session_id: SessionId,
+    pub terminal_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalExitStatus {
+    pub exit_code: Option<i32>,
+    pub signal: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalOutputResponse {
+    pub output: String,
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<TerminalExitStatus>,
+}
+
+/// Requests from the agent that the client has to serve (other than permission prompts)
+#[derive(Debug, Clone)]
+pub enum AgentRequest {
+    ReadTextFile {
+        request_id: RequestId,
+        params: ReadTextFileRequest,
+    },
+    WriteTextFile {
+        request_id: RequestId,
+        params: WriteTextFileRequest,
+    },
+    CreateTerminal {
+        request_id: RequestId,
+        params: CreateTerminalRequest,
+    },
+    TerminalOutput {
+        request_id: RequestId,
+        params: TerminalRequest,
+    },
+    WaitForTerminalExit {
+        request_id: RequestId,
+        params: TerminalRequest,
+    },
+    KillTerminal {
+        request_id: RequestId,
+        params: TerminalRequest,
+    },
+    ReleaseTerminal {
+        request_id: RequestId,
+        params: TerminalRequest,
+    },
+}
+
+impl AgentRequest {
+    /// Parse an agent request by JSON-RPC method name.
+    /// Returns `Ok(None)` for methods the client doesn't serve.
+    pub fn parse(
+        method: &str,
+        request_id: RequestId,
+        params: serde_json::Value,
+    ) -> Result<Option<Self>, serde_json::Error> {
+        let request = match method {
+            "fs/read_text_file" => AgentRequest::ReadTextFile {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "fs/write_text_file" => AgentRequest::WriteTextFile {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "terminal/create" => AgentRequest::CreateTerminal {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "terminal/output" => AgentRequest::TerminalOutput {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "terminal/wait_for_exit" => AgentRequest::WaitForTerminalExit {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "terminal/kill" => AgentRequest::KillTerminal {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            "terminal/release" => AgentRequest::ReleaseTerminal {
+                request_id,
+                params: serde_json::from_value(params)?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(request))
+    }
+
+    pub fn request_id(&self) -> &RequestId {
+        match self {
+            AgentRequest::ReadTextFile { request_id, .. }
+            | AgentRequest::WriteTextFile { request_id, .. }
+            | AgentRequest::CreateTerminal { request_id, .. }
+            | AgentRequest::TerminalOutput { request_id, .. }
+            | AgentRequest::WaitForTerminalExit { request_id, .. }
+            | AgentRequest::KillTerminal { request_id, .. }
+            | AgentRequest::ReleaseTerminal { request_id, .. } => request_id,
+        }
+    }
+
+    pub fn session_id(&self) -> &SessionId {
+        match self {
+            AgentRequest::ReadTextFile { params, .. } => &params.session_id,
+            AgentRequest::WriteTextFile { params, .. } => &params.session_id,
+            AgentRequest::CreateTerminal { params, .. } => &params.session_id,
+            AgentRequest::TerminalOutput { params, .. }
+            | AgentRequest::WaitForTerminalExit { params, .. }
+            | AgentRequest::KillTerminal { params, .. }
+            | AgentRequest::ReleaseTerminal { params, .. } => &params.session_id,
+        }
+    }
+}
+
+/// An agent request and the agent instance whose connection it arrived on.
+/// The instance, not the session ID the agent put in the params, decides how it is served.
+#[derive(Debug, Clone)]
+pub struct TaggedAgentRequest {
+    pub agent_id: String,
+    pub request: AgentRequest,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AvailableCommand {
@@ -387,8 +722,9 @@ pub struct CommandInput {
     pub hint: String,
 }
 
+/// Serialized as tagged by `sessionUpdate`; see the `Deserialize` impl for unknown kinds
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "sessionUpdate", rename_all = "snake_case")]
+#[serde(remote = "Self", tag = "sessionUpdate", rename_all = "snake_case")]
 pub enum SessionUpdate {
     UserMessageChunk { content: ContentBlock },
     AgentMessageChunk { content: ContentBlock },
@@ -400,6 +736,95 @@ pub enum SessionUpdate {
     AvailableCommandsUpdate { available_commands: Vec<AvailableCommand> },
     #[serde(rename_all = "camelCase")]
     CurrentModeUpdate { current_mode_id: SessionModeId },
+    /// Session metadata changed (e.g. the agent generated a title)
+    #[serde(rename_all = "camelCase")]
+    SessionInfoUpdate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        updated_at: Option<String>,
+    },
+    /// Context window usage (unstable API)
+    UsageUpdate(UsageUpdate),
+    #[serde(rename_all = "camelCase")]
+    ConfigOptionUpdate { config_options: Vec<SessionConfigOption> },
+    /// Update kinds this client doesn't know yet, kept as raw JSON
+    #[serde(untagged, skip_deserializing)]
+    Unknown(serde_json::Value),
+}
+
+impl SessionUpdate {
+    /// `sessionUpdate` tags of the known variants
+    const KINDS: &'static [&'static str] = &[
+        "user_message_chunk",
+        "agent_message_chunk",
+        "agent_thought_chunk",
+        "tool_call",
+        "tool_call_update",
+        "plan",
+        "available_commands_update",
+        "current_mode_update",
+        "session_info_update",
+        "usage_update",
+        "config_option_update",
+    ];
+}
+
+impl Serialize for SessionUpdate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SessionUpdate::serialize(self, serializer)
+    }
+}
+
+/// Unrecognised kinds become `Unknown`; a known kind with a bad payload is an error,
+/// not silently `Unknown`
+impl<'de> Deserialize<'de> for SessionUpdate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let kind = value.get("sessionUpdate").and_then(|kind| kind.as_str()).unwrap_or_default();
+        if !Self::KINDS.contains(&kind) {
+            return Ok(SessionUpdate::Unknown(value));
+        }
+        let kind = kind.to_string();
+        SessionUpdate::deserialize(value).map_err(|e| {
+            tracing::warn!("Invalid {} session update: {}", kind, e);
+            D::Error::custom(format!("invalid {} update: {}", kind, e))
+        })
+    }
+}
+
+/// Context window usage of a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageUpdate {
+    /// Tokens currently in context
+    pub used: u64,
+    /// Context window size in tokens
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<UsageCost>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageCost {
+    pub amount: f64,
+    pub currency: String,
+}
+
+/// A session setting exposed by the agent (e.g. reasoning effort)
+/// Option-kind specific fields (type, currentValue, options) are kept as is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionConfigOption {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub value: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -443,3 +868,36 @@ pub struct JsonRpcNotification {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub params: Option<serde_json::Value>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_session_update_kind_kept_raw() {
+        let raw = json!({ "sessionUpdate": "something_new", "data": 1 });
+        let update: SessionUpdate = serde_json::from_value(raw.clone()).unwrap();
+        assert!(matches!(update, SessionUpdate::Unknown(ref value) if *value == raw));
+        assert_eq!(serde_json::to_value(&update).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_known_session_update_kind_with_bad_payload_rejected() {
+        let error = serde_json::from_value::<SessionUpdate>(json!({
+            "sessionUpdate": "agent_message_chunk",
+            "content": "not a content block",
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("invalid agent_message_chunk update"), "{}", error);
+
+        // A good payload still parses as its variant
+        let update: SessionUpdate = serde_json::from_value(json!({
+            "sessionUpdate": "current_mode_update",
+            "currentModeId": "plan",
+        }))
+        .unwrap();
+        assert!(matches!(update, SessionUpdate::CurrentModeUpdate { ref current_mode_id } if current_mode_id == "plan"));
+        assert_eq!(serde_json::to_value(&update).unwrap()["sessionUpdate"], "current_mode_update");
+    }
+}
@@ -3,28 +3,14 @@ use tauri::State;
 use tracing::{error, info};
 
 use crate::acp::{AcpError, InitializeResponse, PermissionOutcome};
-use crate::core::{AgentManager, AppState};
+use crate::core::{AgentInfo, AgentLogEntry, AppState};
 
 #[tauri::command]
 pub async fn connect_agent(
-    state: State<'_, Arc<AppState>>,
+    _state: State<'_, Arc<AppState>>,
 ) -> Result<(), String> {
+    // ACP agents are started lazily when a session is created/resumed
     info!("Connecting to ACP agent...");
-
-    let manager = AgentManager::new(state.client.clone());
-
-    let notification_tx = state.notification_tx.clone();
-    let permission_tx = state.permission_tx.clone();
-
-    manager
-        .connect(notification_tx.clone(), permission_tx.clone())
-        .await
-        .map_err(|e: AcpError| {
-            error!("Failed to connect: {}", e);
-            e.to_string()
-        })?;
-
-    info!("Connected to ACP agent");
     Ok(())
 }
 
@@ -32,7 +18,7 @@ pub async fn connect_agent(
 pub async fn disconnect_agent(state: State<'_, Arc<AppState>>) -> Result<(), String> {
     info!("Disconnecting from ACP agent...");
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     manager.disconnect().await.map_err(|e: AcpError| {
         error!("Failed to disconnect: {}", e);
@@ -49,12 +35,16 @@ pub async fn initialize_agent(
 ) -> Result<InitializeResponse, String> {
     info!("Initializing ACP agent...");
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
-    let response = manager.initialize().await.map_err(|e: AcpError| {
+    let agent_id = manager.default_agent_id();
+    let client = manager.start_agent(&agent_id).await.map_err(|e: AcpError| {
         error!("Failed to initialize: {}", e);
         e.to_string()
     })?;
+    let response = client
+        .initialize_response()
+        .ok_or_else(|| format!("Agent {} is not initialized", agent_id))?;
 
     info!("Initialized ACP agent: {:?}", response.agent_info);
     Ok(response)
@@ -63,20 +53,55 @@ pub async fn initialize_agent(
 #[tauri::command]
 pub async fn respond_permission(
     state: State<'_, Arc<AppState>>,
+    session_id: Option<String>,
     request_id: serde_json::Value,
     outcome: PermissionOutcome,
 ) -> Result<(), String> {
     info!("Responding to permission request id={:?}", request_id);
 
-    let manager = AgentManager::new(state.client.clone());
+    state
+        .respond_permission(session_id.as_deref(), request_id, outcome)
+        .await
+        .map_err(|e| {
+            error!("Failed to respond to permission: {}", e);
+            e
+        })?;
+
+    Ok(())
+}
+
+/// Log in to an agent with one of the auth methods from its `initialize` response
+#[tauri::command]
+pub async fn authenticate_agent(
+    state: State<'_, Arc<AppState>>,
+    agent_id: Option<String>,
+    method_id: String,
+) -> Result<(), String> {
+    let manager = &state.agent_manager;
+    let agent_id = agent_id.unwrap_or_else(|| manager.default_agent_id());
+    info!("Authenticating agent {} with {}", agent_id, method_id);
 
     manager
-        .respond_permission(request_id, outcome)
+        .authenticate(&agent_id, &method_id)
         .await
         .map_err(|e: AcpError| {
-            error!("Failed to respond to permission: {}", e);
+            error!("Failed to authenticate: {}", e);
             e.to_string()
-        })?;
+        })
+}
 
-    Ok(())
+#[tauri::command]
+pub async fn list_agents(state: State<'_, Arc<AppState>>) -> Result<Vec<AgentInfo>, String> {
+    Ok(state.agent_manager.list_agents())
+}
+
+/// Recent agent stderr and lifecycle events
+#[tauri::command]
+pub async fn get_agent_log(
+    state: State<'_, Arc<AppState>>,
+    agent_id: Option<String>,
+    since_seq: Option<u64>,
+    limit: Option<usize>,
+) -> Result<Vec<AgentLogEntry>, String> {
+    Ok(state.agent_log.entries(agent_id.as_deref(), since_seq, limit))
 }
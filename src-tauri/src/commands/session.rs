@@ -2,22 +2,27 @@ use std::sync::Arc;
 use tauri::State;
 use tracing::{error, info};
 
-use crate::acp::{AcpError, NewSessionResponse, PromptResponse};
-use crate::core::{AgentManager, AppState, ListSessionsResponse, SessionInfo};
+use crate::acp::{AcpError, ContentBlock, McpServer, NewSessionResponse, PromptResponse};
+use crate::core::{AppState, ListSessionsResponse, SessionInfo};
 
 #[tauri::command]
 pub async fn create_session(
     state: State<'_, Arc<AppState>>,
     cwd: String,
+    mcp_servers: Option<Vec<McpServer>>,
+    agent_id: Option<String>,
 ) -> Result<NewSessionResponse, String> {
     info!("Creating new session in {}", cwd);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
-    let response = manager.create_session(&cwd).await.map_err(|e: AcpError| {
-        error!("Failed to create session: {}", e);
-        e.to_string()
-    })?;
+    let response = manager
+        .create_session(agent_id.as_deref(), &cwd, mcp_servers.unwrap_or_default())
+        .await
+        .map_err(|e: AcpError| {
+            error!("Failed to create session: {}", e);
+            e.to_string()
+        })?;
 
     // Register session in the registry
     state.session_registry.register_session(
@@ -37,13 +42,14 @@ pub async fn resume_session(
     state: State<'_, Arc<AppState>>,
     session_id: String,
     cwd: String,
+    mcp_servers: Option<Vec<McpServer>>,
 ) -> Result<NewSessionResponse, String> {
     info!("Resuming session {} in {}", session_id, cwd);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     let response = manager
-        .resume_session(&session_id, &cwd)
+        .resume_session(&session_id, &cwd, mcp_servers.unwrap_or_default())
         .await
         .map_err(|e: AcpError| {
             error!("Failed to resume session: {}", e);
@@ -68,13 +74,14 @@ pub async fn fork_session(
     state: State<'_, Arc<AppState>>,
     session_id: String,
     cwd: String,
+    mcp_servers: Option<Vec<McpServer>>,
 ) -> Result<NewSessionResponse, String> {
     info!("Forking session {} in {}", session_id, cwd);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     let response = manager
-        .fork_session(&session_id, &cwd)
+        .fork_session(&session_id, &cwd, mcp_servers.unwrap_or_default())
         .await
         .map_err(|e: AcpError| {
             error!("Failed to fork session: {}", e);
@@ -134,9 +141,10 @@ pub async fn send_prompt(
 ) -> Result<PromptResponse, String> {
     info!("Sending prompt to session {}", session_id);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
-    let response = manager.prompt(&session_id, &content).await.map_err(|e: AcpError| {
+    let prompt = vec![ContentBlock::Text { text: content }];
+    let response = manager.prompt(&session_id, prompt).await.map_err(|e: AcpError| {
         error!("Failed to send prompt: {}", e);
         e.to_string()
     })?;
@@ -152,7 +160,7 @@ pub async fn cancel_session(
 ) -> Result<(), String> {
     info!("Cancelling session {}", session_id);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     manager.cancel(&session_id).await.map_err(|e: AcpError| {
         error!("Failed to cancel session: {}", e);
@@ -171,7 +179,7 @@ pub async fn set_session_mode(
 ) -> Result<(), String> {
     info!("Setting session {} mode to {}", session_id, mode_id);
 
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     manager
         .set_session_mode(&session_id, &mode_id)
@@ -184,3 +192,20 @@ pub async fn set_session_mode(
     info!("Session {} mode set to {}", session_id, mode_id);
     Ok(())
 }
+
+#[tauri::command]
+pub async fn set_session_model(
+    state: State<'_, Arc<AppState>>,
+    session_id: String,
+    model_id: String,
+) -> Result<(), String> {
+    info!("Setting session {} model to {}", session_id, model_id);
+
+    state.set_session_model(&session_id, &model_id).await.map_err(|e| {
+        error!("Failed to set session model: {}", e);
+        e
+    })?;
+
+    info!("Session {} model set to {}", session_id, model_id);
+    Ok(())
+}
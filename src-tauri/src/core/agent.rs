@@ -1,109 +1,1086 @@
+//! Agent Manager Module
+//!
+//! Runs ACP agents side by side (Claude Code, Gemini CLI, custom agents).
+//! Each agent gets its own `AcpClient`, started lazily on first use, and every
+//! session is bound to the agent that created it so requests are routed by session ID.
+//! Each running agent is supervised: an unexpected exit is reported as `AgentEvent::Crashed`
+//! and `restart_agent` brings it back with exponential backoff.
+//! Agent stderr and lifecycle events go to the shared `AgentLog`.
+//! With `sandbox.enabled`, spawned agents run under bubblewrap (see `acp::sandbox`).
+//! Sessions in a project with its own environment (see `project_env`) get a separate
+//! instance of their agent, identified as `<agent id>#<environment key>`.
+
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tracing::info;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{info, warn};
 
 use crate::acp::{
-    AcpClient, AcpError, InitializeResponse, NewSessionResponse, PermissionOutcome,
-    PermissionRequest, PromptResponse, SessionNotification,
+    AcpClient, AcpError, AcpRecorder, AgentCapabilities, AuthMethod, ContentBlock, InitializeResponse,
+    LoadSessionResponse, McpServer, NewSessionResponse, PermissionOutcome, PermissionRequest, PromptResponse,
+    SandboxProfile, SessionId, SessionNotification, TaggedAgentRequest,
 };
 
+use super::agent_log::{AgentLog, AgentLogKind};
+use super::config::{AgentConfig, AgentDefinition, SandboxConfig};
+use super::project_env::{environment_for, AgentEnvironment, ProjectEnvConfig};
+use super::session_replay::recordings_dir;
+
+pub type AgentId = String;
+
+/// ID of the built-in Claude Code agent (the default agent)
+pub const CLAUDE_CODE_AGENT_ID: &str = "claude-code";
+
+/// Restart attempts after a crash before giving up
+const RESTART_ATTEMPTS: u32 = 5;
+/// Delay before the first restart attempt, doubled after each failure
+const RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Lifecycle events of running agents
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    /// The agent process exited without being stopped
+    Crashed { agent_id: AgentId, status: String },
+    /// The agent refused a request until the user picks one of its auth methods
+    AuthRequired {
+        agent_id: AgentId,
+        auth_methods: Vec<AuthMethod>,
+    },
+}
+
+/// Agent definition plus runtime status, for clients
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentInfo {
+    #[serde(flatten)]
+    pub definition: AgentDefinition,
+    pub running: bool,
+    pub is_default: bool,
+    /// The agent refuses requests until the user authenticates
+    pub awaiting_auth: bool,
+    /// Capabilities negotiated in `initialize` (running agents only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<AgentCapabilities>,
+}
+
 pub struct AgentManager {
-    client: Arc<RwLock<Option<AcpClient>>>,
+    /// Known agents, built-in first
+    definitions: RwLock<Vec<AgentDefinition>>,
+    default_agent: RwLock<AgentId>,
+    /// Running agents, by instance ID
+    clients: Arc<RwLock<HashMap<AgentId, Arc<AcpClient>>>>,
+    /// Agent instance each session is bound to
+    sessions: RwLock<HashMap<SessionId, AgentId>>,
+    /// MCP servers each session was opened with, reused when recovering it
+    session_mcp_servers: RwLock<HashMap<SessionId, Vec<McpServer>>>,
+    /// Agent instances that refused a request until the user authenticates
+    auth_required: RwLock<HashSet<AgentId>>,
+    /// Serializes the startup of each agent instance so it is never spawned twice
+    start_locks: RwLock<HashMap<AgentId, Arc<Mutex<()>>>>,
+    notification_tx: mpsc::Sender<SessionNotification>,
+    permission_tx: mpsc::Sender<PermissionRequest>,
+    agent_request_tx: mpsc::Sender<TaggedAgentRequest>,
+    agent_event_tx: mpsc::Sender<AgentEvent>,
+    /// Agent stderr and lifecycle events
+    agent_log: Arc<AgentLog>,
+    /// Records ACP traffic when `recordTraffic` is enabled
+    recorder: Option<Arc<AcpRecorder>>,
+    /// Sandbox settings when `sandbox.enabled` is set
+    sandbox: Option<SandboxConfig>,
+    /// Working directory of the first session of each agent, writable in its sandbox
+    workspaces: RwLock<HashMap<AgentId, String>>,
+    /// Sandbox each running agent was started in
+    sandboxes: RwLock<HashMap<AgentId, SandboxProfile>>,
+    /// Configured project environments, by project directory
+    projects: HashMap<String, ProjectEnvConfig>,
+    /// Project environment files the user trusted (`agent.trustedProjectFiles`)
+    trusted_project_files: HashMap<String, String>,
+    /// Environment of each agent instance with a project environment
+    environments: RwLock<HashMap<AgentId, AgentEnvironment>>,
 }
 
 impl AgentManager {
-    pub fn new(client: Arc<RwLock<Option<AcpClient>>>) -> Self {
-        Self { client }
+    pub fn new(
+        config: &AgentConfig,
+        notification_tx: mpsc::Sender<SessionNotification>,
+        permission_tx: mpsc::Sender<PermissionRequest>,
+        agent_request_tx: mpsc::Sender<TaggedAgentRequest>,
+        agent_event_tx: mpsc::Sender<AgentEvent>,
+        agent_log: Arc<AgentLog>,
+    ) -> Self {
+        let manager = Self {
+            definitions: RwLock::new(builtin_agents()),
+            default_agent: RwLock::new(CLAUDE_CODE_AGENT_ID.to_string()),
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            sessions: RwLock::new(HashMap::new()),
+            session_mcp_servers: RwLock::new(HashMap::new()),
+            auth_required: RwLock::new(HashSet::new()),
+            start_locks: RwLock::new(HashMap::new()),
+            notification_tx,
+            permission_tx,
+            agent_request_tx,
+            agent_event_tx,
+            agent_log,
+            recorder: config
+                .record_traffic
+                .then(|| Arc::new(AcpRecorder::new(recordings_dir()))),
+            sandbox: config.sandbox.enabled.then(|| config.sandbox.clone()),
+            workspaces: RwLock::new(HashMap::new()),
+            sandboxes: RwLock::new(HashMap::new()),
+            projects: config.projects.clone(),
+            trusted_project_files: config.trusted_project_files.clone(),
+            environments: RwLock::new(HashMap::new()),
+        };
+
+        for definition in &config.agents {
+            manager.register_definition(definition.clone());
+        }
+        if let Some(ref agent_id) = config.default_agent {
+            if let Err(e) = manager.set_default_agent(agent_id) {
+                warn!("Ignoring configured default agent: {}", e);
+            }
+        }
+
+        manager
     }
 
-    /// Connect is now a no-op - ACP agent is started lazily when creating/resuming sessions
-    pub async fn connect(
-        &self,
-        _notification_tx: mpsc::Sender<SessionNotification>,
-        _permission_tx: mpsc::Sender<PermissionRequest>,
-    ) -> Result<(), AcpError> {
-        // ACP agent is started lazily when needed (create_session/resume_session)
-        // See ensure_agent_connected() in websocket.rs
+    /// Add an agent definition, replacing any definition with the same ID
+    pub fn register_definition(&self, definition: AgentDefinition) {
+        let mut definitions = self.definitions.write();
+        match definitions.iter_mut().find(|d| d.id == definition.id) {
+            Some(existing) => *existing = definition,
+            None => definitions.push(definition),
+        }
+    }
+
+    pub fn definition(&self, agent_id: &str) -> Option<AgentDefinition> {
+        self.definitions.read().iter().find(|d| d.id == agent_id).cloned()
+    }
+
+    /// List known agents with their status
+    pub fn list_agents(&self) -> Vec<AgentInfo> {
+        let default_agent = self.default_agent_id();
+        let clients = self.clients.read();
+        self.definitions
+            .read()
+            .iter()
+            .map(|definition| {
+                let client = clients
+                    .iter()
+                    .find(|(instance, c)| base_agent_id(instance) == definition.id && c.is_connected())
+                    .map(|(_, c)| c);
+                AgentInfo {
+                    running: client.is_some(),
+                    is_default: definition.id == default_agent,
+                    awaiting_auth: self.is_awaiting_auth(&definition.id),
+                    capabilities: client.and_then(|c| c.agent_capabilities()),
+                    definition: definition.clone(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn default_agent_id(&self) -> AgentId {
+        self.default_agent.read().clone()
+    }
+
+    pub fn set_default_agent(&self, agent_id: &str) -> Result<(), AcpError> {
+        if self.definition(agent_id).is_none() {
+            return Err(AcpError::UnknownAgent(agent_id.to_string()));
+        }
+        *self.default_agent.write() = agent_id.to_string();
         Ok(())
     }
 
-    pub async fn disconnect(&self) -> Result<(), AcpError> {
-        let client = {
-            let mut guard = self.client.write().await;
-            guard.take()
+    /// Running client for an agent
+    fn running_client(&self, agent_id: &str) -> Option<Arc<AcpClient>> {
+        self.clients
+            .read()
+            .get(agent_id)
+            .filter(|c| c.is_connected())
+            .cloned()
+    }
+
+    /// Start an agent if it isn't running and return its client
+    pub async fn start_agent(&self, agent_id: &str) -> Result<Arc<AcpClient>, AcpError> {
+        if let Some(client) = self.running_client(agent_id) {
+            return Ok(client);
+        }
+
+        // Only callers starting the same instance wait on each other
+        let start_lock = self.start_locks.write().entry(agent_id.to_string()).or_default().clone();
+        let _guard = start_lock.lock().await;
+        // Another caller may have started it while we waited
+        if let Some(client) = self.running_client(agent_id) {
+            return Ok(client);
+        }
+
+        let definition = self
+            .definition(base_agent_id(agent_id))
+            .ok_or_else(|| AcpError::UnknownAgent(agent_id.to_string()))?;
+        let environment = self.environments.read().get(agent_id).cloned().unwrap_or_default();
+
+        info!("Starting ACP agent {} (lazy initialization)...", agent_id);
+        let sandbox = self.sandbox_profile(agent_id, &definition);
+        let transport = match definition.transport(&environment, sandbox.clone()) {
+            Ok(transport) => transport,
+            Err(e) => {
+                self.agent_log.push(&definition.id, AgentLogKind::Error, e.to_string());
+                return Err(e);
+            }
         };
+        self.agent_log.push(
+            &definition.id,
+            AgentLogKind::Lifecycle,
+            format!("Starting {}", transport.describe()),
+        );
+
+        // Collect stderr into the agent log
+        let (stderr_tx, mut stderr_rx) = mpsc::channel::<String>(100);
+        let agent_log = self.agent_log.clone();
+        let log_agent_id = definition.id.clone();
+        tokio::spawn(async move {
+            while let Some(line) = stderr_rx.recv().await {
+                agent_log.push(&log_agent_id, AgentLogKind::Stderr, line);
+            }
+        });
+
+        let mut client = AcpClient::new(
+            self.notification_tx.clone(),
+            self.permission_tx.clone(),
+            self.agent_request_tx.clone(),
+        )
+        .with_agent_id(agent_id)
+        .with_stderr_sink(stderr_tx);
+        if let Some(ref recorder) = self.recorder {
+            client = client.with_recorder(recorder.clone());
+        }
+
+        if let Err(e) = client.connect_transport(transport.as_ref()).await {
+            self.agent_log.push(
+                &definition.id,
+                AgentLogKind::Error,
+                format!("Failed to start {}: {}", transport.describe(), e),
+            );
+            return Err(e);
+        }
+
+        let init_response = match client.initialize().await {
+            Ok(response) => response,
+            Err(e) => {
+                self.agent_log.push(
+                    &definition.id,
+                    AgentLogKind::Error,
+                    format!("Initialization failed: {}", e),
+                );
+                return Err(e);
+            }
+        };
+        info!("ACP agent {} initialized: {:?}", definition.id, init_response.agent_info);
+        let agent_name = init_response
+            .agent_info
+            .as_ref()
+            .map(|info| format!("{} {}", info.name, info.version))
+            .unwrap_or_else(|| definition.name.clone());
+        self.agent_log.push(
+            &definition.id,
+            AgentLogKind::Lifecycle,
+            format!(
+                "Initialized {} (protocol version {})",
+                agent_name, init_response.protocol_version
+            ),
+        );
+
+        let client = Arc::new(client);
+        match sandbox {
+            Some(sandbox) => self.sandboxes.write().insert(agent_id.to_string(), sandbox),
+            None => self.sandboxes.write().remove(agent_id),
+        };
+        self.clients.write().insert(agent_id.to_string(), client.clone());
+        self.supervise(agent_id.to_string(), client.clone());
+        Ok(client)
+    }
+
+    /// Instance of an agent for a session working in `cwd`: the agent itself, or a
+    /// separate instance if the project there has its own environment
+    fn agent_instance(&self, agent_id: &str, cwd: &str) -> Result<AgentId, AcpError> {
+        let agent_id = base_agent_id(agent_id);
+        let spawned = self
+            .definition(agent_id)
+            .map(|definition| definition.address.is_none())
+            .unwrap_or(false);
+        if !spawned {
+            return Ok(agent_id.to_string());
+        }
+
+        let environment =
+            environment_for(cwd, &self.projects, &self.trusted_project_files).map_err(AcpError::Environment)?;
+        if environment.is_empty() {
+            return Ok(agent_id.to_string());
+        }
+        let instance = format!("{}#{}", agent_id, environment.key());
+        self.environments.write().insert(instance.clone(), environment);
+        Ok(instance)
+    }
 
-        if let Some(mut c) = client {
-            c.disconnect().await?;
+    /// Start an agent for a session working in `cwd`
+    /// A sandboxed agent is shared by its sessions, so it can only take sessions whose
+    /// directory it may write; the first session's directory becomes writable at startup.
+    async fn start_agent_in(&self, agent_id: &str, cwd: &str) -> Result<Arc<AcpClient>, AcpError> {
+        if self.sandbox.is_some() {
+            if self.running_client(agent_id).is_none() {
+                self.workspaces.write().insert(agent_id.to_string(), cwd.to_string());
+            } else if let Some(sandbox) = self.sandboxes.read().get(agent_id) {
+                if !sandbox.allows_write(cwd) {
+                    return Err(AcpError::Sandbox(format!(
+                        "{} is outside the sandbox of agent {}; stop the agent or add it to sandbox.writablePaths",
+                        cwd, agent_id
+                    )));
+                }
+            }
         }
-        info!("Disconnected from ACP agent");
+        self.start_agent(agent_id).await
+    }
+
+    /// Sandbox for an agent, if sandboxing is enabled: a spawned agent runs inside it,
+    /// and the fs and terminal requests of any agent (remote ones too) are served in it
+    fn sandbox_profile(&self, agent_id: &str, definition: &AgentDefinition) -> Option<SandboxProfile> {
+        let config = self.sandbox.as_ref()?;
+        let workspace = self
+            .workspaces
+            .read()
+            .get(agent_id)
+            .cloned()
+            .or_else(|| definition.cwd.clone())
+            .or_else(|| std::env::current_dir().ok().map(|dir| dir.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        let mut profile = config.profile(&workspace);
+        if let Some(ref cwd) = definition.cwd {
+            profile.writable_paths.push(cwd.into());
+        }
+        Some(profile)
+    }
+
+    /// Watch a running agent and report it if it exits without being stopped
+    fn supervise(&self, agent_id: AgentId, client: Arc<AcpClient>) {
+        let clients = self.clients.clone();
+        let event_tx = self.agent_event_tx.clone();
+        let agent_log = self.agent_log.clone();
+        tokio::spawn(async move {
+            let exit = client.wait_for_exit().await;
+            if exit.requested {
+                agent_log.push(&agent_id, AgentLogKind::Lifecycle, format!("Stopped ({})", exit.status));
+                return;
+            }
+            agent_log.push(
+                &agent_id,
+                AgentLogKind::Error,
+                format!("Exited unexpectedly ({})", exit.status),
+            );
+
+            // Forget the dead client unless it has already been replaced
+            {
+                let mut clients = clients.write();
+                if clients.get(&agent_id).map(|c| Arc::ptr_eq(c, &client)).unwrap_or(false) {
+                    clients.remove(&agent_id);
+                }
+            }
+
+            warn!("ACP agent {} crashed: {}", agent_id, exit.status);
+            let _ = event_tx
+                .send(AgentEvent::Crashed {
+                    agent_id,
+                    status: exit.status,
+                })
+                .await;
+        });
+    }
+
+    /// Start a crashed agent again, retrying with exponential backoff
+    pub async fn restart_agent(&self, agent_id: &str) -> Result<Arc<AcpClient>, AcpError> {
+        let mut delay = RESTART_INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            tokio::time::sleep(delay).await;
+            info!("Restarting ACP agent {} (attempt {}/{})", agent_id, attempt, RESTART_ATTEMPTS);
+            self.agent_log.push(
+                agent_id,
+                AgentLogKind::Lifecycle,
+                format!("Restarting (attempt {}/{})", attempt, RESTART_ATTEMPTS),
+            );
+
+            match self.start_agent(agent_id).await {
+                Ok(client) => return Ok(client),
+                Err(e @ AcpError::UnknownAgent(_)) => return Err(e),
+                Err(e) if attempt >= RESTART_ATTEMPTS => return Err(e),
+                Err(e) => {
+                    warn!("Failed to restart ACP agent {}: {}", agent_id, e);
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Stop an agent instance, or every instance of an agent
+    /// Its sessions stay bound, so resuming one starts the same agent again
+    pub async fn stop_agent(&self, agent_id: &str) -> Result<(), AcpError> {
+        let instances: Vec<AgentId> = self
+            .clients
+            .read()
+            .keys()
+            .filter(|instance| instance.as_str() == agent_id || base_agent_id(instance) == agent_id)
+            .cloned()
+            .collect();
+
+        for instance in instances {
+            let client = self.clients.write().remove(&instance);
+            self.sandboxes.write().remove(&instance);
+            self.workspaces.write().remove(&instance);
+
+            if let Some(client) = client {
+                client.disconnect().await?;
+                info!("Stopped ACP agent {}", instance);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop all agents
+    pub async fn disconnect(&self) -> Result<(), AcpError> {
+        let agent_ids: Vec<AgentId> = self.clients.read().keys().cloned().collect();
+        for agent_id in agent_ids {
+            self.stop_agent(&agent_id).await?;
+        }
+        info!("Disconnected from ACP agents");
+        Ok(())
+    }
+
+    /// Whether any agent is running
+    pub fn is_connected(&self) -> bool {
+        self.clients.read().values().any(|c| c.is_connected())
+    }
+
+    /// Log in to an agent with one of its auth methods; refused requests can then be retried
+    pub async fn authenticate(&self, agent_id: &str, method_id: &str) -> Result<(), AcpError> {
+        let client = self.start_agent(agent_id).await?;
+        if let Err(e) = client.authenticate(method_id).await {
+            self.agent_log.push(
+                agent_id,
+                AgentLogKind::Error,
+                format!("Authentication with {} failed: {}", method_id, e),
+            );
+            return Err(e);
+        }
+        self.agent_log.push(
+            agent_id,
+            AgentLogKind::Lifecycle,
+            format!("Authenticated with {}", method_id),
+        );
+
+        self.auth_required
+            .write()
+            .retain(|instance| instance != agent_id && base_agent_id(instance) != agent_id);
         Ok(())
     }
 
-    pub async fn is_connected(&self) -> bool {
-        let guard = self.client.read().await;
-        guard.as_ref().map(|c| c.is_connected()).unwrap_or(false)
+    /// Whether an agent (any of its instances) refuses requests until the user authenticates
+    pub fn is_awaiting_auth(&self, agent_id: &str) -> bool {
+        self.auth_required
+            .read()
+            .iter()
+            .any(|instance| instance == agent_id || base_agent_id(instance) == agent_id)
+    }
+
+    /// If the agent refused a request until the user logs in, ask clients to pick an
+    /// auth method and return `AuthenticationRequired` at once; the request can be retried
+    /// after `authenticate`. Any other error is returned as is.
+    async fn auth_error(&self, agent_id: &str, error: AcpError) -> AcpError {
+        if !error.is_auth_required() {
+            return error;
+        }
+        let auth_methods = self
+            .running_client(agent_id)
+            .and_then(|client| client.initialize_response())
+            .and_then(|response| response.auth_methods)
+            .unwrap_or_default();
+        if auth_methods.is_empty() {
+            return error;
+        }
+
+        self.auth_required.write().insert(agent_id.to_string());
+        info!("ACP agent {} requires authentication", agent_id);
+        self.agent_log.push(agent_id, AgentLogKind::Lifecycle, "Authentication required");
+        let _ = self
+            .agent_event_tx
+            .send(AgentEvent::AuthRequired {
+                agent_id: agent_id.to_string(),
+                auth_methods,
+            })
+            .await;
+
+        AcpError::AuthenticationRequired(agent_id.to_string())
+    }
+
+    /// Cached `initialize` response of a running agent (default agent if not given)
+    pub fn initialize_response(&self, agent_id: Option<&str>) -> Option<InitializeResponse> {
+        let agent_id = agent_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| self.default_agent_id());
+        self.running_client(&agent_id)
+            .or_else(|| {
+                self.clients
+                    .read()
+                    .iter()
+                    .find(|(instance, c)| base_agent_id(instance) == agent_id && c.is_connected())
+                    .map(|(_, c)| c.clone())
+            })
+            .and_then(|client| client.initialize_response())
+    }
+
+    /// Agent a session is bound to
+    pub fn agent_for_session(&self, session_id: &str) -> Option<AgentId> {
+        self.instance_for_session(session_id)
+            .map(|instance| base_agent_id(&instance).to_string())
+    }
+
+    /// Sandbox to serve the fs and terminal requests of an agent instance in
+    /// With sandboxing enabled, an instance that isn't running gets a sandbox that allows nothing.
+    pub fn sandbox_for_instance(&self, instance: &str) -> Option<SandboxProfile> {
+        self.sandbox.as_ref()?;
+        Some(self.sandboxes.read().get(instance).cloned().unwrap_or_default())
+    }
+
+    /// Agent instance running a session (see `agent_instance`)
+    pub fn instance_for_session(&self, session_id: &str) -> Option<AgentId> {
+        self.sessions.read().get(session_id).cloned()
+    }
+
+    /// Bind a session to an agent (e.g. before resuming a session from disk)
+    pub fn bind_session(&self, session_id: &str, agent_id: &str) {
+        self.sessions
+            .write()
+            .insert(session_id.to_string(), agent_id.to_string());
+    }
+
+    pub fn unbind_session(&self, session_id: &str) {
+        self.sessions.write().remove(session_id);
+        self.session_mcp_servers.write().remove(session_id);
+    }
+
+    /// MCP servers a session was opened with
+    pub fn session_mcp_servers(&self, session_id: &str) -> Vec<McpServer> {
+        self.session_mcp_servers
+            .read()
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Remember the MCP servers a session was opened with
+    fn record_mcp_servers(&self, session_id: &str, mcp_servers: &[McpServer]) {
+        self.session_mcp_servers
+            .write()
+            .insert(session_id.to_string(), mcp_servers.to_vec());
     }
 
-    pub async fn initialize(&self) -> Result<InitializeResponse, AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.initialize().await
+    /// Agent instance for a session: its bound instance, or the default agent
+    fn session_agent(&self, session_id: &str) -> AgentId {
+        self.instance_for_session(session_id)
+            .unwrap_or_else(|| self.default_agent_id())
     }
 
-    pub async fn create_session(&self, cwd: &str) -> Result<NewSessionResponse, AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.create_session(cwd).await
+    /// Client of the agent running a session, started if needed
+    async fn session_client(&self, session_id: &str) -> Result<Arc<AcpClient>, AcpError> {
+        self.start_agent(&self.session_agent(session_id)).await
     }
 
-    /// Resume an existing session
+    /// Capabilities of the agent running a session, started if needed
+    pub async fn session_capabilities(&self, session_id: &str) -> Result<AgentCapabilities, AcpError> {
+        let client = self.session_client(session_id).await?;
+        Ok(client.agent_capabilities().unwrap_or_default())
+    }
+
+    /// Create a session on the given agent (default agent if not given)
+    pub async fn create_session(
+        &self,
+        agent_id: Option<&str>,
+        cwd: &str,
+        mcp_servers: Vec<McpServer>,
+    ) -> Result<NewSessionResponse, AcpError> {
+        let agent_id = agent_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| self.default_agent_id());
+        let agent_id = self.agent_instance(&agent_id, cwd)?;
+        let client = self.start_agent_in(&agent_id, cwd).await?;
+        let response = match client.create_session(cwd, mcp_servers.clone()).await {
+            Ok(response) => response,
+            Err(e) => return Err(self.auth_error(&agent_id, e).await),
+        };
+        self.bind_session(&response.session_id, &agent_id);
+        self.record_mcp_servers(&response.session_id, &mcp_servers);
+        Ok(response)
+    }
+
+    /// Resume an existing session on its bound agent (default agent if unbound)
     pub async fn resume_session(
         &self,
         session_id: &str,
         cwd: &str,
+        mcp_servers: Vec<McpServer>,
     ) -> Result<NewSessionResponse, AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.resume_session(session_id, cwd).await
+        let agent_id = self.agent_instance(&self.session_agent(session_id), cwd)?;
+        let client = self.start_agent_in(&agent_id, cwd).await?;
+        let response = match client.resume_session(session_id, cwd, mcp_servers.clone()).await {
+            Ok(response) => response,
+            Err(e) => return Err(self.auth_error(&agent_id, e).await),
+        };
+        self.bind_session(&response.session_id, &agent_id);
+        self.record_mcp_servers(&response.session_id, &mcp_servers);
+        Ok(response)
     }
 
-    /// Fork an existing session
+    /// Load an existing session on its bound agent; history is replayed as notifications
+    pub async fn load_session(
+        &self,
+        session_id: &str,
+        cwd: &str,
+        mcp_servers: Vec<McpServer>,
+    ) -> Result<LoadSessionResponse, AcpError> {
+        let agent_id = self.agent_instance(&self.session_agent(session_id), cwd)?;
+        let client = self.start_agent_in(&agent_id, cwd).await?;
+        let response = match client.load_session(session_id, cwd, mcp_servers.clone()).await {
+            Ok(response) => response,
+            Err(e) => return Err(self.auth_error(&agent_id, e).await),
+        };
+        self.bind_session(session_id, &agent_id);
+        self.record_mcp_servers(session_id, &mcp_servers);
+        Ok(response)
+    }
+
+    /// Resume a session after its agent restarted, with the MCP servers it was opened with
+    pub async fn recover_session(
+        &self,
+        session_id: &str,
+        cwd: &str,
+    ) -> Result<NewSessionResponse, AcpError> {
+        let mcp_servers = self.session_mcp_servers(session_id);
+        self.resume_session(session_id, cwd, mcp_servers).await
+    }
+
+    /// Fork an existing session; the fork runs on the same agent
     pub async fn fork_session(
         &self,
         session_id: &str,
         cwd: &str,
+        mcp_servers: Vec<McpServer>,
     ) -> Result<NewSessionResponse, AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.fork_session(session_id, cwd).await
+        let agent_id = self.agent_instance(&self.session_agent(session_id), cwd)?;
+        let client = self.start_agent_in(&agent_id, cwd).await?;
+        let response = match client.fork_session(session_id, cwd, mcp_servers.clone()).await {
+            Ok(response) => response,
+            Err(e) => return Err(self.auth_error(&agent_id, e).await),
+        };
+        self.bind_session(&response.session_id, &agent_id);
+        self.record_mcp_servers(&response.session_id, &mcp_servers);
+        Ok(response)
+    }
+
+    /// Reject prompt content the agent that would fork a session doesn't support
+    pub async fn check_fork_prompt_content(
+        &self,
+        session_id: &str,
+        cwd: &str,
+        prompt: &[ContentBlock],
+    ) -> Result<(), AcpError> {
+        let agent_id = self.agent_instance(&self.session_agent(session_id), cwd)?;
+        let client = self.start_agent_in(&agent_id, cwd).await?;
+        client.check_prompt_content(prompt)
     }
 
-    pub async fn prompt(&self, session_id: &str, content: &str) -> Result<PromptResponse, AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.prompt(session_id, content).await
+    /// Reject prompt content the agent running a session doesn't support
+    pub async fn check_prompt_content(&self, session_id: &str, prompt: &[ContentBlock]) -> Result<(), AcpError> {
+        let client = self.session_client(session_id).await?;
+        client.check_prompt_content(prompt)
+    }
+
+    pub async fn prompt(
+        &self,
+        session_id: &str,
+        prompt: Vec<ContentBlock>,
+    ) -> Result<PromptResponse, AcpError> {
+        let client = self.session_client(session_id).await?;
+        client.prompt(session_id, prompt).await
     }
 
     pub async fn cancel(&self, session_id: &str) -> Result<(), AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
+        let client = self.session_client(session_id).await?;
         client.cancel(session_id).await
     }
 
     pub async fn set_session_mode(&self, session_id: &str, mode_id: &str) -> Result<(), AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
+        let client = self.session_client(session_id).await?;
         client.set_session_mode(session_id, mode_id).await
     }
 
+    pub async fn set_session_model(&self, session_id: &str, model_id: &str) -> Result<(), AcpError> {
+        let client = self.session_client(session_id).await?;
+        client.set_session_model(session_id, model_id).await
+    }
+
+    /// Answer a request (fs/*, terminal/*) from the agent instance that sent it
+    pub async fn respond(
+        &self,
+        agent_id: &str,
+        request_id: serde_json::Value,
+        result: serde_json::Value,
+    ) -> Result<(), AcpError> {
+        self.running_client(agent_id)
+            .ok_or(AcpError::NotConnected)?
+            .respond(request_id, result)
+            .await
+    }
+
+    /// Answer a request from an agent instance with a JSON-RPC error
+    pub async fn respond_error(
+        &self,
+        agent_id: &str,
+        request_id: serde_json::Value,
+        code: i32,
+        message: String,
+    ) -> Result<(), AcpError> {
+        self.running_client(agent_id)
+            .ok_or(AcpError::NotConnected)?
+            .respond_error(request_id, code, message)
+            .await
+    }
+
+    /// Answer a permission request of the agent instance that sent it
     pub async fn respond_permission(
         &self,
+        agent_id: &str,
         request_id: serde_json::Value,
         outcome: PermissionOutcome,
     ) -> Result<(), AcpError> {
-        let guard = self.client.read().await;
-        let client = guard.as_ref().ok_or(AcpError::NotConnected)?;
-        client.respond_permission(request_id, outcome).await
+        self.running_client(agent_id)
+            .ok_or(AcpError::NotConnected)?
+            .respond_permission(request_id, outcome)
+            .await
+    }
+}
+
+/// Agent ID of an agent instance ID
+pub fn base_agent_id(instance: &str) -> &str {
+    instance.split_once('#').map(|(agent_id, _)| agent_id).unwrap_or(instance)
+}
+
+/// Agents available without configuration
+fn builtin_agents() -> Vec<AgentDefinition> {
+    let (command, args, env_vars) = find_agent_command();
+    vec![
+        AgentDefinition {
+            id: CLAUDE_CODE_AGENT_ID.to_string(),
+            name: "Claude Code".to_string(),
+            command,
+            args,
+            env: env_vars.unwrap_or_default().into_iter().collect(),
+            cwd: None,
+            address: None,
+        },
+        AgentDefinition {
+            id: "gemini".to_string(),
+            name: "Gemini CLI".to_string(),
+            command: "gemini".to_string(),
+            args: vec!["--experimental-acp".to_string()],
+            env: HashMap::new(),
+            cwd: None,
+            address: None,
+        },
+        AgentDefinition {
+            id: "opencode".to_string(),
+            name: "OpenCode".to_string(),
+            command: "opencode".to_string(),
+            args: vec!["acp".to_string()],
+            env: HashMap::new(),
+            cwd: None,
+            address: None,
+        },
+    ]
+}
+
+/// Bundled agent paths configuration (shared runtime approach)
+#[cfg(feature = "bundled-agent")]
+struct BundledAgentPaths {
+    /// Path to the Bun runtime binary
+    bun_runtime: Option<String>,
+    /// Path to the ACP adapter JS bundle (claude-code-agent.js)
+    acp_agent_js: Option<String>,
+    /// Path to the Claude Code CLI wrapper script (claude-code-cli)
+    claude_cli_wrapper: Option<String>,
+}
+
+/// Find bundled agent files (shared Bun runtime + JS bundles).
+#[cfg(feature = "bundled-agent")]
+fn find_bundled_agents() -> BundledAgentPaths {
+    let mut paths = BundledAgentPaths {
+        bun_runtime: None,
+        acp_agent_js: None,
+        claude_cli_wrapper: None,
+    };
+
+    if let Ok(exe_path) = std::env::current_exe() {
+        let exe_dir = exe_path.parent().unwrap_or(std::path::Path::new("."));
+
+        // Possible locations for bundled files
+        let candidate_dirs = [
+            // macOS .app bundle: Contents/MacOS/../Resources/
+            exe_dir.join("../Resources"),
+            // Next to executable
+            exe_dir.to_path_buf(),
+            // In resources subdirectory (for development)
+            exe_dir.join("resources"),
+            // From project root during development
+            exe_dir.join("../resources"),
+        ];
+
+        for dir in &candidate_dirs {
+            // Look for Bun runtime
+            if paths.bun_runtime.is_none() {
+                let bun_path = dir.join("bun-runtime");
+                if let Ok(canonical) = bun_path.canonicalize() {
+                    if canonical.exists() && canonical.is_file() {
+                        info!("Found bundled Bun runtime at: {:?}", canonical);
+                        paths.bun_runtime = Some(canonical.to_string_lossy().to_string());
+                    }
+                }
+            }
+
+            // Look for ACP agent JS bundle
+            if paths.acp_agent_js.is_none() {
+                let agent_path = dir.join("claude-code-agent.js");
+                if let Ok(canonical) = agent_path.canonicalize() {
+                    if canonical.exists() && canonical.is_file() {
+                        info!("Found bundled ACP agent JS at: {:?}", canonical);
+                        paths.acp_agent_js = Some(canonical.to_string_lossy().to_string());
+                    }
+                }
+            }
+
+            // Look for Claude Code CLI wrapper script
+            if paths.claude_cli_wrapper.is_none() {
+                let cli_path = dir.join("claude-code-cli");
+                if let Ok(canonical) = cli_path.canonicalize() {
+                    if canonical.exists() && canonical.is_file() {
+                        info!("Found bundled Claude CLI wrapper at: {:?}", canonical);
+                        paths.claude_cli_wrapper = Some(canonical.to_string_lossy().to_string());
+                    }
+                }
+            }
+
+            // If all found, no need to continue
+            if paths.bun_runtime.is_some() && paths.acp_agent_js.is_some() && paths.claude_cli_wrapper.is_some() {
+                break;
+            }
+        }
+    }
+
+    paths
+}
+
+/// Find the agent command to use and any environment variables needed.
+///
+/// When `bundled-agent` feature is enabled:
+/// - Uses bundled bun-runtime + claude-code-agent.js
+/// - Sets CLAUDE_CODE_EXECUTABLE to bundled claude-code-cli wrapper
+///
+/// When `bundled-agent` feature is disabled (default):
+/// - Uses npx @zed-industries/claude-code-acp directly
+/// - Requires Node.js/npx to be installed on the system
+fn find_agent_command() -> (String, Vec<String>, Option<Vec<(String, String)>>) {
+    #[cfg(feature = "bundled-agent")]
+    {
+        let bundled = find_bundled_agents();
+
+        // If we have both bun runtime and ACP agent JS, use bundled approach
+        if let (Some(bun_path), Some(acp_js_path)) = (bundled.bun_runtime.clone(), bundled.acp_agent_js) {
+            let mut env_vars = Vec::new();
+
+            // If we have bundled Claude CLI wrapper, set it as the executable path
+            if let Some(cli_wrapper_path) = bundled.claude_cli_wrapper {
+                // The wrapper script is a single executable file that the SDK can check exists
+                env_vars.push(("CLAUDE_CODE_EXECUTABLE".to_string(), cli_wrapper_path));
+            }
+
+            let env_vars_opt = if env_vars.is_empty() { None } else { Some(env_vars) };
+            info!("Using bundled agent: {} {}", bun_path, acp_js_path);
+            return (bun_path, vec![acp_js_path], env_vars_opt);
+        }
+
+        // Bundled feature enabled but files not found - warn and fallback
+        warn!("bundled-agent feature enabled but bundled files not found, falling back to npx");
+    }
+
+    // Use npx (default mode, or fallback when bundled files not found)
+    info!("Using npx @zed-industries/claude-code-acp");
+    ("npx".to_string(), vec!["@zed-industries/claude-code-acp".to_string()], None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_manager(config: &AgentConfig) -> AgentManager {
+        let (notification_tx, _) = mpsc::channel(1);
+        let (permission_tx, _) = mpsc::channel(1);
+        let (agent_request_tx, _) = mpsc::channel(1);
+        let (agent_event_tx, _) = mpsc::channel(1);
+        let (log_tx, _) = mpsc::channel(1);
+        AgentManager::new(
+            config,
+            notification_tx,
+            permission_tx,
+            agent_request_tx,
+            agent_event_tx,
+            Arc::new(AgentLog::new(log_tx)),
+        )
+    }
+
+    #[test]
+    fn test_configured_agents_extend_builtins() {
+        let config = AgentConfig {
+            agents: vec![AgentDefinition {
+                id: "custom".to_string(),
+                name: "Custom".to_string(),
+                command: "my-agent".to_string(),
+                args: vec![],
+                env: HashMap::new(),
+                cwd: None,
+                address: None,
+            }],
+            default_agent: Some("custom".to_string()),
+            ..Default::default()
+        };
+        let manager = test_manager(&config);
+
+        let agents = manager.list_agents();
+        assert!(agents.iter().any(|a| a.definition.id == CLAUDE_CODE_AGENT_ID));
+        assert!(agents.iter().any(|a| a.definition.id == "custom" && a.is_default));
+        assert!(agents.iter().all(|a| !a.running));
+    }
+
+    #[test]
+    fn test_unknown_default_agent_ignored() {
+        let config = AgentConfig {
+            default_agent: Some("missing".to_string()),
+            ..Default::default()
+        };
+        let manager = test_manager(&config);
+        assert_eq!(manager.default_agent_id(), CLAUDE_CODE_AGENT_ID);
+        assert!(manager.set_default_agent("missing").is_err());
+    }
+
+    #[test]
+    fn test_sessions_route_to_bound_agent() {
+        let manager = test_manager(&AgentConfig::default());
+        assert_eq!(manager.session_agent("s1"), CLAUDE_CODE_AGENT_ID);
+
+        manager.bind_session("s1", "gemini");
+        assert_eq!(manager.session_agent("s1"), "gemini");
+
+        manager.unbind_session("s1");
+        assert_eq!(manager.agent_for_session("s1"), None);
+    }
+
+    #[test]
+    fn test_project_environment_gets_own_instance() {
+        let project = std::env::temp_dir().join(format!("aero-work-instance-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&project).unwrap();
+        let cwd = project.to_string_lossy().to_string();
+        let mut config = AgentConfig::default();
+        config.projects.insert(
+            cwd.clone(),
+            ProjectEnvConfig {
+                env: HashMap::from([("API_KEY".to_string(), "project".to_string())]),
+                ..Default::default()
+            },
+        );
+        let manager = test_manager(&config);
+
+        let instance = manager.agent_instance("gemini", &cwd).unwrap();
+        assert!(instance.starts_with("gemini#"));
+        assert_eq!(base_agent_id(&instance), "gemini");
+        // The same environment maps to the same instance, from the agent or the instance ID
+        assert_eq!(manager.agent_instance(&instance, &cwd).unwrap(), instance);
+        assert_eq!(manager.agent_instance("gemini", &std::env::temp_dir().to_string_lossy()).unwrap(), "gemini");
+
+        manager.bind_session("s1", &instance);
+        assert_eq!(manager.agent_for_session("s1").as_deref(), Some("gemini"));
+        assert_eq!(manager.instance_for_session("s1"), Some(instance));
+
+        let _ = std::fs::remove_dir_all(&project);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_agent_exit_fails_pending_requests() {
+        let manager = test_manager(&AgentConfig {
+            agents: vec![AgentDefinition {
+                id: "crashy".to_string(),
+                name: "Crashy".to_string(),
+                command: "sh".to_string(),
+                // Read the initialize request, then die without answering
+                args: vec!["-c".to_string(), "read line; exit 3".to_string()],
+                env: HashMap::new(),
+                cwd: None,
+                address: None,
+            }],
+            ..Default::default()
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(10), manager.start_agent("crashy"))
+            .await
+            .expect("pending request should fail as soon as the agent exits");
+        assert!(matches!(result, Err(AcpError::AgentExited(_))));
+        assert!(!manager.is_connected());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_slow_start_does_not_block_other_agents() {
+        let agent = |id: &str, script: &str| AgentDefinition {
+            id: id.to_string(),
+            name: id.to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: HashMap::new(),
+            cwd: None,
+            address: None,
+        };
+        let manager = Arc::new(test_manager(&AgentConfig {
+            agents: vec![
+                // Never answers initialize
+                agent("hung", "read line; sleep 60"),
+                agent("crashy", "read line; exit 3"),
+            ],
+            ..Default::default()
+        }));
+
+        let hung = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.start_agent("hung").await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let result = tokio::time::timeout(Duration::from_secs(10), manager.start_agent("crashy"))
+            .await
+            .expect("starting one agent should not wait for another");
+        assert!(matches!(result, Err(AcpError::AgentExited(_))));
+        assert!(!hung.is_finished());
+        hung.abort();
     }
 }
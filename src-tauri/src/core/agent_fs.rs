@@ -0,0 +1,241 @@
+//! Agent File System Module
+//!
+//! Serves the ACP `fs/read_text_file` and `fs/write_text_file` requests.
+//! Reads prefer unsaved editor buffers pushed by clients over the file on disk,
+//! and every write made by the agent is recorded so clients can review it.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use chrono::Utc;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::acp::{ReadTextFileRequest, SandboxProfile, SessionId, WriteTextFileRequest};
+
+/// Maximum number of write records kept in memory
+const MAX_WRITE_RECORDS: usize = 500;
+
+/// A file write performed on behalf of the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileWriteRecord {
+    pub session_id: SessionId,
+    pub path: String,
+    /// Whether the file did not exist before this write
+    pub created: bool,
+    /// Size of the written content in bytes
+    pub bytes: usize,
+    pub timestamp: i64,
+}
+
+/// File system access for the ACP agent
+pub struct AgentFileSystem {
+    /// Unsaved editor buffers by absolute path
+    buffers: RwLock<HashMap<String, String>>,
+    /// Most recent writes, oldest first
+    writes: RwLock<VecDeque<FileWriteRecord>>,
+}
+
+impl AgentFileSystem {
+    pub fn new() -> Self {
+        Self {
+            buffers: RwLock::new(HashMap::new()),
+            writes: RwLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Store the unsaved content of an editor buffer
+    pub fn set_buffer(&self, path: String, content: String) {
+        debug!("Editor buffer updated: {}", path);
+        self.buffers.write().insert(path, content);
+    }
+
+    /// Drop an editor buffer (saved or closed in the editor)
+    pub fn clear_buffer(&self, path: &str) {
+        if self.buffers.write().remove(path).is_some() {
+            debug!("Editor buffer cleared: {}", path);
+        }
+    }
+
+    /// Read a text file, preferring an unsaved editor buffer
+    pub async fn read_text_file(&self, request: &ReadTextFileRequest) -> Result<String, String> {
+        ensure_absolute(&request.path)?;
+
+        let buffered = self.buffers.read().get(&request.path).cloned();
+        let content = match buffered {
+            Some(content) => content,
+            None => tokio::fs::read_to_string(&request.path)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", request.path, e))?,
+        };
+
+        Ok(slice_lines(&content, request.line, request.limit))
+    }
+
+    /// Write a text file and record the write
+    /// A sandboxed agent may only write where its sandbox allows.
+    pub async fn write_text_file(
+        &self,
+        request: &WriteTextFileRequest,
+        sandbox: Option<&SandboxProfile>,
+    ) -> Result<FileWriteRecord, String> {
+        ensure_absolute(&request.path)?;
+        if let Some(sandbox) = sandbox {
+            if !sandbox.allows_write(&request.path) {
+                return Err(format!("{} is outside the agent's sandbox", request.path));
+            }
+        }
+
+        let path = Path::new(&request.path);
+        let created = !path.exists();
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create directory {:?}: {}", parent, e))?;
+        }
+
+        tokio::fs::write(path, &request.content)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", request.path, e))?;
+
+        // The agent's content is now the latest version, the editor reloads it
+        self.clear_buffer(&request.path);
+
+        let record = FileWriteRecord {
+            session_id: request.session_id.clone(),
+            path: request.path.clone(),
+            created,
+            bytes: request.content.len(),
+            timestamp: Utc::now().timestamp_millis(),
+        };
+
+        {
+            let mut writes = self.writes.write();
+            writes.push_back(record.clone());
+            while writes.len() > MAX_WRITE_RECORDS {
+                writes.pop_front();
+            }
+        }
+
+        info!("Agent wrote {} ({} bytes)", record.path, record.bytes);
+        Ok(record)
+    }
+
+    /// List recorded writes, optionally filtered by session
+    pub fn list_writes(&self, session_id: Option<&str>) -> Vec<FileWriteRecord> {
+        self.writes
+            .read()
+            .iter()
+            .filter(|w| session_id.map(|id| w.session_id == id).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for AgentFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ACP requires absolute paths for all file operations
+fn ensure_absolute(path: &str) -> Result<(), String> {
+    if Path::new(path).is_absolute() {
+        Ok(())
+    } else {
+        Err(format!("Path must be absolute: {}", path))
+    }
+}
+
+/// Select `limit` lines starting at 1-based `line`, keeping original line endings
+fn slice_lines(content: &str, line: Option<u32>, limit: Option<u32>) -> String {
+    if line.is_none() && limit.is_none() {
+        return content.to_string();
+    }
+
+    let skip = line.map(|l| l.saturating_sub(1) as usize).unwrap_or(0);
+    let take = limit.map(|l| l as usize).unwrap_or(usize::MAX);
+
+    content.split_inclusive('\n').skip(skip).take(take).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_lines() {
+        let content = "one\ntwo\nthree\nfour";
+        assert_eq!(slice_lines(content, None, None), content);
+        assert_eq!(slice_lines(content, Some(2), Some(2)), "two\nthree\n");
+        assert_eq!(slice_lines(content, Some(3), None), "three\nfour");
+        assert_eq!(slice_lines(content, None, Some(1)), "one\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_prefers_editor_buffer() {
+        let fs = AgentFileSystem::new();
+        fs.set_buffer("/tmp/aerowork-unsaved.rs".to_string(), "unsaved".to_string());
+
+        let content = fs
+            .read_text_file(&ReadTextFileRequest {
+                session_id: "s".to_string(),
+                path: "/tmp/aerowork-unsaved.rs".to_string(),
+                line: None,
+                limit: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(content, "unsaved");
+    }
+
+    #[tokio::test]
+    async fn test_relative_path_rejected() {
+        let fs = AgentFileSystem::new();
+        let result = fs
+            .write_text_file(
+                &WriteTextFileRequest {
+                    session_id: "s".to_string(),
+                    path: "relative.txt".to_string(),
+                    content: String::new(),
+                },
+                None,
+            )
+            .await;
+        assert!(result.is_err());
+        assert!(fs.list_writes(None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_outside_sandbox_rejected() {
+        let dir = std::env::temp_dir().join(format!("aerowork-sandbox-{}", uuid::Uuid::new_v4()));
+        let project = dir.join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        let sandbox = SandboxProfile {
+            writable_paths: vec![project.clone()],
+            ..Default::default()
+        };
+        let fs = AgentFileSystem::new();
+        let write = |path: std::path::PathBuf| WriteTextFileRequest {
+            session_id: "s".to_string(),
+            path: path.to_string_lossy().to_string(),
+            content: "x".to_string(),
+        };
+
+        let inside = project.join("src/main.rs");
+        assert!(fs.write_text_file(&write(inside.clone()), Some(&sandbox)).await.is_ok());
+        for outside in [
+            dir.join("outside.txt"),
+            project.join("../outside.txt"),
+            project.join("new/../../outside.txt"),
+        ] {
+            assert!(fs.write_text_file(&write(outside), Some(&sandbox)).await.is_err());
+        }
+        assert!(!dir.join("outside.txt").exists());
+        assert_eq!(fs.list_writes(None).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
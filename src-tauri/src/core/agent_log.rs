@@ -0,0 +1,153 @@
+//! Agent Log Module
+//!
+//! Bounded in-memory log of agent stderr and lifecycle events (spawn, init, exit).
+//! Entries are kept in a ring buffer so clients can fetch recent output, and
+//! every new entry is also streamed so the UI can show why an agent failed to start.
+
+use std::collections::VecDeque;
+
+use chrono::Utc;
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::agent::AgentId;
+
+/// Entries kept before the oldest are dropped
+const DEFAULT_CAPACITY: usize = 2000;
+
+/// Source of a log entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentLogKind {
+    /// A line the agent wrote to stderr
+    Stderr,
+    /// Spawn, initialization, exit or restart of the agent process
+    Lifecycle,
+    /// Agent failed to start or exited unexpectedly
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentLogEntry {
+    /// Increasing sequence number, unique for the lifetime of the app
+    pub seq: u64,
+    /// ISO 8601 timestamp
+    pub timestamp: String,
+    pub agent_id: AgentId,
+    pub kind: AgentLogKind,
+    pub message: String,
+}
+
+struct LogBuffer {
+    entries: VecDeque<AgentLogEntry>,
+    next_seq: u64,
+}
+
+pub struct AgentLog {
+    buffer: RwLock<LogBuffer>,
+    capacity: usize,
+    /// New entries for clients (best effort, the buffer is authoritative)
+    entry_tx: mpsc::Sender<AgentLogEntry>,
+}
+
+impl AgentLog {
+    pub fn new(entry_tx: mpsc::Sender<AgentLogEntry>) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, entry_tx)
+    }
+
+    pub fn with_capacity(capacity: usize, entry_tx: mpsc::Sender<AgentLogEntry>) -> Self {
+        Self {
+            buffer: RwLock::new(LogBuffer {
+                entries: VecDeque::with_capacity(capacity),
+                next_seq: 1,
+            }),
+            capacity,
+            entry_tx,
+        }
+    }
+
+    /// Append an entry, dropping the oldest one when full
+    pub fn push(&self, agent_id: &str, kind: AgentLogKind, message: impl Into<String>) {
+        let entry = {
+            let mut buffer = self.buffer.write();
+            let entry = AgentLogEntry {
+                seq: buffer.next_seq,
+                timestamp: Utc::now().to_rfc3339(),
+                agent_id: agent_id.to_string(),
+                kind,
+                message: message.into(),
+            };
+            buffer.next_seq += 1;
+            if buffer.entries.len() >= self.capacity {
+                buffer.entries.pop_front();
+            }
+            buffer.entries.push_back(entry.clone());
+            entry
+        };
+
+        // Never block the agent on a slow consumer
+        let _ = self.entry_tx.try_send(entry);
+    }
+
+    /// Recent entries, oldest first, optionally for one agent and after a sequence number
+    pub fn entries(
+        &self,
+        agent_id: Option<&str>,
+        since_seq: Option<u64>,
+        limit: Option<usize>,
+    ) -> Vec<AgentLogEntry> {
+        let buffer = self.buffer.read();
+        let mut entries: Vec<AgentLogEntry> = buffer
+            .entries
+            .iter()
+            .filter(|e| agent_id.map(|id| e.agent_id == id).unwrap_or(true))
+            .filter(|e| since_seq.map(|seq| e.seq > seq).unwrap_or(true))
+            .cloned()
+            .collect();
+
+        if let Some(limit) = limit {
+            if entries.len() > limit {
+                entries.drain(..entries.len() - limit);
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_entries_dropped_when_full() {
+        let (entry_tx, _entry_rx) = mpsc::channel(10);
+        let log = AgentLog::with_capacity(3, entry_tx);
+        for i in 0..5 {
+            log.push("a", AgentLogKind::Stderr, format!("line {}", i));
+        }
+
+        let entries = log.entries(None, None, None);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].message, "line 2");
+        assert_eq!(entries[2].seq, 5);
+    }
+
+    #[test]
+    fn test_entries_filtered_by_agent_and_seq() {
+        let (entry_tx, _entry_rx) = mpsc::channel(10);
+        let log = AgentLog::new(entry_tx);
+        log.push("a", AgentLogKind::Lifecycle, "spawned");
+        log.push("b", AgentLogKind::Stderr, "hello");
+        log.push("a", AgentLogKind::Error, "exited");
+
+        let entries = log.entries(Some("a"), Some(1), None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message, "exited");
+
+        let entries = log.entries(None, None, Some(1));
+        assert_eq!(entries[0].agent_id, "a");
+        assert_eq!(entries[0].seq, 3);
+    }
+}
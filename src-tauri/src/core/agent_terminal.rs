@@ -0,0 +1,480 @@
+//! Agent Terminal Module
+//!
+//! Non-PTY command runner backing the ACP `terminal/*` requests.
+//! Output is buffered for the agent (with `outputByteLimit` truncation) and
+//! streamed as `TerminalOutput` so clients can watch agent commands live.
+//! A terminal belongs to the agent instance and session that created it; agents
+//! can only use their own terminals, which are released when either goes away.
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::acp::{
+    CreateTerminalRequest, SandboxProfile, SessionId, TerminalExitStatus, TerminalOutputResponse, TerminalRequest,
+};
+
+use super::terminal::TerminalOutput;
+
+/// How long to wait for output readers to drain after the process exits
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Output kept when the agent doesn't set `outputByteLimit`
+const DEFAULT_OUTPUT_BYTE_LIMIT: usize = 1024 * 1024;
+
+/// Most output kept for any terminal, whatever the agent asks for
+const MAX_OUTPUT_BYTE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Who is using a terminal
+#[derive(Debug, Clone, Copy)]
+pub enum TerminalCaller<'a> {
+    /// Aero Work's own clients, which can watch and stop every agent command
+    Client,
+    /// An agent instance acting for one of its sessions
+    Agent { agent_id: &'a str, session_id: &'a str },
+}
+
+impl<'a> TerminalCaller<'a> {
+    /// The agent that sent a `terminal/*` request
+    pub fn agent(agent_id: &'a str, request: &'a TerminalRequest) -> Self {
+        TerminalCaller::Agent {
+            agent_id,
+            session_id: &request.session_id,
+        }
+    }
+}
+
+/// Agent terminal metadata for clients
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentTerminalInfo {
+    pub id: String,
+    pub session_id: SessionId,
+    pub command: String,
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+    pub exit_status: Option<TerminalExitStatus>,
+}
+
+/// Captured output, truncated from the beginning when over the byte limit
+struct OutputBuffer {
+    data: Vec<u8>,
+    truncated: bool,
+    limit: usize,
+}
+
+impl OutputBuffer {
+    fn new(output_byte_limit: Option<u64>) -> Self {
+        let limit = output_byte_limit
+            .map(|limit| usize::try_from(limit).unwrap_or(usize::MAX))
+            .unwrap_or(DEFAULT_OUTPUT_BYTE_LIMIT)
+            .min(MAX_OUTPUT_BYTE_LIMIT);
+        Self {
+            data: Vec::new(),
+            truncated: false,
+            limit,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+
+        if self.data.len() > self.limit {
+            let mut cut = self.data.len() - self.limit;
+            // Never start in the middle of a UTF-8 sequence
+            while cut < self.data.len() && (self.data[cut] & 0b1100_0000) == 0b1000_0000 {
+                cut += 1;
+            }
+            self.data.drain(..cut);
+            self.truncated = true;
+        }
+    }
+}
+
+struct AgentTerminal {
+    /// Agent instance that created the terminal
+    agent_id: String,
+    info: AgentTerminalInfo,
+    output: Arc<Mutex<OutputBuffer>>,
+    exit_rx: watch::Receiver<Option<TerminalExitStatus>>,
+    kill_tx: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+pub struct AgentTerminalManager {
+    terminals: RwLock<HashMap<String, Arc<AgentTerminal>>>,
+    output_tx: mpsc::Sender<TerminalOutput>,
+}
+
+impl AgentTerminalManager {
+    pub fn new(output_tx: mpsc::Sender<TerminalOutput>) -> Self {
+        Self {
+            terminals: RwLock::new(HashMap::new()),
+            output_tx,
+        }
+    }
+
+    /// Spawn the requested command for agent instance `agent_id` and return its terminal ID
+    /// The command of a sandboxed agent runs in the agent's sandbox.
+    pub fn create_terminal(
+        &self,
+        agent_id: &str,
+        request: &CreateTerminalRequest,
+        sandbox: Option<&SandboxProfile>,
+    ) -> Result<AgentTerminalInfo, String> {
+        let (program, args) = match sandbox {
+            Some(sandbox) => sandbox
+                .wrap(&request.command, &request.args, request.cwd.as_deref())
+                .map_err(|e| e.to_string())?,
+            None => (request.command.clone(), request.args.clone()),
+        };
+        let mut cmd = Command::new(program);
+        cmd.args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(ref cwd) = request.cwd {
+            cmd.current_dir(cwd);
+        }
+        for var in &request.env {
+            cmd.env(&var.name, &var.value);
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to spawn {}: {}", request.command, e))?;
+
+        let terminal_id = Uuid::new_v4().to_string();
+        info!(
+            "Agent terminal {} started: {} {:?}",
+            terminal_id, request.command, request.args
+        );
+
+        let output = Arc::new(Mutex::new(OutputBuffer::new(request.output_byte_limit)));
+
+        let stdout_task = child.stdout.take().map(|stdout| {
+            spawn_reader(stdout, terminal_id.clone(), output.clone(), self.output_tx.clone())
+        });
+        let stderr_task = child.stderr.take().map(|stderr| {
+            spawn_reader(stderr, terminal_id.clone(), output.clone(), self.output_tx.clone())
+        });
+
+        let (exit_tx, exit_rx) = watch::channel(None);
+        let (kill_tx, kill_rx) = oneshot::channel::<()>();
+
+        // Wait for the process to exit (or be killed), then publish its exit status
+        let tid = terminal_id.clone();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = kill_rx => {
+                    let _ = child.kill().await;
+                    child.wait().await
+                }
+            };
+
+            // Let readers drain so output is complete when the exit status is visible
+            for task in [stdout_task, stderr_task].into_iter().flatten() {
+                let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, task).await;
+            }
+
+            let exit_status = match status {
+                Ok(status) => to_exit_status(status),
+                Err(e) => {
+                    warn!("Failed to wait for agent terminal {}: {}", tid, e);
+                    TerminalExitStatus {
+                        exit_code: None,
+                        signal: None,
+                    }
+                }
+            };
+            debug!("Agent terminal {} exited: {:?}", tid, exit_status);
+            let _ = exit_tx.send(Some(exit_status));
+        });
+
+        let info = AgentTerminalInfo {
+            id: terminal_id.clone(),
+            session_id: request.session_id.clone(),
+            command: request.command.clone(),
+            args: request.args.clone(),
+            cwd: request.cwd.clone(),
+            exit_status: None,
+        };
+
+        self.terminals.write().insert(
+            terminal_id,
+            Arc::new(AgentTerminal {
+                agent_id: agent_id.to_string(),
+                info: info.clone(),
+                output,
+                exit_rx,
+                kill_tx: Mutex::new(Some(kill_tx)),
+            }),
+        );
+
+        Ok(info)
+    }
+
+    /// Current output and exit status (if exited)
+    pub fn output(&self, caller: TerminalCaller, terminal_id: &str) -> Result<TerminalOutputResponse, String> {
+        let terminal = self.get(caller, terminal_id)?;
+        let exit_status = terminal.exit_rx.borrow().clone();
+        let output = terminal.output.lock();
+        Ok(TerminalOutputResponse {
+            output: String::from_utf8_lossy(&output.data).to_string(),
+            truncated: output.truncated,
+            exit_status,
+        })
+    }
+
+    /// Wait until the command exits
+    pub async fn wait_for_exit(&self, caller: TerminalCaller<'_>, terminal_id: &str) -> Result<TerminalExitStatus, String> {
+        let mut exit_rx = self.get(caller, terminal_id)?.exit_rx.clone();
+        let status = exit_rx
+            .wait_for(|status| status.is_some())
+            .await
+            .map_err(|_| format!("Terminal {} was dropped", terminal_id))?;
+        Ok(status.clone().unwrap_or(TerminalExitStatus {
+            exit_code: None,
+            signal: None,
+        }))
+    }
+
+    /// Kill the command but keep the terminal (and its output) around
+    pub fn kill_terminal(&self, caller: TerminalCaller, terminal_id: &str) -> Result<(), String> {
+        self.get(caller, terminal_id)?.kill();
+        Ok(())
+    }
+
+    /// Kill the command if still running and forget the terminal
+    pub fn release_terminal(&self, caller: TerminalCaller, terminal_id: &str) -> Result<(), String> {
+        self.get(caller, terminal_id)?.kill();
+        self.terminals.write().remove(terminal_id);
+        debug!("Released agent terminal {}", terminal_id);
+        Ok(())
+    }
+
+    /// Release the terminals of a session that ended
+    pub fn release_session(&self, session_id: &str) {
+        self.release_where(|terminal| terminal.info.session_id == session_id);
+    }
+
+    /// Release the terminals of an agent instance that exited
+    pub fn release_agent(&self, agent_id: &str) {
+        self.release_where(|terminal| terminal.agent_id == agent_id);
+    }
+
+    fn release_where(&self, matches: impl Fn(&AgentTerminal) -> bool) {
+        let mut terminals = self.terminals.write();
+        terminals.retain(|_, terminal| {
+            if !matches(terminal) {
+                return true;
+            }
+            terminal.kill();
+            debug!("Released agent terminal {}", terminal.info.id);
+            false
+        });
+    }
+
+    /// List agent terminals, optionally filtered by session
+    pub fn list_terminals(&self, session_id: Option<&str>) -> Vec<AgentTerminalInfo> {
+        self.terminals
+            .read()
+            .values()
+            .filter(|t| session_id.map(|id| t.info.session_id == id).unwrap_or(true))
+            .map(|t| {
+                let mut info = t.info.clone();
+                info.exit_status = t.exit_rx.borrow().clone();
+                info
+            })
+            .collect()
+    }
+
+    fn get(&self, caller: TerminalCaller, terminal_id: &str) -> Result<Arc<AgentTerminal>, String> {
+        let not_found = || format!("Terminal {} not found", terminal_id);
+        let terminal = self.terminals.read().get(terminal_id).cloned().ok_or_else(not_found)?;
+        match caller {
+            TerminalCaller::Client => Ok(terminal),
+            // Another agent's or session's terminal looks the same as a missing one
+            TerminalCaller::Agent { agent_id, session_id }
+                if terminal.agent_id == agent_id && terminal.info.session_id == session_id =>
+            {
+                Ok(terminal)
+            }
+            TerminalCaller::Agent { .. } => Err(not_found()),
+        }
+    }
+}
+
+impl AgentTerminal {
+    fn kill(&self) {
+        if let Some(kill_tx) = self.kill_tx.lock().take() {
+            info!("Killing agent terminal {}", self.info.id);
+            let _ = kill_tx.send(());
+        }
+    }
+}
+
+/// Read a pipe into the output buffer and mirror it to clients
+fn spawn_reader<R>(
+    mut reader: R,
+    terminal_id: String,
+    output: Arc<Mutex<OutputBuffer>>,
+    output_tx: mpsc::Sender<TerminalOutput>,
+) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    output.lock().push(&buf[..n]);
+                    let _ = output_tx
+                        .send(TerminalOutput {
+                            terminal_id: terminal_id.clone(),
+                            data: String::from_utf8_lossy(&buf[..n]).to_string(),
+                        })
+                        .await;
+                }
+            }
+        }
+    })
+}
+
+fn to_exit_status(status: std::process::ExitStatus) -> TerminalExitStatus {
+    #[cfg(unix)]
+    let signal = {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal().map(signal_name)
+    };
+    #[cfg(not(unix))]
+    let signal = None;
+
+    TerminalExitStatus {
+        exit_code: status.code(),
+        signal,
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "SIGHUP".to_string(),
+        2 => "SIGINT".to_string(),
+        3 => "SIGQUIT".to_string(),
+        6 => "SIGABRT".to_string(),
+        9 => "SIGKILL".to_string(),
+        13 => "SIGPIPE".to_string(),
+        15 => "SIGTERM".to_string(),
+        other => format!("SIG{}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_truncated_from_beginning() {
+        let mut buffer = OutputBuffer::new(Some(5));
+        buffer.push(b"hello ");
+        buffer.push(b"world");
+        assert!(buffer.truncated);
+        assert_eq!(buffer.data, b"world");
+    }
+
+    #[test]
+    fn test_output_truncation_respects_char_boundary() {
+        let mut buffer = OutputBuffer::new(Some(3));
+        // "é" is two bytes, cutting at 3 bytes from the end would split it
+        buffer.push("aéb".as_bytes());
+        buffer.push("c".as_bytes());
+        assert_eq!(String::from_utf8(buffer.data).unwrap(), "bc");
+    }
+
+    #[test]
+    fn test_output_capped_without_limit() {
+        assert_eq!(OutputBuffer::new(None).limit, DEFAULT_OUTPUT_BYTE_LIMIT);
+        assert_eq!(OutputBuffer::new(Some(u64::MAX)).limit, MAX_OUTPUT_BYTE_LIMIT);
+    }
+
+    #[cfg(unix)]
+    fn run(manager: &AgentTerminalManager, agent_id: &str, session_id: &str, script: &str) -> AgentTerminalInfo {
+        manager
+            .create_terminal(
+                agent_id,
+                &CreateTerminalRequest {
+                    session_id: session_id.to_string(),
+                    command: "sh".to_string(),
+                    args: vec!["-c".to_string(), script.to_string()],
+                    env: vec![],
+                    cwd: None,
+                    output_byte_limit: None,
+                },
+                None,
+            )
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_to_exit() {
+        let (output_tx, _output_rx) = mpsc::channel(100);
+        let manager = AgentTerminalManager::new(output_tx);
+        let info = run(&manager, "a", "s", "echo hi; exit 3");
+        let caller = TerminalCaller::Agent { agent_id: "a", session_id: "s" };
+
+        let status = manager.wait_for_exit(caller, &info.id).await.unwrap();
+        assert_eq!(status.exit_code, Some(3));
+
+        let output = manager.output(caller, &info.id).unwrap();
+        assert_eq!(output.output, "hi\n");
+        assert!(!output.truncated);
+
+        manager.release_terminal(caller, &info.id).unwrap();
+        assert!(manager.list_terminals(None).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminals_scoped_to_owner() {
+        let (output_tx, _output_rx) = mpsc::channel(100);
+        let manager = AgentTerminalManager::new(output_tx);
+        let info = run(&manager, "a", "s1", "exec sleep 30");
+
+        // Other agents and other sessions of the same agent can't touch it
+        for caller in [
+            TerminalCaller::Agent { agent_id: "b", session_id: "s1" },
+            TerminalCaller::Agent { agent_id: "a", session_id: "s2" },
+        ] {
+            assert!(manager.output(caller, &info.id).is_err());
+            assert!(manager.kill_terminal(caller, &info.id).is_err());
+            assert!(manager.release_terminal(caller, &info.id).is_err());
+        }
+        assert!(manager.output(TerminalCaller::Client, &info.id).unwrap().exit_status.is_none());
+
+        // Ending the session kills and forgets its terminals
+        manager.release_session("s1");
+        assert!(manager.list_terminals(None).is_empty());
+
+        let info = run(&manager, "a", "s2", "exec sleep 30");
+        let mut exit_rx = manager.get(TerminalCaller::Client, &info.id).unwrap().exit_rx.clone();
+        manager.release_agent("a");
+        assert!(manager.list_terminals(None).is_empty());
+        let status = exit_rx.wait_for(|status| status.is_some()).await.unwrap().clone().unwrap();
+        assert_eq!(status.signal.as_deref(), Some("SIGKILL"));
+    }
+}
@@ -6,11 +6,19 @@
 //! - Data: ~/.local/share/aerowork/ (or $XDG_DATA_HOME/aerowork/)
 //! - Cache: ~/.cache/aerowork/ (or $XDG_CACHE_HOME/aerowork/)
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
+use super::project_env::ProjectEnvConfig;
+// Running agents is desktop-only; on Android the config is only read and written
+#[cfg(not(target_os = "android"))]
+use super::project_env::AgentEnvironment;
+#[cfg(not(target_os = "android"))]
+use crate::acp::{transport_for_address, AcpError, AgentTransport, SandboxProfile, StdioTransport};
+
 /// Application name for directory paths
 const APP_NAME: &str = "aerowork";
 
@@ -107,6 +115,153 @@ pub struct AgentConfig {
     /// Agent-specific settings (placeholder for future use)
     #[serde(default)]
     pub settings: serde_json::Value,
+
+    /// Agent definitions, in addition to the built-in ones
+    /// A definition with the ID of a built-in agent replaces it
+    #[serde(default)]
+    pub agents: Vec<AgentDefinition>,
+
+    /// ID of the agent used when a session doesn't name one
+    #[serde(default)]
+    pub default_agent: Option<String>,
+
+    /// Record the ACP traffic of every session to NDJSON files for debugging
+    #[serde(default)]
+    pub record_traffic: bool,
+
+    /// Sandbox for spawned agent processes (Linux, requires bubblewrap)
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+
+    /// Agent environment per project directory; overrides the project's `.aerowork/agent.json`
+    #[serde(default)]
+    pub projects: HashMap<String, ProjectEnvConfig>,
+
+    /// Project `.aerowork/agent.json` files the user reviewed: project directory to the
+    /// SHA-256 of the file and the env files it loads; any other project file is ignored
+    #[serde(default)]
+    pub trusted_project_files: HashMap<String, String>,
+}
+
+/// Filesystem and network restrictions for agents: spawned agents run inside them,
+/// and the file writes and terminals of every agent are served within them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Paths the agent may write besides the session's working directory;
+    /// a leading `~` is the home directory
+    #[serde(default = "default_writable_paths")]
+    pub writable_paths: Vec<String>,
+
+    #[serde(default = "default_allow_network")]
+    pub allow_network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            writable_paths: default_writable_paths(),
+            allow_network: default_allow_network(),
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl SandboxConfig {
+    /// Sandbox profile for an agent working in `cwd`
+    pub fn profile(&self, cwd: &str) -> SandboxProfile {
+        let mut writable_paths = vec![PathBuf::from(cwd)];
+        writable_paths.extend(self.writable_paths.iter().map(|path| expand_home(path)));
+        SandboxProfile {
+            writable_paths,
+            // The agent must not be able to change AeroWork's config (and turn off the
+            // sandbox) or its stored sessions
+            read_only_paths: vec![config_dir(), data_dir()],
+            allow_network: self.allow_network,
+        }
+    }
+}
+
+/// Temp, cache and agent state directories that CLI agents write to
+fn default_writable_paths() -> Vec<String> {
+    [
+        "/tmp",
+        "~/.cache",
+        "~/.npm",
+        "~/.claude",
+        "~/.claude.json",
+        "~/.gemini",
+        "~/.local/state",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+fn default_allow_network() -> bool {
+    true
+}
+
+#[cfg(not(target_os = "android"))]
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// An ACP agent that can be started by Aero Work
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentDefinition {
+    /// Unique agent ID (e.g., "claude-code", "gemini")
+    pub id: String,
+    /// Display name
+    pub name: String,
+    /// Executable to start
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables for the agent process
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Working directory for the agent process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Connect to an already running agent instead of starting `command`:
+    /// `tcp://host:port`, `unix:///path/to/socket`, `ws://host:port/path` or
+    /// `wss://host:port/path` (TLS, checked against the Mozilla root certificates)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl AgentDefinition {
+    /// How to reach the agent: its address if set, otherwise a spawned `command`
+    /// with the project `environment` added, run inside `sandbox` if given
+    pub fn transport(
+        &self,
+        environment: &AgentEnvironment,
+        sandbox: Option<SandboxProfile>,
+    ) -> Result<Box<dyn AgentTransport>, AcpError> {
+        if let Some(address) = &self.address {
+            return transport_for_address(address);
+        }
+        let mut env: Vec<(String, String)> = self.env.clone().into_iter().collect();
+        env.extend(environment.env.iter().cloned());
+        Ok(Box::new(
+            StdioTransport::new(self.command.clone(), self.args.clone())
+                .with_env(env)
+                .with_path_prefix(environment.path_prefix.clone())
+                .with_cwd(self.cwd.clone())
+                .with_sandbox(sandbox),
+        ))
+    }
 }
 
 /// Model-related configuration
@@ -228,4 +383,56 @@ mod tests {
         let parsed: Config = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed.server.port, config.server.port);
     }
+
+    #[test]
+    fn test_agent_definitions_parse() {
+        let json = r#"{
+            "agent": {
+                "defaultAgent": "gemini",
+                "agents": [{ "id": "gemini", "name": "Gemini CLI", "command": "gemini", "args": ["--experimental-acp"] }]
+            }
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.agent.default_agent.as_deref(), Some("gemini"));
+        assert_eq!(config.agent.agents[0].args, vec!["--experimental-acp"]);
+        assert!(config.agent.agents[0].env.is_empty());
+        assert!(config.agent.agents[0].cwd.is_none());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn test_sandbox_profile() {
+        let json = r#"{ "agent": { "sandbox": { "enabled": true, "writablePaths": ["~/.cache"], "allowNetwork": false } } }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.agent.sandbox.enabled);
+
+        let profile = config.agent.sandbox.profile("/work/project");
+        assert!(!profile.allow_network);
+        assert!(profile.allows_write("/work/project/src/main.rs"));
+        assert!(!profile.allows_write("/work/other"));
+        if let Some(home) = dirs::home_dir() {
+            assert!(profile.allows_write(&home.join(".cache/npm").to_string_lossy()));
+        }
+
+        // Disabled by default, with network access and the usual agent directories
+        let defaults = SandboxConfig::default();
+        assert!(!defaults.enabled && defaults.allow_network);
+        assert!(defaults.writable_paths.contains(&"/tmp".to_string()));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn test_sandbox_keeps_own_dirs_read_only() {
+        let sandbox = SandboxConfig {
+            enabled: true,
+            writable_paths: vec!["~".to_string()],
+            allow_network: true,
+        };
+        let profile = sandbox.profile("/work/project");
+        assert!(!profile.allows_write(&config_dir().join("config.json").to_string_lossy()));
+        assert!(!profile.allows_write(&data_dir().join("sessions/s1.ndjson").to_string_lossy()));
+        if let Some(home) = dirs::home_dir() {
+            assert!(profile.allows_write(&home.join(".cache/npm").to_string_lossy()));
+        }
+    }
 }
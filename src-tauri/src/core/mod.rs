@@ -1,31 +1,51 @@
 pub mod config;
+pub mod project_env;
 pub mod state;
 
-pub use config::{Config, ConfigManager, config_dir, data_dir, cache_dir};
+pub use config::{AgentConfig, AgentDefinition, Config, SandboxConfig, ConfigManager, config_dir, data_dir, cache_dir};
+pub use project_env::{AgentEnvironment, ProjectEnvConfig};
 pub use state::AppState;
 
 // Desktop-only: session_state depends on acp types
 #[cfg(not(target_os = "android"))]
 pub mod session_state;
 #[cfg(not(target_os = "android"))]
-pub use session_state::{ChatItem, Message, MessageRole, SessionState, SessionStateUpdate};
+pub use session_state::{
+    ChatItem, Message, MessageRole, QueuedPrompt, SequencedUpdate, SessionState, SessionStateUpdate, Thought,
+};
 
 // Desktop-only modules (require pty, websocket server, etc.)
 #[cfg(not(target_os = "android"))]
 pub mod agent;
 #[cfg(not(target_os = "android"))]
+pub mod agent_fs;
+#[cfg(not(target_os = "android"))]
+pub mod agent_log;
+#[cfg(not(target_os = "android"))]
+pub mod agent_terminal;
+#[cfg(not(target_os = "android"))]
 pub mod model_config;
 #[cfg(not(target_os = "android"))]
 pub mod plugins;
 #[cfg(not(target_os = "android"))]
 pub mod session_registry;
 #[cfg(not(target_os = "android"))]
+pub mod session_replay;
+#[cfg(not(target_os = "android"))]
 pub mod session_state_manager;
 #[cfg(not(target_os = "android"))]
+pub mod session_store;
+#[cfg(not(target_os = "android"))]
 pub mod terminal;
 
 #[cfg(not(target_os = "android"))]
-pub use agent::AgentManager;
+pub use agent::{base_agent_id, AgentEvent, AgentId, AgentInfo, AgentManager};
+#[cfg(not(target_os = "android"))]
+pub use agent_fs::{AgentFileSystem, FileWriteRecord};
+#[cfg(not(target_os = "android"))]
+pub use agent_log::{AgentLog, AgentLogEntry, AgentLogKind};
+#[cfg(not(target_os = "android"))]
+pub use agent_terminal::{AgentTerminalInfo, AgentTerminalManager, TerminalCaller};
 #[cfg(not(target_os = "android"))]
 pub use model_config::ModelConfig;
 #[cfg(not(target_os = "android"))]
@@ -34,10 +54,14 @@ pub use plugins::{
     ListPluginsResponse, MarketplaceResponse, PluginManager, UninstallPluginResponse,
 };
 #[cfg(not(target_os = "android"))]
-pub use session_registry::{ListSessionsResponse, SessionInfo, SessionRegistry, SessionStatus};
+pub use session_registry::{branches_path, BranchPoint, ListSessionsResponse, SessionInfo, SessionRegistry, SessionStatus};
+#[cfg(not(target_os = "android"))]
+pub use session_replay::{recordings_dir, replay_recording};
 #[cfg(not(target_os = "android"))]
 pub use session_state_manager::{ClientId, SessionStateManager, SharedSessionStateManager};
 #[cfg(not(target_os = "android"))]
+pub use session_store::{sessions_dir, SessionStore};
+#[cfg(not(target_os = "android"))]
 pub use state::SessionActivated;
 #[cfg(not(target_os = "android"))]
 pub use terminal::{TerminalInfo, TerminalManager, TerminalOutput};
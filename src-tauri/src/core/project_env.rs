@@ -0,0 +1,365 @@
+//! Project Environment Module
+//!
+//! Per-project agent environment: extra variables, `.env` files, a PATH prefix and
+//! an nvm Node.js version. A project declares it in `<project>/.aerowork/agent.json`,
+//! or the user sets it in `agent.projects` of the config, keyed by project directory
+//! (which takes precedence). Agents whose environments differ run as separate processes.
+//!
+//! A project file can set variables such as `LD_PRELOAD` or an API base URL, so it is
+//! only used once the user trusts it: `agent.trustedProjectFiles` maps the project
+//! directory to a SHA-256 of the file and the `.env` files it loads, and any change to
+//! them revokes the trust. Env files and PATH entries must stay inside the project.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Project environment file, relative to the project directory
+pub const PROJECT_ENV_FILE: &str = ".aerowork/agent.json";
+
+/// Agent environment settings of one project
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectEnvConfig {
+    /// Extra environment variables; they override variables from `envFiles`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// `.env` files to load, relative to the project directory
+    #[serde(default)]
+    pub env_files: Vec<String>,
+
+    /// Directories put in front of PATH, relative to the project directory
+    #[serde(default)]
+    pub path_prefix: Vec<String>,
+
+    /// Node.js version installed with nvm (e.g. "20" or "20.11.1")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_version: Option<String>,
+}
+
+/// Environment an agent process runs with, on top of its definition's
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AgentEnvironment {
+    /// Variables in the order they are applied
+    pub env: Vec<(String, String)>,
+    pub path_prefix: Vec<String>,
+}
+
+impl AgentEnvironment {
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.path_prefix.is_empty()
+    }
+
+    /// Short stable key, equal for equal environments
+    pub fn key(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        format!("{:012x}", hasher.finish() & 0xffff_ffff_ffff)
+    }
+}
+
+/// Environment for an agent working in `cwd`: the settings of the nearest enclosing
+/// project that has any, or an empty environment
+/// `trusted` maps project directories to the hash of their trusted project file.
+pub fn environment_for(
+    cwd: &str,
+    projects: &HashMap<String, ProjectEnvConfig>,
+    trusted: &HashMap<String, String>,
+) -> Result<AgentEnvironment, String> {
+    for dir in Path::new(cwd).ancestors() {
+        let configured = find_project(projects, dir).cloned();
+        let config = match configured {
+            Some(config) => config,
+            None => match read_project_env(dir, find_project(trusted, dir).map(String::as_str))? {
+                // Use the env files that were hashed, not a later version of them
+                Some((config, env_files)) => return build(dir, &config, &env_files),
+                None => continue,
+            },
+        };
+        return resolve(dir, &config);
+    }
+    Ok(AgentEnvironment::default())
+}
+
+/// Entry of a per-project config map for `dir`
+fn find_project<'a, T>(projects: &'a HashMap<String, T>, dir: &Path) -> Option<&'a T> {
+    projects
+        .iter()
+        .find(|(project, _)| Path::new(project.trim_end_matches('/')) == dir)
+        .map(|(_, value)| value)
+}
+
+/// `.aerowork/agent.json` of a directory and the content of its env files, if it has
+/// one and together they hash to `trusted_hash`
+fn read_project_env(
+    dir: &Path,
+    trusted_hash: Option<&str>,
+) -> Result<Option<(ProjectEnvConfig, Vec<String>)>, String> {
+    let path = dir.join(PROJECT_ENV_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let config: ProjectEnvConfig =
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse {:?}: {}", path, e))?;
+    let env_files = read_env_files(dir, &config)?;
+    let hash = project_env_hash(&content, &env_files);
+    if trusted_hash.map(|trusted| !trusted.eq_ignore_ascii_case(&hash)).unwrap_or(true) {
+        warn!(
+            "Ignoring untrusted {:?}; to use it, add \"{}\": \"{}\" to agent.trustedProjectFiles",
+            path,
+            dir.display(),
+            hash
+        );
+        return Ok(None);
+    }
+    Ok(Some((config, env_files)))
+}
+
+/// Hex SHA-256 of a project file and the content of its env files, as trusted in
+/// `agent.trustedProjectFiles`
+pub fn project_env_hash(content: &str, env_files: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for part in std::iter::once(content).chain(env_files.iter().map(String::as_str)) {
+        // Length-prefixed, so text can't move from one file to the next unnoticed
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Content of each of `config.env_files`
+fn read_env_files(project_dir: &Path, config: &ProjectEnvConfig) -> Result<Vec<String>, String> {
+    config
+        .env_files
+        .iter()
+        .map(|file| {
+            let path = inside_project(project_dir, file)?;
+            std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))
+        })
+        .collect()
+}
+
+/// `entry` joined to the project directory; absolute paths, `..` and symlinks that
+/// lead out of the project are rejected
+fn inside_project(project_dir: &Path, entry: &str) -> Result<PathBuf, String> {
+    let outside = || format!("{} is outside the project {}", entry, project_dir.display());
+    if !Path::new(entry).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(outside());
+    }
+    let path = project_dir.join(entry);
+    if let (Ok(real), Ok(real_project)) = (path.canonicalize(), project_dir.canonicalize()) {
+        if !real.starts_with(real_project) {
+            return Err(outside());
+        }
+    }
+    Ok(path)
+}
+
+/// Turn project settings into the variables and PATH prefix to apply
+pub fn resolve(project_dir: &Path, config: &ProjectEnvConfig) -> Result<AgentEnvironment, String> {
+    build(project_dir, config, &read_env_files(project_dir, config)?)
+}
+
+/// Environment from project settings and the content of their env files
+fn build(project_dir: &Path, config: &ProjectEnvConfig, env_files: &[String]) -> Result<AgentEnvironment, String> {
+    let mut env: Vec<_> = env_files.iter().flat_map(|content| parse_dotenv(content)).collect();
+    let mut explicit: Vec<_> = config.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    explicit.sort();
+    env.extend(explicit);
+
+    let mut path_prefix = config
+        .path_prefix
+        .iter()
+        .map(|dir| inside_project(project_dir, dir).map(|path| path.to_string_lossy().to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(ref version) = config.node_version {
+        path_prefix.push(nvm_node_bin(version)?.to_string_lossy().to_string());
+    }
+
+    Ok(AgentEnvironment { env, path_prefix })
+}
+
+/// `bin` directory of the newest installed nvm Node.js matching `version`
+fn nvm_node_bin(version: &str) -> Result<PathBuf, String> {
+    let nvm_dir = std::env::var_os("NVM_DIR")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".nvm")))
+        .ok_or("Cannot locate nvm")?;
+    let versions_dir = nvm_dir.join("versions/node");
+    let wanted = format!("v{}", version.trim_start_matches('v'));
+
+    let mut matches: Vec<(Vec<u64>, PathBuf)> = std::fs::read_dir(&versions_dir)
+        .map_err(|e| format!("Failed to read {:?}: {}", versions_dir, e))?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let matching = name == wanted || name.starts_with(&format!("{}.", wanted));
+            matching.then(|| (version_numbers(&name), entry.path().join("bin")))
+        })
+        .collect();
+    matches.sort();
+    matches
+        .pop()
+        .map(|(_, bin)| bin)
+        .ok_or_else(|| format!("Node.js {} is not installed with nvm", version))
+}
+
+fn version_numbers(name: &str) -> Vec<u64> {
+    name.trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Parse `.env` content: `KEY=value` lines with optional `export` and quotes
+pub fn parse_dotenv(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), parse_dotenv_value(value.trim())))
+        })
+        .collect()
+}
+
+fn parse_dotenv_value(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.rsplit_once('"')).map(|(v, _)| v) {
+        return inner.replace("\\n", "\n").replace("\\\"", "\"");
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.rsplit_once('\'')).map(|(v, _)| v) {
+        return inner.to_string();
+    }
+    // Unquoted values end at an inline comment
+    match value.find(" #") {
+        Some(pos) => value[..pos].trim_end().to_string(),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        let content = "# keys\nexport API_KEY=abc123\nNAME=\"two words\"\nRAW='a $b'\nPORT=8080 # dev\n\nBROKEN\n";
+        assert_eq!(
+            parse_dotenv(content),
+            vec![
+                ("API_KEY".to_string(), "abc123".to_string()),
+                ("NAME".to_string(), "two words".to_string()),
+                ("RAW".to_string(), "a $b".to_string()),
+                ("PORT".to_string(), "8080".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment_from_project_file() {
+        let project = std::env::temp_dir().join(format!("aero-work-project-{}", uuid::Uuid::new_v4()));
+        let nested = project.join("src/app");
+        std::fs::create_dir_all(project.join(".aerowork")).unwrap();
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(project.join(".env"), "API_KEY=from-file\nREGION=eu\n").unwrap();
+        let project_file =
+            r#"{ "envFiles": [".env"], "env": { "API_KEY": "override" }, "pathPrefix": ["node_modules/.bin"] }"#;
+        std::fs::write(project.join(PROJECT_ENV_FILE), project_file).unwrap();
+        let trusted = HashMap::from([(
+            project.to_string_lossy().to_string(),
+            project_env_hash(project_file, &["API_KEY=from-file\nREGION=eu\n".to_string()]),
+        )]);
+
+        // Found from a subdirectory; explicit variables come after (and win over) `.env`
+        let environment = environment_for(&nested.to_string_lossy(), &HashMap::new(), &trusted).unwrap();
+        assert_eq!(environment.env.last().unwrap(), &("API_KEY".to_string(), "override".to_string()));
+        assert!(environment.env.contains(&("REGION".to_string(), "eu".to_string())));
+        assert_eq!(
+            environment.path_prefix,
+            vec![project.join("node_modules/.bin").to_string_lossy().to_string()]
+        );
+
+        // Config entries take precedence over the project file
+        let projects = HashMap::from([(project.to_string_lossy().to_string(), ProjectEnvConfig::default())]);
+        assert!(environment_for(&nested.to_string_lossy(), &projects, &trusted).unwrap().is_empty());
+        assert_ne!(environment.key(), AgentEnvironment::default().key());
+
+        let _ = std::fs::remove_dir_all(&project);
+    }
+
+    #[test]
+    fn test_untrusted_project_file_ignored() {
+        let project = std::env::temp_dir().join(format!("aero-work-untrusted-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(project.join(".aerowork")).unwrap();
+        let project_file = r#"{ "env": { "LD_PRELOAD": "./evil.so" }, "pathPrefix": ["."] }"#;
+        std::fs::write(project.join(PROJECT_ENV_FILE), project_file).unwrap();
+        let cwd = project.to_string_lossy().to_string();
+
+        assert!(environment_for(&cwd, &HashMap::new(), &HashMap::new()).unwrap().is_empty());
+
+        // Trust is for the reviewed content; a changed file is ignored again
+        let trusted = HashMap::from([(cwd.clone(), project_env_hash(project_file, &[]))]);
+        assert!(!environment_for(&cwd, &HashMap::new(), &trusted).unwrap().is_empty());
+        std::fs::write(project.join(PROJECT_ENV_FILE), r#"{ "env": { "LD_PRELOAD": "./other.so" } }"#).unwrap();
+        assert!(environment_for(&cwd, &HashMap::new(), &trusted).unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&project);
+    }
+
+    #[test]
+    fn test_env_files_are_part_of_trust() {
+        let project = std::env::temp_dir().join(format!("aero-work-env-trust-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(project.join(".aerowork")).unwrap();
+        std::fs::write(project.join(".env"), "REGION=eu\n").unwrap();
+        let project_file = r#"{ "envFiles": [".env"] }"#;
+        std::fs::write(project.join(PROJECT_ENV_FILE), project_file).unwrap();
+        let cwd = project.to_string_lossy().to_string();
+        let trusted = HashMap::from([(cwd.clone(), project_env_hash(project_file, &["REGION=eu\n".to_string()]))]);
+        assert!(!environment_for(&cwd, &HashMap::new(), &trusted).unwrap().is_empty());
+
+        // Changing a trusted project's `.env` revokes the trust
+        std::fs::write(project.join(".env"), "REGION=eu\nLD_PRELOAD=./evil.so\n").unwrap();
+        assert!(environment_for(&cwd, &HashMap::new(), &trusted).unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&project);
+    }
+
+    #[test]
+    fn test_paths_outside_project_rejected() {
+        let project = std::env::temp_dir().join(format!("aero-work-escape-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&project).unwrap();
+        let config = |env_files: &[&str], path_prefix: &[&str]| ProjectEnvConfig {
+            env_files: env_files.iter().map(|s| s.to_string()).collect(),
+            path_prefix: path_prefix.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+
+        assert!(resolve(&project, &config(&["/etc/passwd"], &[])).is_err());
+        assert!(resolve(&project, &config(&["../secrets.env"], &[])).is_err());
+        assert!(resolve(&project, &config(&[], &["/usr/evil/bin"])).is_err());
+        assert!(resolve(&project, &config(&[], &["tools/../../bin"])).is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", project.join("etc")).unwrap();
+            assert!(resolve(&project, &config(&["etc/hostname"], &[])).is_err());
+        }
+        assert!(resolve(&project, &config(&[], &["./node_modules/.bin"])).is_ok());
+
+        let _ = std::fs::remove_dir_all(&project);
+    }
+}
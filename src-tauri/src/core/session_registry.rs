@@ -5,7 +5,7 @@
 //! and tracks active sessions in memory.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
@@ -14,7 +14,13 @@ use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::acp::{SessionId, SessionModeState, SessionModelState, ToolCall, ToolCallStatus, ToolCallContent, ContentBlock};
-use super::session_state::{ChatItem, Message, MessageRole};
+use super::config::data_dir;
+use super::session_state::{ChatItem, Message, MessageRole, Thought};
+
+/// Where the branch points of branched sessions are kept
+pub fn branches_path() -> PathBuf {
+    data_dir().join("branches.json")
+}
 
 /// Session status for UI display
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -28,6 +34,8 @@ pub enum SessionStatus {
     Running,
     /// Session is waiting for user permission or AskUserQuestion response
     Pending,
+    /// Session could not be recovered after its agent crashed
+    Error,
 }
 
 /// Information about a session (both active and historical)
@@ -59,6 +67,24 @@ pub struct SessionInfo {
     pub last_assistant_message: Option<String>,
     /// Whether there's any agent response (text or tool call)
     pub has_agent_response: bool,
+    /// Agent the session is bound to (active sessions only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    /// Where this session was branched off (`rewind_session`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branched_from: Option<BranchPoint>,
+    /// Sessions branched off this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<SessionId>,
+}
+
+/// The session and user message a branch was made from; the branch holds the chat
+/// before that message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchPoint {
+    pub session_id: SessionId,
+    pub message_id: String,
 }
 
 /// Active session state in memory
@@ -71,6 +97,8 @@ pub struct ActiveSession {
     pub modes: Option<SessionModeState>,
     pub models: Option<SessionModelState>,
     pub status: SessionStatus,
+    /// Agent running this session
+    pub agent_id: Option<String>,
 }
 
 /// Response for list_sessions command
@@ -86,6 +114,10 @@ pub struct ListSessionsResponse {
 pub struct SessionRegistry {
     /// Active sessions (connected to agent)
     active_sessions: RwLock<HashMap<SessionId, ActiveSession>>,
+    /// Branch point of each branched session
+    branch_points: RwLock<HashMap<SessionId, BranchPoint>>,
+    /// File the branch points are saved to; None keeps them in memory only
+    branches_file: Option<PathBuf>,
     /// Path to Claude projects directory (~/.claude/projects)
     projects_dir: PathBuf,
 }
@@ -97,10 +129,24 @@ impl SessionRegistry {
 
         Self {
             active_sessions: RwLock::new(HashMap::new()),
+            branch_points: RwLock::new(HashMap::new()),
+            branches_file: None,
             projects_dir,
         }
     }
 
+    /// Registry that saves branch points to `path`, starting with the ones saved there
+    pub fn with_branches_file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let branch_points = load_branch_points(&path);
+        info!("Loaded {} branch points", branch_points.len());
+        Self {
+            branch_points: RwLock::new(branch_points),
+            branches_file: Some(path),
+            ..Self::new()
+        }
+    }
+
     /// Register a new active session
     pub fn register_session(
         &self,
@@ -118,6 +164,7 @@ impl SessionRegistry {
             modes,
             models,
             status: SessionStatus::Idle, // New sessions start as idle
+            agent_id: None,
         };
 
         let mut sessions = self.active_sessions.write();
@@ -149,6 +196,14 @@ impl SessionRegistry {
         }
     }
 
+    /// Record which agent runs the session
+    pub fn set_agent_id(&self, id: &SessionId, agent_id: String) {
+        let mut sessions = self.active_sessions.write();
+        if let Some(session) = sessions.get_mut(id) {
+            session.agent_id = Some(agent_id);
+        }
+    }
+
     /// Update session status
     pub fn update_status(&self, id: &SessionId, status: SessionStatus) {
         let mut sessions = self.active_sessions.write();
@@ -159,6 +214,49 @@ impl SessionRegistry {
         }
     }
 
+    /// Record that `id` was branched off `branch_point`
+    pub fn link_branch(&self, id: &SessionId, branch_point: BranchPoint) {
+        info!(
+            "Session {} branched off {} at message {}",
+            id, branch_point.session_id, branch_point.message_id
+        );
+        let mut branch_points = self.branch_points.write();
+        branch_points.insert(id.clone(), branch_point);
+        self.save_branch_points(&branch_points);
+    }
+
+    /// Where a session was branched off, if it is a branch
+    pub fn branch_point(&self, id: &str) -> Option<BranchPoint> {
+        self.branch_points.read().get(id).cloned()
+    }
+
+    /// Sessions branched off a session
+    pub fn branches_of(&self, id: &str) -> Vec<SessionId> {
+        self.branch_points
+            .read()
+            .iter()
+            .filter(|(_, point)| point.session_id == id)
+            .map(|(branch, _)| branch.clone())
+            .collect()
+    }
+
+    fn save_branch_points(&self, branch_points: &HashMap<SessionId, BranchPoint>) {
+        let Some(ref path) = self.branches_file else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(branch_points)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(path, json).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save branch points to {:?}: {}", path, e);
+        }
+    }
+
     /// Get session status
     pub fn get_status(&self, id: &SessionId) -> Option<SessionStatus> {
         let sessions = self.active_sessions.read();
@@ -219,6 +317,9 @@ impl SessionRegistry {
                         last_user_message: None,
                         last_assistant_message: None,
                         has_agent_response: true, // Active sessions always have potential response
+                        agent_id: session.agent_id.clone(),
+                        branched_from: None,
+                        branches: Vec::new(),
                     },
                 );
             }
@@ -302,6 +403,10 @@ impl SessionRegistry {
 
         // 3. Sort by last activity (newest first)
         let mut sessions: Vec<_> = all_sessions.into_values().collect();
+        for info in sessions.iter_mut() {
+            info.branched_from = self.branch_point(&info.id);
+            info.branches = self.branches_of(&info.id);
+        }
         sessions.sort_by(|a, b| b.last_activity.cmp(&a.last_activity));
 
         // 4. Apply pagination
@@ -358,6 +463,12 @@ impl SessionRegistry {
             let mut active = self.active_sessions.write();
             active.remove(session_id);
         }
+        {
+            let mut branch_points = self.branch_points.write();
+            if branch_points.remove(session_id).is_some() {
+                self.save_branch_points(&branch_points);
+            }
+        }
 
         // Find and delete the session file
         if let Some(file_path) = self.find_session_file(session_id) {
@@ -395,6 +506,9 @@ impl SessionRegistry {
                     last_user_message: None,
                     last_assistant_message: None,
                     has_agent_response: true, // Active sessions always have potential response
+                    agent_id: session.agent_id.clone(),
+                    branched_from: self.branch_point(session_id),
+                    branches: self.branches_of(session_id),
                 });
             }
         }
@@ -405,6 +519,9 @@ impl SessionRegistry {
                 info.id = session_id.to_string();
                 info.active = false;
 
+                info.branched_from = self.branch_point(session_id);
+                info.branches = self.branches_of(session_id);
+
                 // Get project from parent directory
                 if let Some(project_dir) = file_path.parent() {
                     if let Some(project_name) = project_dir.file_name().and_then(|n| n.to_str()) {
@@ -450,7 +567,7 @@ fn path_key_to_cwd(path_key: &str) -> String {
 }
 
 /// Truncate a string to approximately max_chars characters, respecting char boundaries
-fn truncate_string(s: &str, max_chars: usize) -> String {
+pub(crate) fn truncate_string(s: &str, max_chars: usize) -> String {
     let char_count = s.chars().count();
     if char_count <= max_chars {
         s.to_string()
@@ -526,6 +643,7 @@ pub fn load_session_chat_items(path: &PathBuf) -> Vec<ChatItem> {
                     // Track pending text to flush when we encounter a tool_use
                     let mut pending_text = String::new();
                     let mut text_counter = 0;
+                    let mut thinking_counter = 0;
 
                     // Helper closure to flush pending text as a message
                     let base_id = entry
@@ -548,6 +666,38 @@ pub fn load_session_chat_items(path: &PathBuf) -> Vec<ChatItem> {
                                     }
                                 }
                             }
+                            Some("thinking") => {
+                                let thinking = content_item.get("thinking").and_then(|v| v.as_str()).unwrap_or("");
+                                if thinking.trim().is_empty() {
+                                    continue;
+                                }
+                                // Keep text and reasoning in their original order
+                                if !pending_text.is_empty() {
+                                    let msg_id = if text_counter == 0 {
+                                        base_id.clone()
+                                    } else {
+                                        format!("{}-text-{}", base_id, text_counter)
+                                    };
+                                    text_counter += 1;
+
+                                    let message = Message {
+                                        id: msg_id,
+                                        role: MessageRole::Assistant,
+                                        content: std::mem::take(&mut pending_text),
+                                        attachments: Vec::new(),
+                                        timestamp,
+                                    };
+                                    chat_items.push(ChatItem::Message { message });
+                                }
+
+                                let thought = Thought {
+                                    id: format!("{}-thinking-{}", base_id, thinking_counter),
+                                    content: thinking.to_string(),
+                                    timestamp,
+                                };
+                                thinking_counter += 1;
+                                chat_items.push(ChatItem::Thought { thought });
+                            }
                             Some("tool_use") => {
                                 // Flush any pending text BEFORE adding the tool call
                                 if !pending_text.is_empty() {
@@ -562,6 +712,7 @@ pub fn load_session_chat_items(path: &PathBuf) -> Vec<ChatItem> {
                                         id: msg_id,
                                         role: MessageRole::Assistant,
                                         content: pending_text.clone(),
+                                        attachments: Vec::new(),
                                         timestamp,
                                     };
                                     chat_items.push(ChatItem::Message { message });
@@ -613,6 +764,7 @@ pub fn load_session_chat_items(path: &PathBuf) -> Vec<ChatItem> {
                             id: msg_id,
                             role: MessageRole::Assistant,
                             content: pending_text,
+                            attachments: Vec::new(),
                             timestamp,
                         };
                         chat_items.push(ChatItem::Message { message });
@@ -729,6 +881,7 @@ pub fn load_session_chat_items(path: &PathBuf) -> Vec<ChatItem> {
                     id,
                     role,
                     content: text,
+                    attachments: extract_image_attachments(msg.get("content")),
                     timestamp,
                 };
 
@@ -926,6 +1079,9 @@ fn parse_session_file(path: &PathBuf) -> Option<SessionInfo> {
         last_user_message,
         last_assistant_message,
         has_agent_response,
+        agent_id: None,
+        branched_from: None,
+        branches: Vec::new(),
     })
 }
 
@@ -955,6 +1111,38 @@ fn extract_text_content(content: Option<&serde_json::Value>) -> Option<String> {
     None
 }
 
+/// Extract pasted images from Claude message content as prompt attachments
+/// (e.g., [{"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "..."}}])
+fn extract_image_attachments(content: Option<&serde_json::Value>) -> Vec<ContentBlock> {
+    let arr = match content.and_then(|v| v.as_array()) {
+        Some(arr) => arr,
+        None => return Vec::new(),
+    };
+
+    arr.iter()
+        .filter(|item| item.get("type").and_then(|v| v.as_str()) == Some("image"))
+        .filter_map(|item| {
+            let source = item.get("source")?;
+            Some(ContentBlock::Image {
+                data: source.get("data")?.as_str()?.to_string(),
+                mime_type: source.get("media_type")?.as_str()?.to_string(),
+                uri: None,
+            })
+        })
+        .collect()
+}
+
+/// Branch points saved by `save_branch_points`; none if the file is missing or unreadable
+fn load_branch_points(path: &Path) -> HashMap<SessionId, BranchPoint> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable branch points in {:?}: {}", path, e);
+        HashMap::new()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -982,4 +1170,25 @@ mod tests {
         assert!(!is_system_message("Hello, how can I help?"));
         assert!(!is_system_message(""));
     }
+
+    #[test]
+    fn test_branch_points_survive_restart() {
+        let path = std::env::temp_dir().join(format!("aero-work-branches-{}.json", Uuid::new_v4()));
+        let branch_point = BranchPoint {
+            session_id: "original".to_string(),
+            message_id: "msg-1".to_string(),
+        };
+
+        let registry = SessionRegistry::with_branches_file(&path);
+        registry.link_branch(&"branch".to_string(), branch_point.clone());
+        drop(registry);
+
+        let registry = SessionRegistry::with_branches_file(&path);
+        assert_eq!(registry.branch_point("branch"), Some(branch_point));
+        assert_eq!(registry.branches_of("original"), vec!["branch".to_string()]);
+
+        registry.delete_session("branch").unwrap();
+        assert!(SessionRegistry::with_branches_file(&path).branch_point("branch").is_none());
+        let _ = std::fs::remove_file(&path);
+    }
 }
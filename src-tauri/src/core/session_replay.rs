@@ -0,0 +1,215 @@
+//! Session Replay Module
+//!
+//! Rebuilds a session from an ACP recording (see `acp::recorder`) by feeding its
+//! frames through a fresh `SessionStateManager`, the same way live traffic is
+//! applied, so a bug report shows exactly what the UI showed.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use crate::acp::{
+    read_recording, recording_path, ContentBlock, FrameDirection, PermissionRequest, RecordedFrame, SessionModeState,
+    SessionModelState, SessionNotification,
+};
+
+use super::config::data_dir;
+use super::session_state::SessionState;
+use super::session_state_manager::SessionStateManager;
+
+/// Where recordings are written when `recordTraffic` is enabled
+pub fn recordings_dir() -> PathBuf {
+    data_dir().join("recordings")
+}
+
+/// Replay the recording of a session from `recordings_dir()`
+pub fn replay_recording(session_id: &str) -> Result<SessionState, String> {
+    let path = recording_path(&recordings_dir(), session_id);
+    let frames = read_recording(&path).map_err(|e| format!("Failed to read recording {:?}: {}", path, e))?;
+    replay_frames(session_id, &frames).ok_or_else(|| format!("Recording has no frames for session {}", session_id))
+}
+
+/// State of `session_id` after applying the frames in order
+pub fn replay_frames(session_id: &str, frames: &[RecordedFrame]) -> Option<SessionState> {
+    let manager = SessionStateManager::new();
+    // Requests by sender and ID, to interpret their responses
+    let mut requests: HashMap<(FrameDirection, String), &Value> = HashMap::new();
+
+    for recorded in frames {
+        let frame = &recorded.frame;
+        let id = frame.get("id").filter(|id| !id.is_null());
+        let params = frame.get("params").cloned().unwrap_or_default();
+        let frame_session = params.get("sessionId").and_then(|s| s.as_str()).map(String::from);
+
+        let Some(method) = frame.get("method").and_then(|m| m.as_str()) else {
+            // Response to an earlier request
+            let Some(id) = id else { continue };
+            let direction = match recorded.direction {
+                FrameDirection::Outbound => FrameDirection::Inbound,
+                FrameDirection::Inbound => FrameDirection::Outbound,
+            };
+            if let Some(request) = requests.remove(&(direction, id.to_string())) {
+                apply_response(&manager, request, frame);
+            }
+            continue;
+        };
+
+        if let Some(id) = id {
+            requests.insert((recorded.direction, id.to_string()), frame);
+        }
+
+        match (recorded.direction, method) {
+            (FrameDirection::Outbound, "session/load") => {
+                // Live loads start from a fresh state too
+                if let Some(session_id) = frame_session {
+                    manager.remove_session(&session_id);
+                    manager.create_session(session_id, string_param(&params, "cwd"), None, None);
+                }
+            }
+            (FrameDirection::Outbound, "session/prompt") => {
+                if let Some(session_id) = frame_session {
+                    ensure_session(&manager, &session_id);
+                    let prompt: Vec<ContentBlock> =
+                        serde_json::from_value(params["prompt"].clone()).unwrap_or_default();
+                    manager.add_user_prompt(&session_id, &prompt, None);
+                }
+            }
+            (FrameDirection::Inbound, "session/update") => {
+                if let Ok(notification) = serde_json::from_value::<SessionNotification>(params) {
+                    ensure_session(&manager, &notification.session_id);
+                    manager.apply_update(&notification.session_id, notification.update);
+                }
+            }
+            (FrameDirection::Inbound, "session/request_permission") => {
+                let mut request = params;
+                request["requestId"] = id.cloned().unwrap_or_default();
+                if let Ok(request) = serde_json::from_value::<PermissionRequest>(request) {
+                    ensure_session(&manager, &request.session_id);
+                    manager.set_pending_permission(&request.session_id.clone(), Some(request));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    manager.get_state(&session_id.to_string())
+}
+
+/// Apply the effect of a successful response on the session it belongs to
+fn apply_response(manager: &SessionStateManager, request: &Value, response: &Value) {
+    let method = request.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+    let params = request.get("params").cloned().unwrap_or_default();
+    let Some(result) = response.get("result") else {
+        return;
+    };
+
+    match method {
+        "session/new" | "session/resume" | "session/fork" => {
+            let Some(session_id) = result.get("sessionId").and_then(|s| s.as_str()) else {
+                return;
+            };
+            let (modes, models) = modes_and_models(result);
+            let session_id = session_id.to_string();
+            if manager.has_session(&session_id) {
+                manager.set_modes_and_models(&session_id, modes, models);
+            } else {
+                manager.create_session(session_id, string_param(&params, "cwd"), modes, models);
+            }
+        }
+        "session/load" => {
+            let session_id = string_param(&params, "sessionId");
+            let (modes, models) = modes_and_models(result);
+            manager.set_modes_and_models(&session_id, modes, models);
+        }
+        "session/set_model" => {
+            manager.set_current_model(&string_param(&params, "sessionId"), &string_param(&params, "modelId"));
+        }
+        // Our answer to a permission request
+        "session/request_permission" => {
+            manager.set_pending_permission(&string_param(&params, "sessionId"), None);
+        }
+        _ => {}
+    }
+}
+
+/// Sessions recorded mid-way have no creating request
+fn ensure_session(manager: &SessionStateManager, session_id: &str) {
+    let session_id = session_id.to_string();
+    if !manager.has_session(&session_id) {
+        manager.create_session(session_id, String::new(), None, None);
+    }
+}
+
+fn modes_and_models(result: &Value) -> (Option<SessionModeState>, Option<SessionModelState>) {
+    let modes = result.get("modes").and_then(|m| serde_json::from_value(m.clone()).ok());
+    let models = result.get("models").and_then(|m| serde_json::from_value(m.clone()).ok());
+    (modes, models)
+}
+
+fn string_param(params: &Value, key: &str) -> String {
+    params.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acp::AcpRecorder;
+    use crate::core::session_state::ChatItem;
+
+    fn record_all(recorder: &AcpRecorder, frames: &[(FrameDirection, Value)]) {
+        for (direction, frame) in frames {
+            recorder.record(*direction, &frame.to_string());
+        }
+    }
+
+    #[test]
+    fn test_recording_replays_into_session_state() {
+        let dir = std::env::temp_dir().join(format!("aero-work-recording-{}", uuid::Uuid::new_v4()));
+        let recorder = AcpRecorder::new(&dir);
+        let update = |text: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "session/update",
+                "params": {
+                    "sessionId": "s1",
+                    "update": { "sessionUpdate": "agent_message_chunk", "content": { "type": "text", "text": text } },
+                },
+            })
+        };
+        record_all(
+            &recorder,
+            &[
+                (FrameDirection::Outbound, serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })),
+                (FrameDirection::Inbound, serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "protocolVersion": 1 } })),
+                (FrameDirection::Outbound, serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "session/new", "params": { "cwd": "/work", "mcpServers": [] } })),
+                (FrameDirection::Inbound, serde_json::json!({ "jsonrpc": "2.0", "id": 2, "result": { "sessionId": "s1" } })),
+                (FrameDirection::Outbound, serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "session/prompt", "params": { "sessionId": "s1", "prompt": [{ "type": "text", "text": "Hi" }] } })),
+                (FrameDirection::Inbound, update("Hello ")),
+                (FrameDirection::Inbound, update("there")),
+                (FrameDirection::Inbound, serde_json::json!({ "jsonrpc": "2.0", "id": 7, "method": "session/request_permission", "params": { "sessionId": "s1", "toolCall": { "toolCallId": "t1" }, "options": [] } })),
+                (FrameDirection::Inbound, serde_json::json!({ "jsonrpc": "2.0", "id": 3, "result": { "stopReason": "end_turn" } })),
+            ],
+        );
+
+        // Session traffic, including the `session/new` request, lands in the session's file
+        let frames = read_recording(&recording_path(&dir, "s1")).unwrap();
+        assert_eq!(frames.len(), 7);
+        assert_eq!(frames[0].frame["method"], "session/new");
+        // `initialize` and, as it is recorded before its response, the `session/new` request
+        let unscoped = read_recording(&recording_path(&dir, "unscoped")).unwrap();
+        assert_eq!(unscoped.len(), 3);
+
+        let state = replay_frames("s1", &frames).unwrap();
+        assert_eq!(state.cwd, "/work");
+        assert_eq!(state.chat_items.len(), 2);
+        match &state.chat_items[1] {
+            ChatItem::Message { message } => assert_eq!(message.content, "Hello there"),
+            other => panic!("unexpected item {:?}", other),
+        }
+        // The permission request was never answered
+        assert!(state.pending_permission.is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -10,9 +10,13 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::acp::{
-    AvailableCommand, ContentBlock, PermissionRequest, Plan, SessionId, SessionModeId,
-    SessionModeState, SessionModelState, SessionUpdate, ToolCall, ToolCallId, ToolCallUpdate,
+    AvailableCommand, ContentBlock, PermissionRequest, Plan, SessionConfigOption, SessionId,
+    SessionModeId, SessionModeState, SessionModelState, SessionUpdate, ToolCall, ToolCallId,
+    ToolCallStatus, ToolCallUpdate, UsageUpdate,
 };
+use tracing::debug;
+
+use super::session_registry::truncate_string;
 
 /// Message role
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -29,16 +33,74 @@ pub struct Message {
     pub id: String,
     pub role: MessageRole,
     pub content: String,
+    /// Non-text prompt blocks (images, embedded files, links) sent with the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<ContentBlock>,
+    pub timestamp: i64,
+}
+
+/// Agent reasoning, kept apart from the assistant's answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thought {
+    pub id: String,
+    pub content: String,
+    pub timestamp: i64,
+}
+
+/// A correction that interrupted a turn (`steer_session`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Steering {
+    pub id: String,
+    pub correction: String,
+    /// What the interrupted turn had done, if it was cancelled mid-way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
     pub timestamp: i64,
 }
 
-/// Unified chat item - either a message or a tool call
+/// A prompt that failed with no client waiting for the answer, e.g. a queued prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFailure {
+    pub id: String,
+    /// The user message of the prompt, if it was added to the chat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    pub error: String,
+    pub timestamp: i64,
+}
+
+/// Unified chat item - a message, an agent thought, a tool call, a steering note or a
+/// failed prompt
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ChatItem {
     Message { message: Message },
+    Thought { thought: Thought },
     #[serde(rename_all = "camelCase")]
     ToolCall { tool_call: ToolCall },
+    Steering { steering: Steering },
+    PromptFailure { failure: PromptFailure },
+}
+
+/// Longest excerpt of a message in a turn summary
+const SUMMARY_MESSAGE_CHARS: usize = 300;
+
+/// A prompt sent while a turn was running, waiting to be sent to the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedPrompt {
+    pub id: String,
+    pub prompt: Vec<ContentBlock>,
+    /// ID for the user message once sent (from the client's optimistic update)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Correction that interrupts the running turn (`steer_session`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub steer: bool,
+    pub queued_at: i64,
 }
 
 /// Full session state stored in backend
@@ -54,11 +116,29 @@ pub struct SessionState {
     pub modes: Option<SessionModeState>,
     pub models: Option<SessionModelState>,
     pub available_commands: Option<Vec<AvailableCommand>>,
+    /// Title set by the agent (`session_info_update`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Context window usage reported by the agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageUpdate>,
+    /// Session settings exposed by the agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_options: Option<Vec<SessionConfigOption>>,
     /// Pending permission request waiting for user response
     pub pending_permission: Option<PermissionRequest>,
     /// Dangerous mode - auto-approve all tool calls for this session
     #[serde(default)]
     pub dangerous_mode: bool,
+    /// Prompts waiting for the running turn, sent in order
+    #[serde(default)]
+    pub queued_prompts: Vec<QueuedPrompt>,
+    /// A prompt turn is in progress
+    #[serde(skip)]
+    turn_running: bool,
+    /// Sequence number of the last update, to resume a subscription from (`sinceSeq`)
+    #[serde(default)]
+    pub seq: u64,
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -75,8 +155,14 @@ impl SessionState {
             modes: None,
             models: None,
             available_commands: None,
+            title: None,
+            usage: None,
+            config_options: None,
             pending_permission: None,
             dangerous_mode: false,
+            queued_prompts: Vec::new(),
+            turn_running: false,
+            seq: 0,
             created_at: now,
             updated_at: now,
         }
@@ -121,25 +207,140 @@ impl SessionState {
         self.updated_at = Utc::now().timestamp_millis();
     }
 
-    /// Load historical chat items (for resuming sessions)
-    pub fn load_history(&mut self, chat_items: Vec<ChatItem>) {
-        // Build tool_calls_map index for any tool calls in history
-        for (idx, item) in chat_items.iter().enumerate() {
-            if let ChatItem::ToolCall { tool_call } = item {
-                self.tool_calls_map.insert(tool_call.tool_call_id.clone(), idx);
+    /// Switch the current model; returns false if the model isn't available
+    pub fn set_current_model(&mut self, model_id: &str) -> bool {
+        match self.models {
+            Some(ref mut models) if models.available_models.iter().any(|m| m.model_id == model_id) => {
+                models.current_model_id = model_id.to_string();
+                self.updated_at = Utc::now().timestamp_millis();
+                true
             }
+            _ => false,
         }
+    }
+
+    /// Load historical chat items (for resuming sessions)
+    pub fn load_history(&mut self, chat_items: Vec<ChatItem>) {
         self.chat_items = chat_items;
+        self.rebuild_tool_call_index();
         self.updated_at = Utc::now().timestamp_millis();
     }
 
+    /// Rebuild the tool call index (not serialized) from chat_items
+    pub fn rebuild_tool_call_index(&mut self) {
+        self.tool_calls_map = self
+            .chat_items
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, item)| match item {
+                ChatItem::ToolCall { tool_call } => Some((tool_call.tool_call_id.clone(), idx)),
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// Apply a delta produced by this state earlier, at time `at`; used to rebuild a
+    /// stored session from its log
+    pub fn apply_delta(&mut self, update: &SessionStateUpdate, at: i64) {
+        match update {
+            SessionStateUpdate::MessageChunk { content } => {
+                if let Some(ChatItem::Message { message }) = self.chat_items.last_mut() {
+                    message.content.push_str(content);
+                    message.timestamp = at;
+                }
+            }
+            SessionStateUpdate::MessageAdded { message } => {
+                self.chat_items.push(ChatItem::Message { message: message.clone() });
+            }
+            SessionStateUpdate::MessageUpdated { message } => {
+                let existing = self.chat_items.iter_mut().rev().find_map(|item| match item {
+                    ChatItem::Message { message: m } if m.id == message.id => Some(m),
+                    _ => None,
+                });
+                if let Some(existing) = existing {
+                    *existing = message.clone();
+                }
+            }
+            SessionStateUpdate::ThoughtChunk { content } => {
+                if let Some(ChatItem::Thought { thought }) = self.chat_items.last_mut() {
+                    thought.content.push_str(content);
+                    thought.timestamp = at;
+                }
+            }
+            SessionStateUpdate::ThoughtAdded { thought } => {
+                self.chat_items.push(ChatItem::Thought { thought: thought.clone() });
+            }
+            SessionStateUpdate::ToolCallAdded { tool_call } => {
+                self.tool_calls_map.insert(tool_call.tool_call_id.clone(), self.chat_items.len());
+                self.chat_items.push(ChatItem::ToolCall { tool_call: tool_call.clone() });
+            }
+            SessionStateUpdate::ToolCallUpdated { tool_call } => {
+                if let Some(&idx) = self.tool_calls_map.get(&tool_call.tool_call_id) {
+                    self.chat_items[idx] = ChatItem::ToolCall { tool_call: tool_call.clone() };
+                }
+            }
+            SessionStateUpdate::PlanUpdated { plan } => self.plan = Some(plan.clone()),
+            SessionStateUpdate::AvailableCommandsUpdated { commands } => {
+                self.available_commands = Some(commands.clone());
+            }
+            SessionStateUpdate::CurrentModeUpdated { mode_id } => {
+                if let Some(ref mut modes) = self.modes {
+                    modes.current_mode_id = mode_id.clone();
+                }
+            }
+            SessionStateUpdate::CurrentModelUpdated { model_id } => {
+                self.set_current_model(model_id);
+            }
+            SessionStateUpdate::SessionInfoUpdated { title } => self.title = title.clone(),
+            SessionStateUpdate::UsageUpdated { usage } => self.usage = Some(usage.clone()),
+            SessionStateUpdate::ConfigOptionsUpdated { config_options } => {
+                self.config_options = Some(config_options.clone());
+            }
+            SessionStateUpdate::FullState { state } => {
+                *self = state.as_ref().clone();
+                self.rebuild_tool_call_index();
+            }
+            SessionStateUpdate::DangerousModeUpdated { dangerous_mode } => self.dangerous_mode = *dangerous_mode,
+            SessionStateUpdate::PendingPermissionUpdated { pending_permission } => {
+                self.pending_permission = pending_permission.clone();
+            }
+            SessionStateUpdate::QueuedPromptsUpdated { queued_prompts } => {
+                self.queued_prompts = queued_prompts.clone();
+            }
+            SessionStateUpdate::TurnSteered { steering } => {
+                self.chat_items.push(ChatItem::Steering { steering: steering.clone() });
+            }
+            SessionStateUpdate::PromptFailed { failure } => {
+                self.chat_items.push(ChatItem::PromptFailure { failure: failure.clone() });
+            }
+            SessionStateUpdate::Noop => {}
+        }
+        self.updated_at = at;
+    }
+
     /// Add a user message
     /// If message_id is provided, use it; otherwise generate a new UUID
     pub fn add_user_message(&mut self, content: String, message_id: Option<String>) -> SessionStateUpdate {
+        self.add_user_prompt(&[ContentBlock::Text { text: content }], message_id)
+    }
+
+    /// Add a user message from prompt content blocks
+    /// Text blocks form the message content, other blocks are kept as attachments
+    pub fn add_user_prompt(&mut self, prompt: &[ContentBlock], message_id: Option<String>) -> SessionStateUpdate {
+        let mut content = String::new();
+        let mut attachments = Vec::new();
+        for block in prompt {
+            match block {
+                ContentBlock::Text { text } => content.push_str(text),
+                other => attachments.push(other.clone()),
+            }
+        }
+
         let message = Message {
             id: message_id.unwrap_or_else(|| Uuid::new_v4().to_string()),
             role: MessageRole::User,
             content,
+            attachments,
             timestamp: Utc::now().timestamp_millis(),
         };
         self.chat_items.push(ChatItem::Message {
@@ -149,6 +350,186 @@ impl SessionState {
         SessionStateUpdate::MessageAdded { message }
     }
 
+    /// Note that the turn was interrupted by a correction
+    pub fn add_steering(&mut self, correction: String, summary: Option<String>) -> SessionStateUpdate {
+        let steering = Steering {
+            id: Uuid::new_v4().to_string(),
+            correction,
+            summary,
+            timestamp: Utc::now().timestamp_millis(),
+        };
+        self.chat_items.push(ChatItem::Steering {
+            steering: steering.clone(),
+        });
+        self.updated_at = Utc::now().timestamp_millis();
+        SessionStateUpdate::TurnSteered { steering }
+    }
+
+    /// Note that a prompt nobody waits for failed
+    pub fn add_prompt_failure(&mut self, message_id: Option<String>, error: String) -> SessionStateUpdate {
+        let failure = PromptFailure {
+            id: Uuid::new_v4().to_string(),
+            message_id,
+            error,
+            timestamp: Utc::now().timestamp_millis(),
+        };
+        self.chat_items.push(ChatItem::PromptFailure {
+            failure: failure.clone(),
+        });
+        self.updated_at = Utc::now().timestamp_millis();
+        SessionStateUpdate::PromptFailed { failure }
+    }
+
+    /// A prompt turn is in progress
+    pub fn is_turn_running(&self) -> bool {
+        self.turn_running
+    }
+
+    /// Start a prompt turn, or queue the prompt if one is running
+    /// Returns the queued prompt if it was queued
+    pub fn start_turn_or_queue(&mut self, prompt: Vec<ContentBlock>, message_id: Option<String>) -> Option<QueuedPrompt> {
+        if !self.turn_running {
+            self.turn_running = true;
+            return None;
+        }
+        let queued = QueuedPrompt {
+            id: Uuid::new_v4().to_string(),
+            prompt,
+            message_id,
+            steer: false,
+            queued_at: Utc::now().timestamp_millis(),
+        };
+        self.queued_prompts.push(queued.clone());
+        self.updated_at = Utc::now().timestamp_millis();
+        Some(queued)
+    }
+
+    /// Queue a correction for the running turn ahead of other queued prompts
+    /// Returns `None` (and queues nothing) if no turn is running
+    pub fn queue_steering(&mut self, prompt: Vec<ContentBlock>, message_id: Option<String>) -> Option<QueuedPrompt> {
+        if !self.turn_running {
+            return None;
+        }
+        let queued = QueuedPrompt {
+            id: Uuid::new_v4().to_string(),
+            prompt,
+            message_id,
+            steer: true,
+            queued_at: Utc::now().timestamp_millis(),
+        };
+        // After earlier corrections, so they are sent in the order given
+        let position = self.queued_prompts.iter().take_while(|q| q.steer).count();
+        self.queued_prompts.insert(position, queued.clone());
+        self.updated_at = Utc::now().timestamp_millis();
+        Some(queued)
+    }
+
+    /// What the current (or last) turn did so far: the items after the last user message
+    pub fn turn_summary(&self) -> Option<String> {
+        let start = self
+            .chat_items
+            .iter()
+            .rposition(|item| matches!(item, ChatItem::Message { message } if message.role == MessageRole::User))
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+
+        let lines: Vec<String> = self.chat_items[start..]
+            .iter()
+            .filter_map(|item| match item {
+                ChatItem::ToolCall { tool_call } => {
+                    let status = match tool_call.status {
+                        Some(ToolCallStatus::Pending) | None => "pending",
+                        Some(ToolCallStatus::InProgress) => "in progress",
+                        Some(ToolCallStatus::Completed) => "completed",
+                        Some(ToolCallStatus::Failed) => "failed",
+                    };
+                    Some(format!("- Tool call: {} ({})", tool_call.title, status))
+                }
+                ChatItem::Message { message } if !message.content.trim().is_empty() => {
+                    Some(format!("- Said: {}", truncate_string(message.content.trim(), SUMMARY_MESSAGE_CHARS)))
+                }
+                ChatItem::Message { .. }
+                | ChatItem::Thought { .. }
+                | ChatItem::Steering { .. }
+                | ChatItem::PromptFailure { .. } => None,
+            })
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// The chat before a user message and the message itself; what a branch that
+    /// replaces the message starts from
+    pub fn history_before(&self, message_id: &str) -> Option<(Vec<ChatItem>, &Message)> {
+        self.chat_items.iter().enumerate().find_map(|(idx, item)| match item {
+            ChatItem::Message { message } if message.role == MessageRole::User && message.id == message_id => {
+                Some((self.chat_items[..idx].to_vec(), message))
+            }
+            _ => None,
+        })
+    }
+
+
+    /// End the running turn; returns the next queued prompt, which starts the next turn
+    pub fn finish_turn(&mut self) -> Option<QueuedPrompt> {
+        if self.queued_prompts.is_empty() {
+            self.turn_running = false;
+            return None;
+        }
+        self.updated_at = Utc::now().timestamp_millis();
+        Some(self.queued_prompts.remove(0))
+    }
+
+    /// Replace the content of a queued prompt
+    pub fn update_queued_prompt(&mut self, id: &str, prompt: Vec<ContentBlock>) -> Result<(), String> {
+        let queued = self
+            .queued_prompts
+            .iter_mut()
+            .find(|q| q.id == id)
+            .ok_or_else(|| format!("Queued prompt not found: {}", id))?;
+        queued.prompt = prompt;
+        self.updated_at = Utc::now().timestamp_millis();
+        Ok(())
+    }
+
+    /// Drop queued prompts when no turn is running to send them, as after a restart
+    pub fn clear_stale_queue(&mut self) {
+        if !self.turn_running {
+            self.queued_prompts.clear();
+        }
+    }
+
+    /// Drop a queued prompt before it is sent
+    pub fn remove_queued_prompt(&mut self, id: &str) -> Result<(), String> {
+        let before = self.queued_prompts.len();
+        self.queued_prompts.retain(|q| q.id != id);
+        if self.queued_prompts.len() == before {
+            return Err(format!("Queued prompt not found: {}", id));
+        }
+        self.updated_at = Utc::now().timestamp_millis();
+        Ok(())
+    }
+
+    /// Put the queue in the given order; `ids` must list every queued prompt once
+    pub fn reorder_queued_prompts(&mut self, ids: &[String]) -> Result<(), String> {
+        let mut reordered = Vec::with_capacity(ids.len());
+        for id in ids {
+            let queued = self
+                .queued_prompts
+                .iter()
+                .find(|q| &q.id == id)
+                .ok_or_else(|| format!("Queued prompt not found: {}", id))?;
+            reordered.push(queued.clone());
+        }
+        if reordered.len() != self.queued_prompts.len()
+            || ids.iter().enumerate().any(|(i, id)| ids[..i].contains(id))
+        {
+            return Err("Reorder must list every queued prompt exactly once".to_string());
+        }
+        self.queued_prompts = reordered;
+        self.updated_at = Utc::now().timestamp_millis();
+        Ok(())
+    }
+
     /// Apply a SessionUpdate from ACP agent and return the delta for broadcasting
     pub fn apply_update(&mut self, update: &SessionUpdate) -> SessionStateUpdate {
         self.updated_at = Utc::now().timestamp_millis();
@@ -162,8 +543,7 @@ impl SessionState {
                 self.handle_user_message_chunk(content)
             }
             SessionUpdate::AgentThoughtChunk { content } => {
-                // Treat thought chunks like message chunks for now
-                self.handle_agent_message_chunk(content)
+                self.handle_agent_thought_chunk(content)
             }
             SessionUpdate::ToolCall(tool_call) => {
                 // Check if tool call already exists (avoid duplicates)
@@ -228,6 +608,35 @@ impl SessionState {
                     mode_id: current_mode_id.clone(),
                 }
             }
+            SessionUpdate::SessionInfoUpdate { title, .. } => match title {
+                Some(title) => {
+                    self.title = Some(title.clone());
+                    SessionStateUpdate::SessionInfoUpdated {
+                        title: self.title.clone(),
+                    }
+                }
+                None => SessionStateUpdate::Noop,
+            },
+            SessionUpdate::UsageUpdate(usage) => {
+                self.usage = Some(usage.clone());
+                SessionStateUpdate::UsageUpdated {
+                    usage: usage.clone(),
+                }
+            }
+            SessionUpdate::ConfigOptionUpdate { config_options } => {
+                self.config_options = Some(config_options.clone());
+                SessionStateUpdate::ConfigOptionsUpdated {
+                    config_options: config_options.clone(),
+                }
+            }
+            SessionUpdate::Unknown(raw) => {
+                // Still forwarded raw as session/update, just not stored
+                debug!(
+                    "Ignoring unknown session update: {}",
+                    raw.get("sessionUpdate").and_then(|v| v.as_str()).unwrap_or("?")
+                );
+                SessionStateUpdate::Noop
+            }
         }
     }
 
@@ -245,7 +654,7 @@ impl SessionState {
             if message.role == MessageRole::Assistant {
                 // Append to existing assistant message
                 message.content.push_str(&text);
-                message.timestamp = Utc::now().timestamp_millis();
+                message.timestamp = self.updated_at;
                 return SessionStateUpdate::MessageChunk { content: text };
             }
         }
@@ -255,6 +664,7 @@ impl SessionState {
             id: Uuid::new_v4().to_string(),
             role: MessageRole::Assistant,
             content: text,
+            attachments: Vec::new(),
             timestamp: Utc::now().timestamp_millis(),
         };
         self.chat_items.push(ChatItem::Message {
@@ -263,20 +673,52 @@ impl SessionState {
         SessionStateUpdate::MessageAdded { message }
     }
 
-    /// Handle user message chunk
-    /// Only appends if the LAST item is a user message
-    fn handle_user_message_chunk(&mut self, content: &ContentBlock) -> SessionStateUpdate {
+    /// Handle agent thought chunk - append to the last thought or start a new one
+    /// Only appends if the LAST item in chat_items is a thought
+    fn handle_agent_thought_chunk(&mut self, content: &ContentBlock) -> SessionStateUpdate {
         let text = match content {
             ContentBlock::Text { text } => text.clone(),
             _ => return SessionStateUpdate::Noop,
         };
 
+        if let Some(ChatItem::Thought { thought }) = self.chat_items.last_mut() {
+            thought.content.push_str(&text);
+            thought.timestamp = self.updated_at;
+            return SessionStateUpdate::ThoughtChunk { content: text };
+        }
+
+        let thought = Thought {
+            id: Uuid::new_v4().to_string(),
+            content: text,
+            timestamp: Utc::now().timestamp_millis(),
+        };
+        self.chat_items.push(ChatItem::Thought {
+            thought: thought.clone(),
+        });
+        SessionStateUpdate::ThoughtAdded { thought }
+    }
+
+    /// Handle user message chunk
+    /// Only appends if the LAST item is a user message
+    fn handle_user_message_chunk(&mut self, content: &ContentBlock) -> SessionStateUpdate {
+        // Non-text chunks are attachments of the user message
+        let (text, attachments) = match content {
+            ContentBlock::Text { text } => (text.clone(), Vec::new()),
+            other => (String::new(), vec![other.clone()]),
+        };
+
         // Check if the LAST item is a user message - only then append
         if let Some(ChatItem::Message { message }) = self.chat_items.last_mut() {
             if message.role == MessageRole::User {
+                message.timestamp = self.updated_at;
+                if !attachments.is_empty() {
+                    message.attachments.extend(attachments);
+                    return SessionStateUpdate::MessageUpdated {
+                        message: message.clone(),
+                    };
+                }
                 // Append to existing user message
                 message.content.push_str(&text);
-                message.timestamp = Utc::now().timestamp_millis();
                 return SessionStateUpdate::MessageChunk { content: text };
             }
         }
@@ -286,6 +728,7 @@ impl SessionState {
             id: Uuid::new_v4().to_string(),
             role: MessageRole::User,
             content: text,
+            attachments,
             timestamp: Utc::now().timestamp_millis(),
         };
         self.chat_items.push(ChatItem::Message {
@@ -340,6 +783,14 @@ impl SessionState {
     }
 }
 
+/// A delta with its place in the session's update sequence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequencedUpdate {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub update: SessionStateUpdate,
+}
+
 /// Delta update for broadcasting to clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "updateType", rename_all = "snake_case")]
@@ -348,6 +799,12 @@ pub enum SessionStateUpdate {
     MessageChunk { content: String },
     /// A new message was added
     MessageAdded { message: Message },
+    /// An existing message changed (e.g. attachments added)
+    MessageUpdated { message: Message },
+    /// Append text to the last thought
+    ThoughtChunk { content: String },
+    /// A new agent thought was added
+    ThoughtAdded { thought: Thought },
     /// A new tool call was added
     ToolCallAdded { tool_call: ToolCall },
     /// An existing tool call was updated
@@ -358,11 +815,31 @@ pub enum SessionStateUpdate {
     AvailableCommandsUpdated { commands: Vec<AvailableCommand> },
     /// Current mode was updated
     CurrentModeUpdated { mode_id: SessionModeId },
+    /// Current model was updated
+    #[serde(rename_all = "camelCase")]
+    CurrentModelUpdated { model_id: String },
+    /// Session title was set by the agent
+    SessionInfoUpdated { title: Option<String> },
+    /// Context window usage changed
+    UsageUpdated { usage: UsageUpdate },
+    /// Session settings exposed by the agent changed
+    #[serde(rename_all = "camelCase")]
+    ConfigOptionsUpdated { config_options: Vec<SessionConfigOption> },
     /// Full state sync (for new subscribers)
     FullState { state: Box<SessionState> },
     /// Dangerous mode was updated
     #[serde(rename_all = "camelCase")]
     DangerousModeUpdated { dangerous_mode: bool },
+    /// A permission request is waiting for the user, or was answered (None)
+    #[serde(rename_all = "camelCase")]
+    PendingPermissionUpdated { pending_permission: Option<PermissionRequest> },
+    /// Prompts were queued, edited, reordered, cancelled or sent
+    #[serde(rename_all = "camelCase")]
+    QueuedPromptsUpdated { queued_prompts: Vec<QueuedPrompt> },
+    /// The running turn was interrupted to send a correction (`steer_session`)
+    TurnSteered { steering: Steering },
+    /// A prompt nobody waits for failed
+    PromptFailed { failure: PromptFailure },
     /// No operation (used for unhandled updates)
     Noop,
 }
@@ -409,6 +886,100 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prompts_queue_while_turn_runs() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        let text = |t: &str| vec![ContentBlock::Text { text: t.to_string() }];
+
+        assert!(state.start_turn_or_queue(text("first"), None).is_none());
+        let second = state.start_turn_or_queue(text("second"), None).unwrap();
+        let third = state.start_turn_or_queue(text("third"), Some("msg-3".to_string())).unwrap();
+        assert_eq!(state.queued_prompts.len(), 2);
+
+        state.update_queued_prompt(&second.id, text("second, edited")).unwrap();
+        state.reorder_queued_prompts(&[third.id.clone(), second.id.clone()]).unwrap();
+        assert!(state.reorder_queued_prompts(&[third.id.clone(), third.id.clone()]).is_err());
+        assert!(state.remove_queued_prompt("missing").is_err());
+
+        // Finishing a turn hands out the next prompt in queue order
+        let next = state.finish_turn().unwrap();
+        assert_eq!(next.message_id.as_deref(), Some("msg-3"));
+        let next = state.finish_turn().unwrap();
+        assert!(matches!(&next.prompt[0], ContentBlock::Text { text } if text == "second, edited"));
+        assert!(state.finish_turn().is_none());
+
+        // The session is idle again
+        assert!(state.start_turn_or_queue(text("fourth"), None).is_none());
+    }
+
+    #[test]
+    fn test_steering_jumps_queue_and_summarizes_turn() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        let text = |t: &str| vec![ContentBlock::Text { text: t.to_string() }];
+        assert!(state.queue_steering(text("idle"), None).is_none());
+
+        state.add_user_message("Refactor the parser".to_string(), None);
+        assert!(state.start_turn_or_queue(text("Refactor the parser"), None).is_none());
+        state.start_turn_or_queue(text("queued"), None).unwrap();
+        let steer = state.queue_steering(text("Keep the old API"), None).unwrap();
+        assert_eq!(state.queued_prompts[0].id, steer.id);
+
+        state.apply_update(&SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Splitting the lexer out.".to_string() },
+        });
+        let summary = state.turn_summary().unwrap();
+        assert_eq!(summary, "- Said: Splitting the lexer out.");
+    }
+
+    #[test]
+    fn test_history_before_user_message() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        state.add_user_message("First".to_string(), Some("msg-1".to_string()));
+        state.apply_update(&SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Done.".to_string() },
+        });
+        state.add_user_message("Second".to_string(), Some("msg-2".to_string()));
+        state.apply_update(&SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Also done.".to_string() },
+        });
+
+        let (history, message) = state.history_before("msg-2").unwrap();
+        assert_eq!(message.content, "Second");
+        assert_eq!(history.len(), 2);
+        assert!(state.history_before("msg-1").unwrap().0.is_empty());
+        assert!(state.history_before("missing").is_none());
+        assert_eq!(state.chat_items.len(), 4);
+    }
+
+    #[test]
+    fn test_add_user_prompt_keeps_attachments() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        let update = state.add_user_prompt(
+            &[
+                ContentBlock::Text {
+                    text: "What is in ".to_string(),
+                },
+                ContentBlock::Image {
+                    data: "aGk=".to_string(),
+                    mime_type: "image/png".to_string(),
+                    uri: None,
+                },
+                ContentBlock::Text {
+                    text: "this?".to_string(),
+                },
+            ],
+            None,
+        );
+
+        if let SessionStateUpdate::MessageAdded { message } = update {
+            assert_eq!(message.content, "What is in this?");
+            assert_eq!(message.attachments.len(), 1);
+            assert!(matches!(message.attachments[0], ContentBlock::Image { .. }));
+        } else {
+            panic!("Expected MessageAdded update");
+        }
+    }
+
     #[test]
     fn test_apply_agent_message_chunk() {
         let mut state = SessionState::new("test".to_string(), "/".to_string());
@@ -434,4 +1005,80 @@ mod tests {
             assert_eq!(message.content, "Hello World");
         }
     }
+
+    #[test]
+    fn test_thought_chunks_stay_out_of_messages() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        let thought = |text: &str| SessionUpdate::AgentThoughtChunk {
+            content: ContentBlock::Text { text: text.to_string() },
+        };
+
+        assert!(matches!(state.apply_update(&thought("Let me")), SessionStateUpdate::ThoughtAdded { .. }));
+        assert!(matches!(state.apply_update(&thought(" think")), SessionStateUpdate::ThoughtChunk { .. }));
+        // An answer after the thought starts a new message instead of appending to it
+        let update = state.apply_update(&SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Answer".to_string() },
+        });
+        assert!(matches!(update, SessionStateUpdate::MessageAdded { .. }));
+
+        assert_eq!(state.chat_items.len(), 2);
+        match &state.chat_items[0] {
+            ChatItem::Thought { thought } => assert_eq!(thought.content, "Let me think"),
+            other => panic!("Expected thought, got {:?}", other),
+        }
+        match &state.chat_items[1] {
+            ChatItem::Message { message } => assert_eq!(message.content, "Answer"),
+            other => panic!("Expected message, got {:?}", other),
+        }
+        assert_eq!(state.turn_summary(), Some("- Said: Answer".to_string()));
+    }
+
+    #[test]
+    fn test_set_current_model() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+        assert!(!state.set_current_model("haiku"));
+
+        state.set_models(SessionModelState {
+            current_model_id: "sonnet".to_string(),
+            available_models: vec![crate::acp::SessionModel {
+                model_id: "haiku".to_string(),
+                name: "Haiku".to_string(),
+                description: None,
+            }],
+        });
+        assert!(state.set_current_model("haiku"));
+        assert!(!state.set_current_model("unknown"));
+        assert_eq!(state.models.unwrap().current_model_id, "haiku");
+    }
+
+    #[test]
+    fn test_newer_updates_stored_and_unknown_ignored() {
+        let mut state = SessionState::new("test".to_string(), "/".to_string());
+
+        let update: SessionUpdate = serde_json::from_value(serde_json::json!({
+            "sessionUpdate": "usage_update",
+            "used": 1200,
+            "size": 200000,
+        }))
+        .unwrap();
+        assert!(matches!(state.apply_update(&update), SessionStateUpdate::UsageUpdated { .. }));
+        assert_eq!(state.usage.as_ref().unwrap().used, 1200);
+
+        let update: SessionUpdate = serde_json::from_value(serde_json::json!({
+            "sessionUpdate": "session_info_update",
+            "title": "Fix login bug",
+        }))
+        .unwrap();
+        state.apply_update(&update);
+        assert_eq!(state.title.as_deref(), Some("Fix login bug"));
+
+        // A newer agent's update kind must not fail to parse
+        let update: SessionUpdate = serde_json::from_value(serde_json::json!({
+            "sessionUpdate": "something_new",
+            "data": 1,
+        }))
+        .unwrap();
+        assert!(matches!(update, SessionUpdate::Unknown(_)));
+        assert!(matches!(state.apply_update(&update), SessionStateUpdate::Noop));
+    }
 }
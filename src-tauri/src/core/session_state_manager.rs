@@ -3,26 +3,32 @@
 //! Manages all session states and handles subscriptions for real-time updates.
 //! This is the central point for session data management.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
 use parking_lot::RwLock;
 use tokio::sync::broadcast;
 use tracing::{debug, info};
 
-use crate::acp::{PermissionRequest, SessionId, SessionModeState, SessionModelState, SessionUpdate};
+use crate::acp::{ContentBlock, PermissionRequest, SessionId, SessionModeState, SessionModelState, SessionUpdate};
 
-use super::session_state::{SessionState, SessionStateUpdate};
+use super::session_state::{ChatItem, Message, QueuedPrompt, SequencedUpdate, SessionState, SessionStateUpdate};
+use super::session_store::SessionStore;
 
 /// Client identifier for subscription management
 pub type ClientId = String;
 
+/// Updates kept per session for clients resuming a subscription
+const UPDATE_LOG_SIZE: usize = 1000;
+
 /// Subscription info for a session
 struct SessionSubscription {
     /// Broadcast sender for this session's updates
-    tx: broadcast::Sender<SessionStateUpdate>,
+    tx: broadcast::Sender<SequencedUpdate>,
     /// Set of subscribed client IDs
     subscribers: HashSet<ClientId>,
+    /// The last `UPDATE_LOG_SIZE` updates, oldest first
+    log: VecDeque<SequencedUpdate>,
 }
 
 /// Session State Manager - single source of truth for all session data
@@ -31,6 +37,10 @@ pub struct SessionStateManager {
     states: RwLock<HashMap<SessionId, SessionState>>,
     /// Subscriptions by session ID
     subscriptions: RwLock<HashMap<SessionId, SessionSubscription>>,
+    /// Durable copy of every state; None keeps sessions in memory only
+    store: Option<SessionStore>,
+    /// States of inactive sessions, kept until they are restored
+    stored: RwLock<HashMap<SessionId, SessionState>>,
 }
 
 impl SessionStateManager {
@@ -38,6 +48,25 @@ impl SessionStateManager {
         Self {
             states: RwLock::new(HashMap::new()),
             subscriptions: RwLock::new(HashMap::new()),
+            store: None,
+            stored: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Manager that writes every state through to `store`, starting with the sessions
+    /// stored there (inactive until restored)
+    pub fn with_store(store: SessionStore) -> Self {
+        let stored: HashMap<SessionId, SessionState> = store
+            .load_all()
+            .into_iter()
+            .map(|state| (state.id.clone(), state))
+            .collect();
+        info!("Loaded {} stored session states", stored.len());
+        Self {
+            states: RwLock::new(HashMap::new()),
+            subscriptions: RwLock::new(HashMap::new()),
+            store: Some(store),
+            stored: RwLock::new(stored),
         }
     }
 
@@ -57,20 +86,7 @@ impl SessionStateManager {
             state.set_models(m);
         }
 
-        let mut states = self.states.write();
-        states.insert(id.clone(), state.clone());
-
-        // Create subscription channel for this session
-        let (tx, _) = broadcast::channel(1000);
-        let mut subs = self.subscriptions.write();
-        subs.insert(
-            id.clone(),
-            SessionSubscription {
-                tx,
-                subscribers: HashSet::new(),
-            },
-        );
-
+        self.insert_session(&state);
         info!("Created session state: {}", id);
         state
     }
@@ -95,35 +111,126 @@ impl SessionStateManager {
         // Load historical chat items
         state.load_history(chat_items);
 
-        let mut states = self.states.write();
-        states.insert(id.clone(), state.clone());
+        self.insert_session(&state);
+        info!("Created session state with history: {} ({} items)", id, state.chat_items.len());
+        state
+    }
+
+    /// Make a stored session active again as `session_id`, with the modes and models
+    /// the agent reports now
+    /// Returns the state as stored (with its mode and model selections), if there was one
+    pub fn restore_session(
+        &self,
+        stored_id: &SessionId,
+        session_id: SessionId,
+        modes: Option<SessionModeState>,
+        models: Option<SessionModelState>,
+    ) -> Option<SessionState> {
+        let stored = self.stored.write().remove(stored_id)?;
+        let mut state = stored.clone();
+        state.id = session_id;
+        // The request died with the turn that made it
+        state.set_pending_permission(None);
+        state.clear_stale_queue();
+        if let Some(m) = modes {
+            state.set_modes(m);
+        }
+        if let Some(m) = models {
+            state.set_models(m);
+        }
+        if let Some(ref store) = self.store {
+            if *stored_id != state.id {
+                store.remove(stored_id);
+            }
+        }
+
+        self.insert_session(&state);
+        info!("Restored stored session state: {} ({} items)", state.id, state.chat_items.len());
+        Some(stored)
+    }
+
+    /// State of an inactive session, as stored
+    pub fn stored_state(&self, session_id: &SessionId) -> Option<SessionState> {
+        self.stored.read().get(session_id).cloned()
+    }
 
-        // Create subscription channel for this session
-        let (tx, _) = broadcast::channel(1000);
+    /// Add an active session state with its subscription channel, replacing any stored one
+    fn insert_session(&self, state: &SessionState) {
+        self.stored.write().remove(&state.id);
+        let mut state = state.clone();
+
+        let mut states = self.states.write();
         let mut subs = self.subscriptions.write();
-        subs.insert(
-            id.clone(),
-            SessionSubscription {
-                tx,
-                subscribers: HashSet::new(),
-            },
-        );
+        if let Some(sub) = subs.get(&state.id) {
+            // Clients stayed subscribed while the session was reattached: carry on numbering
+            // from the updates they have and send them the new state
+            let last_seq = sub.log.back().map(|u| u.seq).unwrap_or(0);
+            state.seq = state.seq.max(last_seq);
+            drop(subs);
+            self.record_full_state(&mut state);
+        } else {
+            self.persist_snapshot(&state);
+            // Create subscription channel for this session
+            let (tx, _) = broadcast::channel(UPDATE_LOG_SIZE);
+            subs.insert(
+                state.id.clone(),
+                SessionSubscription {
+                    tx,
+                    subscribers: HashSet::new(),
+                    log: VecDeque::new(),
+                },
+            );
+        }
+        states.insert(state.id.clone(), state);
+    }
 
-        info!("Created session state with history: {} ({} items)", id, state.chat_items.len());
-        state
+    /// Set modes and models once known (e.g. after `session/load` replayed the history)
+    pub fn set_modes_and_models(
+        &self,
+        session_id: &SessionId,
+        modes: Option<SessionModeState>,
+        models: Option<SessionModelState>,
+    ) {
+        let mut states = self.states.write();
+        let Some(state) = states.get_mut(session_id) else {
+            return;
+        };
+        if let Some(m) = modes {
+            state.set_modes(m);
+        }
+        if let Some(m) = models {
+            state.set_models(m);
+        }
+        self.record_full_state(state);
     }
 
-    /// Remove a session state
+    /// Remove a session state; with a store it stays stored until restored
+    /// Subscribers keep their subscription and get the state if the session comes back.
     pub fn remove_session(&self, id: &SessionId) {
         let mut states = self.states.write();
-        states.remove(id);
+        let removed = states.remove(id);
 
         let mut subs = self.subscriptions.write();
-        subs.remove(id);
+        if subs.get(id).is_some_and(|sub| sub.subscribers.is_empty()) {
+            subs.remove(id);
+        }
 
+        if let (Some(state), Some(_)) = (removed, &self.store) {
+            self.stored.write().insert(id.clone(), state);
+        }
         info!("Removed session state: {}", id);
     }
 
+    /// Remove a session state, including its stored copy
+    pub fn delete_session(&self, id: &SessionId) {
+        self.remove_session(id);
+        self.subscriptions.write().remove(id);
+        self.stored.write().remove(id);
+        if let Some(ref store) = self.store {
+            store.remove(id);
+        }
+    }
+
     /// Check if a session exists
     pub fn has_session(&self, id: &SessionId) -> bool {
         let states = self.states.read();
@@ -132,46 +239,67 @@ impl SessionStateManager {
 
     /// Apply an update from ACP agent
     pub fn apply_update(&self, session_id: &SessionId, update: SessionUpdate) {
-        let delta = {
-            let mut states = self.states.write();
-            if let Some(state) = states.get_mut(session_id) {
-                state.apply_update(&update)
-            } else {
-                debug!("Session not found for update: {}", session_id);
-                return;
+        let mut states = self.states.write();
+        if let Some(state) = states.get_mut(session_id) {
+            match state.apply_update(&update) {
+                // Nothing to sequence or broadcast
+                SessionStateUpdate::Noop => {}
+                delta => self.record(state, delta),
             }
-        };
-
-        // Broadcast delta to subscribers (skip Noop)
-        if !matches!(delta, SessionStateUpdate::Noop) {
-            self.broadcast_update(session_id, delta);
+        } else {
+            debug!("Session not found for update: {}", session_id);
         }
     }
 
     /// Load historical chat items into an existing session
     pub fn load_history(&self, session_id: &SessionId, chat_items: Vec<super::session_state::ChatItem>) {
         let mut states = self.states.write();
-        if let Some(state) = states.get_mut(session_id) {
-            state.load_history(chat_items);
-            info!("Loaded history into session {}", session_id);
-        } else {
+        let Some(state) = states.get_mut(session_id) else {
             debug!("Session not found for history load: {}", session_id);
-        }
+            return;
+        };
+        state.load_history(chat_items);
+        info!("Loaded history into session {}", session_id);
+        self.record_full_state(state);
     }
 
     /// Add a user message to session
     /// If message_id is provided, use it; otherwise generate a new UUID
     pub fn add_user_message(&self, session_id: &SessionId, content: String, message_id: Option<String>) {
-        let delta = {
-            let mut states = self.states.write();
-            if let Some(state) = states.get_mut(session_id) {
-                state.add_user_message(content, message_id)
-            } else {
-                return;
-            }
-        };
+        let mut states = self.states.write();
+        if let Some(state) = states.get_mut(session_id) {
+            let delta = state.add_user_message(content, message_id);
+            self.record(state, delta);
+        }
+    }
+
+    /// Add a user message built from prompt content blocks
+    pub fn add_user_prompt(&self, session_id: &SessionId, prompt: &[ContentBlock], message_id: Option<String>) {
+        let mut states = self.states.write();
+        if let Some(state) = states.get_mut(session_id) {
+            let delta = state.add_user_prompt(prompt, message_id);
+            self.record(state, delta);
+        }
+    }
 
-        self.broadcast_update(session_id, delta);
+    /// Whether the session's last chat item is the message `message_id`
+    pub fn ends_with_message(&self, session_id: &SessionId, message_id: &str) -> bool {
+        let states = self.states.read();
+        states
+            .get(session_id)
+            .and_then(|state| state.chat_items.last())
+            .is_some_and(|item| matches!(item, ChatItem::Message { message } if message.id == message_id))
+    }
+
+    /// Whether a session's chat has a message
+    pub fn has_message(&self, session_id: &SessionId, message_id: &str) -> bool {
+        let states = self.states.read();
+        states.get(session_id).is_some_and(|state| {
+            state
+                .chat_items
+                .iter()
+                .any(|item| matches!(item, ChatItem::Message { message } if message.id == message_id))
+        })
     }
 
     /// Subscribe a client to session updates
@@ -180,12 +308,10 @@ impl SessionStateManager {
         &self,
         client_id: ClientId,
         session_id: &SessionId,
-    ) -> Option<(SessionState, broadcast::Receiver<SessionStateUpdate>)> {
-        // Get current state
-        let state = {
-            let states = self.states.read();
-            states.get(session_id).cloned()?
-        };
+    ) -> Option<(SessionState, broadcast::Receiver<SequencedUpdate>)> {
+        // Holding the state lock means the receiver gets every update after this state
+        let states = self.states.read();
+        let state = states.get(session_id).cloned()?;
 
         // Add to subscribers and get receiver
         let mut subs = self.subscriptions.write();
@@ -205,6 +331,7 @@ impl SessionStateManager {
 
     /// Unsubscribe a client from session updates
     pub fn unsubscribe(&self, client_id: &ClientId, session_id: &SessionId) {
+        let states = self.states.read();
         let mut subs = self.subscriptions.write();
         if let Some(sub) = subs.get_mut(session_id) {
             sub.subscribers.remove(client_id);
@@ -214,6 +341,10 @@ impl SessionStateManager {
                 session_id,
                 sub.subscribers.len()
             );
+            // Kept only for subscribers of a removed session
+            if sub.subscribers.is_empty() && !states.contains_key(session_id) {
+                subs.remove(session_id);
+            }
         }
     }
 
@@ -250,14 +381,16 @@ impl SessionStateManager {
     /// Set pending permission request for a session
     pub fn set_pending_permission(&self, session_id: &SessionId, request: Option<PermissionRequest>) {
         let mut states = self.states.write();
-        if let Some(state) = states.get_mut(session_id) {
-            state.set_pending_permission(request.clone());
-            if request.is_some() {
-                info!("Set pending permission for session {}", session_id);
-            } else {
-                info!("Cleared pending permission for session {}", session_id);
-            }
+        let Some(state) = states.get_mut(session_id) else {
+            return;
+        };
+        state.set_pending_permission(request.clone());
+        if request.is_some() {
+            info!("Set pending permission for session {}", session_id);
+        } else {
+            info!("Cleared pending permission for session {}", session_id);
         }
+        self.record(state, SessionStateUpdate::PendingPermissionUpdated { pending_permission: request });
     }
 
     /// Get pending permission request for a session
@@ -288,25 +421,176 @@ impl SessionStateManager {
 
     /// Set dangerous mode for a session and broadcast to all subscribers
     pub fn set_dangerous_mode(&self, session_id: &SessionId, enabled: bool) -> bool {
-        let updated = {
-            let mut states = self.states.write();
-            if let Some(state) = states.get_mut(session_id) {
-                state.set_dangerous_mode(enabled);
-                info!("Set dangerous mode for session {}: {}", session_id, enabled);
-                true
-            } else {
-                false
-            }
+        let mut states = self.states.write();
+        let Some(state) = states.get_mut(session_id) else {
+            return false;
+        };
+        state.set_dangerous_mode(enabled);
+        info!("Set dangerous mode for session {}: {}", session_id, enabled);
+        self.record(state, SessionStateUpdate::DangerousModeUpdated { dangerous_mode: enabled });
+        true
+    }
+
+    /// Whether a session offers the model (unknown sessions are left to the agent to check)
+    pub fn has_model(&self, session_id: &SessionId, model_id: &str) -> bool {
+        let states = self.states.read();
+        match states.get(session_id) {
+            Some(state) => state
+                .models
+                .as_ref()
+                .map(|m| m.available_models.iter().any(|model| model.model_id == model_id))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Set the current model of a session and broadcast to all subscribers
+    pub fn set_current_model(&self, session_id: &SessionId, model_id: &str) -> bool {
+        let mut states = self.states.write();
+        let Some(state) = states.get_mut(session_id) else {
+            return false;
         };
+        if !state.set_current_model(model_id) {
+            return false;
+        }
+        info!("Set model for session {}: {}", session_id, model_id);
+        let model_id = model_id.to_string();
+        self.record(state, SessionStateUpdate::CurrentModelUpdated { model_id });
+        true
+    }
+
+    /// Start a prompt turn, or queue the prompt if a turn is running
+    /// Returns the queued prompt if it was queued; unknown sessions never queue
+    pub fn start_turn_or_queue(
+        &self,
+        session_id: &SessionId,
+        prompt: Vec<ContentBlock>,
+        message_id: Option<String>,
+    ) -> Option<QueuedPrompt> {
+        let mut states = self.states.write();
+        let state = states.get_mut(session_id)?;
+        let queued = state.start_turn_or_queue(prompt, message_id)?;
+        self.record_queue(state);
+        info!("Queued prompt {} for busy session {}", queued.id, session_id);
+        Some(queued)
+    }
+
+    /// Whether a prompt turn is in progress
+    pub fn is_turn_running(&self, session_id: &SessionId) -> bool {
+        let states = self.states.read();
+        states.get(session_id).is_some_and(|state| state.is_turn_running())
+    }
+
+    /// Queue a correction ahead of other prompts if a turn is running (see `steer_session`)
+    pub fn queue_steering(
+        &self,
+        session_id: &SessionId,
+        prompt: Vec<ContentBlock>,
+        message_id: Option<String>,
+    ) -> Option<QueuedPrompt> {
+        let mut states = self.states.write();
+        let state = states.get_mut(session_id)?;
+        let queued = state.queue_steering(prompt, message_id)?;
+        self.record_queue(state);
+        info!("Queued correction {} for running session {}", queued.id, session_id);
+        Some(queued)
+    }
+
+    /// Add a note that a turn was interrupted by a correction
+    pub fn announce_steering(&self, session_id: &SessionId, correction: String, summary: Option<String>) {
+        let mut states = self.states.write();
+        if let Some(state) = states.get_mut(session_id) {
+            let delta = state.add_steering(correction, summary);
+            self.record(state, delta);
+        }
+    }
+
+    /// Tell subscribers a prompt failed when no client waits for its response
+    pub fn report_prompt_failure(&self, session_id: &SessionId, message_id: Option<String>, error: String) {
+        let mut states = self.states.write();
+        if let Some(state) = states.get_mut(session_id) {
+            let delta = state.add_prompt_failure(message_id, error);
+            self.record(state, delta);
+        }
+    }
+
+    /// Summary of what the last turn of a session did
+    pub fn turn_summary(&self, session_id: &SessionId) -> Option<String> {
+        let states = self.states.read();
+        states.get(session_id)?.turn_summary()
+    }
 
-        if updated {
-            // Broadcast to all subscribers
-            self.broadcast_update(session_id, SessionStateUpdate::DangerousModeUpdated {
-                dangerous_mode: enabled,
-            });
+    /// The chat of a session (live or stored) before one of its user messages, and
+    /// that message
+    pub fn history_before(
+        &self,
+        session_id: &SessionId,
+        message_id: &str,
+    ) -> Result<(Vec<ChatItem>, Message), String> {
+        let find = |state: &SessionState| {
+            state
+                .history_before(message_id)
+                .map(|(history, message)| (history, message.clone()))
+                .ok_or_else(|| format!("User message not found: {}", message_id))
+        };
+        if let Some(state) = self.states.read().get(session_id) {
+            return find(state);
         }
+        match self.stored.read().get(session_id) {
+            Some(state) => find(state),
+            None => Err(format!("Session not found: {}", session_id)),
+        }
+    }
+
+    /// End the running turn of a session; returns the next queued prompt to send
+    pub fn finish_turn(&self, session_id: &SessionId) -> Option<QueuedPrompt> {
+        let mut states = self.states.write();
+        let state = states.get_mut(session_id)?;
+        let next = state.finish_turn()?;
+        self.record_queue(state);
+        Some(next)
+    }
 
-        updated
+    /// Prompts waiting in a session's queue
+    pub fn queued_prompts(&self, session_id: &SessionId) -> Vec<QueuedPrompt> {
+        let states = self.states.read();
+        states.get(session_id)
+            .map(|s| s.queued_prompts.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replace the content of a queued prompt
+    pub fn update_queued_prompt(
+        &self,
+        session_id: &SessionId,
+        id: &str,
+        prompt: Vec<ContentBlock>,
+    ) -> Result<Vec<QueuedPrompt>, String> {
+        self.modify_queue(session_id, |state| state.update_queued_prompt(id, prompt))
+    }
+
+    /// Cancel a queued prompt before it is sent
+    pub fn cancel_queued_prompt(&self, session_id: &SessionId, id: &str) -> Result<Vec<QueuedPrompt>, String> {
+        self.modify_queue(session_id, |state| state.remove_queued_prompt(id))
+    }
+
+    /// Reorder a session's queue
+    pub fn reorder_queued_prompts(&self, session_id: &SessionId, ids: &[String]) -> Result<Vec<QueuedPrompt>, String> {
+        self.modify_queue(session_id, |state| state.reorder_queued_prompts(ids))
+    }
+
+    fn modify_queue(
+        &self,
+        session_id: &SessionId,
+        modify: impl FnOnce(&mut SessionState) -> Result<(), String>,
+    ) -> Result<Vec<QueuedPrompt>, String> {
+        let mut states = self.states.write();
+        let state = states
+            .get_mut(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+        modify(state)?;
+        self.record_queue(state);
+        Ok(state.queued_prompts.clone())
     }
 
     /// Get dangerous mode status for a session
@@ -317,16 +601,83 @@ impl SessionStateManager {
             .unwrap_or(false)
     }
 
-    /// Broadcast an update to all subscribers of a session
-    fn broadcast_update(&self, session_id: &SessionId, update: SessionStateUpdate) {
-        let subs = self.subscriptions.read();
-        if let Some(sub) = subs.get(session_id) {
+    /// Updates a client missed since `since_seq` and a receiver for the ones after them
+    /// When the gap is no longer in the update log, the missed updates are a single `FullState`.
+    /// Updates sent while this runs may arrive twice; clients skip sequence numbers they have.
+    pub fn subscribe_since(
+        &self,
+        client_id: ClientId,
+        session_id: &SessionId,
+        since_seq: u64,
+    ) -> Option<(Vec<SequencedUpdate>, broadcast::Receiver<SequencedUpdate>)> {
+        // Holding the state lock keeps new updates out of the log until the receiver exists
+        let states = self.states.read();
+        let state = states.get(session_id)?;
+        let mut subs = self.subscriptions.write();
+        let sub = subs.get_mut(session_id)?;
+
+        let oldest = sub.log.front().map(|u| u.seq).unwrap_or(state.seq + 1);
+        let updates = if since_seq <= state.seq && since_seq + 1 >= oldest {
+            sub.log.iter().filter(|u| u.seq > since_seq).cloned().collect()
+        } else {
+            debug!("Session {} can't replay from {} (log starts at {}), sending full state", session_id, since_seq, oldest);
+            vec![SequencedUpdate {
+                seq: state.seq,
+                update: SessionStateUpdate::FullState {
+                    state: Box::new(state.clone()),
+                },
+            }]
+        };
+
+        sub.subscribers.insert(client_id.clone());
+        info!("Client {} resumed session {} from seq {}", client_id, session_id, since_seq);
+        Some((updates, sub.tx.subscribe()))
+    }
+
+    /// Give a delta the session's next sequence number; called with the state lock held,
+    /// so subscribers get updates in the order of the changes
+    fn record(&self, state: &mut SessionState, update: SessionStateUpdate) {
+        state.seq += 1;
+        self.log_update(state, update);
+    }
+
+    /// Record the whole state, for changes that have no delta of their own
+    fn record_full_state(&self, state: &mut SessionState) {
+        state.seq += 1;
+        let update = SessionStateUpdate::FullState {
+            state: Box::new(state.clone()),
+        };
+        self.log_update(state, update);
+    }
+
+    fn record_queue(&self, state: &mut SessionState) {
+        let queued_prompts = state.queued_prompts.clone();
+        self.record(state, SessionStateUpdate::QueuedPromptsUpdated { queued_prompts });
+    }
+
+    /// Write an update to the store and the update log, and send it to subscribers
+    fn log_update(&self, state: &SessionState, update: SessionStateUpdate) {
+        let update = SequencedUpdate { seq: state.seq, update };
+        if let Some(ref store) = self.store {
+            store.append(state, &update);
+        }
+        if let Some(sub) = self.subscriptions.write().get_mut(&state.id) {
+            if sub.log.len() == UPDATE_LOG_SIZE {
+                sub.log.pop_front();
+            }
+            sub.log.push_back(update.clone());
             if !sub.subscribers.is_empty() {
-                // Ignore send errors (no subscribers is fine)
+                // Ignore send errors (no receivers is fine)
                 let _ = sub.tx.send(update);
             }
         }
     }
+
+    fn persist_snapshot(&self, state: &SessionState) {
+        if let Some(ref store) = self.store {
+            store.save(state);
+        }
+    }
 }
 
 impl Default for SessionStateManager {
@@ -383,4 +734,138 @@ mod tests {
         manager.remove_session(&"test".to_string());
         assert!(!manager.has_session(&"test".to_string()));
     }
+
+    #[test]
+    fn test_subscription_survives_reattach() {
+        let manager = SessionStateManager::new();
+        let id = "test".to_string();
+        manager.create_session(id.clone(), "/".to_string(), None, None);
+        let (_, mut rx) = manager.subscribe("client".to_string(), &id).unwrap();
+        manager.add_user_message(&id, "Hi".to_string(), None);
+
+        // Stopped and started again: the subscriber gets the new state, numbered after
+        // what it has seen
+        manager.remove_session(&id);
+        manager.create_session(id.clone(), "/".to_string(), None, None);
+        assert_eq!(rx.try_recv().unwrap().seq, 1);
+        let update = rx.try_recv().unwrap();
+        assert_eq!(update.seq, 2);
+        assert!(matches!(update.update, SessionStateUpdate::FullState { .. }));
+
+        manager.unsubscribe(&"client".to_string(), &id);
+        manager.delete_session(&id);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_replayed_updates_build_history() {
+        // session/load replays the conversation as session/update notifications
+        let manager = SessionStateManager::new();
+        let id = "loaded".to_string();
+        manager.create_session(id.clone(), "/".to_string(), None, None);
+
+        manager.apply_update(&id, SessionUpdate::UserMessageChunk {
+            content: ContentBlock::Text { text: "Hi".to_string() },
+        });
+        manager.apply_update(&id, SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Hello".to_string() },
+        });
+
+        let state = manager.get_state(&id).unwrap();
+        assert_eq!(state.chat_items.len(), 2);
+    }
+
+    #[test]
+    fn test_subscribe_since_replays_missed_updates() {
+        let manager = SessionStateManager::new();
+        let id = "seq".to_string();
+        manager.create_session(id.clone(), "/".to_string(), None, None);
+        let chunk = |text: &str| SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: text.to_string() },
+        };
+
+        manager.add_user_message(&id, "Hi".to_string(), None);
+        manager.apply_update(&id, chunk("Hel"));
+        manager.apply_update(&id, chunk("lo"));
+        assert_eq!(manager.get_state(&id).unwrap().seq, 3);
+
+        // Only the updates after `since_seq`, in order
+        let (updates, _rx) = manager.subscribe_since("client".to_string(), &id, 1).unwrap();
+        assert_eq!(updates.iter().map(|u| u.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(matches!(updates[1].update, SessionStateUpdate::MessageChunk { .. }));
+        let (updates, _rx) = manager.subscribe_since("client".to_string(), &id, 3).unwrap();
+        assert!(updates.is_empty());
+
+        // A gap beyond the log, or a sequence number the session never reached, gets the full state
+        for _ in 0..UPDATE_LOG_SIZE {
+            manager.apply_update(&id, chunk("."));
+        }
+        for since_seq in [1, 10_000] {
+            let (updates, _rx) = manager.subscribe_since("client".to_string(), &id, since_seq).unwrap();
+            assert_eq!(updates.len(), 1);
+            assert_eq!(updates[0].seq, 3 + UPDATE_LOG_SIZE as u64);
+            assert!(matches!(updates[0].update, SessionStateUpdate::FullState { .. }));
+        }
+        let (updates, _rx) = manager.subscribe_since("client".to_string(), &id, 3).unwrap();
+        assert_eq!(updates.len(), UPDATE_LOG_SIZE);
+    }
+
+    #[test]
+    fn test_stored_state_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("aero-work-sessions-{}", uuid::Uuid::new_v4()));
+        let id = "persisted".to_string();
+
+        let manager = SessionStateManager::with_store(SessionStore::new(&dir));
+        manager.create_session(id.clone(), "/project".to_string(), None, None);
+        manager.add_user_message(&id, "Hi".to_string(), Some("client-msg-1".to_string()));
+        manager.apply_update(&id, SessionUpdate::AgentMessageChunk {
+            content: ContentBlock::Text { text: "Hello".to_string() },
+        });
+        manager.set_dangerous_mode(&id, true);
+        let before = manager.get_state(&id).unwrap();
+        drop(manager);
+
+        // After a restart the session is inactive but its state is stored
+        let manager = SessionStateManager::with_store(SessionStore::new(&dir));
+        assert!(!manager.has_session(&id));
+        let stored = manager.stored_state(&id).unwrap();
+        assert_eq!(serde_json::to_value(&stored).unwrap(), serde_json::to_value(&before).unwrap());
+
+        manager.restore_session(&id, id.clone(), None, None).unwrap();
+        let restored = manager.get_state(&id).unwrap();
+        assert!(restored.is_dangerous_mode());
+        assert_eq!(restored.chat_items.len(), 2);
+
+        // Stopping keeps it stored, deleting removes it for good
+        manager.remove_session(&id);
+        assert!(manager.stored_state(&id).is_some());
+        manager.delete_session(&id);
+        drop(manager);
+        let manager = SessionStateManager::with_store(SessionStore::new(&dir));
+        assert!(manager.stored_state(&id).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_restore_after_restart_drops_queued_prompts() {
+        let dir = std::env::temp_dir().join(format!("aero-work-sessions-{}", uuid::Uuid::new_v4()));
+        let id = "queued".to_string();
+        let prompt = vec![ContentBlock::Text { text: "Later".to_string() }];
+
+        let manager = SessionStateManager::with_store(SessionStore::new(&dir));
+        manager.create_session(id.clone(), "/project".to_string(), None, None);
+        assert!(manager.start_turn_or_queue(&id, prompt.clone(), None).is_none());
+        assert!(manager.start_turn_or_queue(&id, prompt.clone(), None).is_some());
+        drop(manager);
+
+        // The turn that would have sent the prompt is gone, so nothing would send it
+        let manager = SessionStateManager::with_store(SessionStore::new(&dir));
+        assert_eq!(manager.stored_state(&id).unwrap().queued_prompts.len(), 1);
+        manager.restore_session(&id, id.clone(), None, None).unwrap();
+        assert!(manager.queued_prompts(&id).is_empty());
+        assert!(manager.start_turn_or_queue(&id, prompt, None).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
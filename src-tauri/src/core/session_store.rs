@@ -0,0 +1,402 @@
+//! Session Store Module
+//!
+//! Durable copy of the `SessionState` of every session, independent of the agent's
+//! own transcript. Each session has an append-only NDJSON log under
+//! `data_dir()/sessions`: a snapshot of the state followed by the deltas that were
+//! broadcast to clients, so replaying the log gives back exactly what clients saw.
+//! Logs are compacted into a single snapshot once they grow long.
+//!
+//! Callers only queue writes; a dedicated writer thread does the file I/O, so
+//! nothing blocks on the disk while holding the session state locks.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::acp::SessionId;
+
+use super::config::data_dir;
+use super::session_state::{SequencedUpdate, SessionState, SessionStateUpdate};
+
+/// Deltas written after a snapshot before the log is compacted
+const COMPACT_AFTER: usize = 1000;
+
+/// Where session logs are kept
+pub fn sessions_dir() -> PathBuf {
+    data_dir().join("sessions")
+}
+
+/// One line of a session log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum StoreRecord {
+    /// The whole state; replaces everything before it
+    Snapshot { state: Box<SessionState> },
+    /// A delta and when it was applied (milliseconds)
+    Update { update: Box<SequencedUpdate>, at: i64 },
+}
+
+/// Work queued for the writer thread
+enum WriteCommand {
+    Save(Box<SessionState>),
+    Append {
+        session_id: SessionId,
+        update: Box<SequencedUpdate>,
+        at: i64,
+    },
+    Remove(SessionId),
+    /// Answered once every command before it is done
+    Flush(mpsc::Sender<()>),
+}
+
+/// An open log and the number of deltas written since its snapshot
+struct SessionLog {
+    file: File,
+    updates: usize,
+}
+
+pub struct SessionStore {
+    dir: PathBuf,
+    /// Sessions this store has written a snapshot for; their deltas can be appended
+    logged: Mutex<HashSet<SessionId>>,
+    writer: Option<(mpsc::Sender<WriteCommand>, JoinHandle<()>)>,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let (tx, rx) = mpsc::channel();
+        let writer = {
+            let dir = dir.clone();
+            std::thread::Builder::new()
+                .name("session-store".to_string())
+                .spawn(move || run_writer(&dir, rx))
+                .map_err(|e| warn!("Failed to start session store writer: {}", e))
+                .ok()
+                .map(|handle| (tx, handle))
+        };
+        Self {
+            dir,
+            logged: Mutex::new(HashSet::new()),
+            writer,
+        }
+    }
+
+    /// Replace the log of a session with a snapshot of its state
+    pub fn save(&self, state: &SessionState) {
+        self.logged.lock().insert(state.id.clone());
+        self.send(WriteCommand::Save(Box::new(state.clone())));
+    }
+
+    /// Append a delta that was applied to `state` (its state afterwards);
+    /// failures are logged, never returned
+    pub fn append(&self, state: &SessionState, update: &SequencedUpdate) {
+        match update.update {
+            SessionStateUpdate::Noop => return,
+            SessionStateUpdate::FullState { .. } => return self.save(state),
+            _ => {}
+        }
+        if !self.logged.lock().contains(&state.id) {
+            // Nothing written by this store yet: start the log from the current state
+            return self.save(state);
+        }
+        self.send(WriteCommand::Append {
+            session_id: state.id.clone(),
+            update: Box::new(update.clone()),
+            at: state.updated_at,
+        });
+    }
+
+    /// Delete the log of a session
+    pub fn remove(&self, session_id: &SessionId) {
+        self.logged.lock().remove(session_id);
+        self.send(WriteCommand::Remove(session_id.clone()));
+    }
+
+    /// Wait until every write queued so far is on disk
+    pub fn flush(&self) {
+        let (tx, rx) = mpsc::channel();
+        self.send(WriteCommand::Flush(tx));
+        let _ = rx.recv();
+    }
+
+    /// Every stored session, rebuilt from its log
+    pub fn load_all(&self) -> Vec<SessionState> {
+        self.flush();
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ndjson"))
+            .filter_map(|path| match read_log(&path) {
+                Ok(Some(state)) => {
+                    // Logs written before names were hashed are moved to where
+                    // later writes for the session go
+                    let expected = log_path(&self.dir, &state.id);
+                    if path != expected {
+                        if let Err(e) = std::fs::rename(&path, &expected) {
+                            warn!("Failed to move session log {:?}: {}", path, e);
+                        }
+                    }
+                    Some(state)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("Failed to read session log {:?}: {}", path, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn send(&self, command: WriteCommand) {
+        match self.writer {
+            Some((ref tx, _)) => {
+                if tx.send(command).is_err() {
+                    warn!("Session store writer has stopped; write dropped");
+                }
+            }
+            None => run_command(&self.dir, &mut HashMap::new(), command),
+        }
+    }
+}
+
+impl Drop for SessionStore {
+    /// Let the writer finish what is queued
+    fn drop(&mut self) {
+        if let Some((tx, handle)) = self.writer.take() {
+            drop(tx);
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_writer(dir: &Path, commands: mpsc::Receiver<WriteCommand>) {
+    let mut logs = HashMap::new();
+    for command in commands {
+        run_command(dir, &mut logs, command);
+    }
+}
+
+fn run_command(dir: &Path, logs: &mut HashMap<SessionId, SessionLog>, command: WriteCommand) {
+    match command {
+        WriteCommand::Save(state) => save(dir, logs, state),
+        WriteCommand::Append { session_id, update, at } => {
+            if !logs.contains_key(&session_id) {
+                match OpenOptions::new().append(true).open(log_path(dir, &session_id)) {
+                    Ok(file) => {
+                        logs.insert(session_id.clone(), SessionLog { file, updates: 0 });
+                    }
+                    Err(e) => {
+                        warn!("Failed to open log of session {}: {}", session_id, e);
+                        return;
+                    }
+                }
+            }
+
+            let log = logs.get_mut(&session_id).expect("log was just opened");
+            let record = StoreRecord::Update { update, at };
+            match write_record(&mut log.file, &record) {
+                Ok(()) => log.updates += 1,
+                Err(e) => warn!("Failed to append to log of session {}: {}", session_id, e),
+            }
+
+            if log.updates >= COMPACT_AFTER {
+                debug!("Compacting log of session {}", session_id);
+                match read_log(&log_path(dir, &session_id)) {
+                    Ok(Some(state)) => save(dir, logs, Box::new(state)),
+                    Ok(None) => warn!("Log of session {} has no snapshot to compact", session_id),
+                    Err(e) => warn!("Failed to compact log of session {}: {}", session_id, e),
+                }
+            }
+        }
+        WriteCommand::Remove(session_id) => {
+            logs.remove(&session_id);
+            let path = log_path(dir, &session_id);
+            if path.exists() {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("Failed to delete {:?}: {}", path, e);
+                }
+            }
+        }
+        WriteCommand::Flush(done) => {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn save(dir: &Path, logs: &mut HashMap<SessionId, SessionLog>, state: Box<SessionState>) {
+    let session_id = state.id.clone();
+    logs.remove(&session_id);
+    match write_snapshot(dir, state) {
+        Ok(file) => {
+            logs.insert(session_id, SessionLog { file, updates: 0 });
+        }
+        Err(e) => warn!("Failed to save session {}: {}", session_id, e),
+    }
+}
+
+/// Write a snapshot to a new file and swap it in, so a crash never leaves half a log
+fn write_snapshot(dir: &Path, state: Box<SessionState>) -> std::io::Result<File> {
+    std::fs::create_dir_all(dir)?;
+    let path = log_path(dir, &state.id);
+    let tmp = path.with_extension("ndjson.tmp");
+    let mut file = File::create(&tmp)?;
+    write_record(&mut file, &StoreRecord::Snapshot { state })?;
+    file.sync_data()?;
+    std::fs::rename(&tmp, &path)?;
+    OpenOptions::new().append(true).open(&path)
+}
+
+/// Log file of a session in `dir`
+fn log_path(dir: &Path, session_id: &str) -> PathBuf {
+    // Session IDs come from the agent: hash them so every ID gets its own file
+    // and none can escape `dir`
+    let name: String = Sha256::digest(session_id.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    dir.join(format!("{}.ndjson", name))
+}
+
+fn write_record(file: &mut File, record: &StoreRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())
+}
+
+/// Replay a log; lines that don't parse (e.g. cut off by a crash) are skipped
+fn read_log(path: &Path) -> std::io::Result<Option<SessionState>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut state: Option<SessionState> = None;
+    for line in reader.lines() {
+        let line = line?;
+        match serde_json::from_str::<StoreRecord>(&line) {
+            Ok(StoreRecord::Snapshot { state: snapshot }) => {
+                let mut snapshot = *snapshot;
+                snapshot.rebuild_tool_call_index();
+                state = Some(snapshot);
+            }
+            Ok(StoreRecord::Update { update, at }) => {
+                if let Some(ref mut state) = state {
+                    state.apply_delta(&update.update, at);
+                    state.seq = update.seq;
+                }
+            }
+            Err(e) => debug!("Skipping unreadable line in {:?}: {}", path, e),
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acp::{ContentBlock, SessionUpdate, ToolCall, ToolCallStatus};
+
+    #[test]
+    fn test_log_replays_to_same_state() {
+        let dir = std::env::temp_dir().join(format!("aero-work-store-{}", uuid::Uuid::new_v4()));
+        let store = SessionStore::new(&dir);
+
+        let mut state = SessionState::new("s1".to_string(), "/project".to_string());
+        store.save(&state);
+
+        let append = |state: &mut SessionState, update: SessionStateUpdate| {
+            state.seq += 1;
+            store.append(state, &SequencedUpdate { seq: state.seq, update });
+        };
+        let update = state.add_user_message("Fix the bug".to_string(), Some("msg-1".to_string()));
+        append(&mut state, update);
+        for update in [
+            SessionUpdate::AgentMessageChunk {
+                content: ContentBlock::Text { text: "On it".to_string() },
+            },
+            SessionUpdate::AgentMessageChunk {
+                content: ContentBlock::Text { text: "!".to_string() },
+            },
+            SessionUpdate::ToolCall(ToolCall {
+                tool_call_id: "call-1".to_string(),
+                title: "Edit main.rs".to_string(),
+                kind: None,
+                status: Some(ToolCallStatus::Pending),
+                raw_input: None,
+                raw_output: None,
+                content: None,
+                locations: None,
+            }),
+        ] {
+            let delta = state.apply_update(&update);
+            append(&mut state, delta);
+        }
+        state.set_dangerous_mode(true);
+        append(&mut state, SessionStateUpdate::DangerousModeUpdated { dangerous_mode: true });
+        let update = state.add_steering("Leave main.rs alone".to_string(), Some("- Said: On it!".to_string()));
+        append(&mut state, update);
+
+        store.flush();
+        let loaded = SessionStore::new(&dir).load_all();
+        assert_eq!(loaded.len(), 1);
+        let loaded = &loaded[0];
+        assert_eq!(
+            serde_json::to_value(loaded).unwrap(),
+            serde_json::to_value(&state).unwrap()
+        );
+        assert!(loaded.get_tool_call(&"call-1".to_string()).is_some());
+
+        store.remove(&"s1".to_string());
+        store.flush();
+        assert!(SessionStore::new(&dir).load_all().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_long_log_compacted_to_snapshot() {
+        let dir = std::env::temp_dir().join(format!("aero-work-store-{}", uuid::Uuid::new_v4()));
+        let store = SessionStore::new(&dir);
+        let mut state = SessionState::new("s1".to_string(), "/project".to_string());
+        store.save(&state);
+        for _ in 0..COMPACT_AFTER + 1 {
+            let update = state.apply_update(&SessionUpdate::AgentMessageChunk {
+                content: ContentBlock::Text { text: ".".to_string() },
+            });
+            state.seq += 1;
+            store.append(&state, &SequencedUpdate { seq: state.seq, update });
+        }
+        store.flush();
+
+        let log = std::fs::read_to_string(log_path(&dir, &state.id)).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        let loaded = SessionStore::new(&dir).load_all();
+        assert_eq!(
+            serde_json::to_value(&loaded[0]).unwrap(),
+            serde_json::to_value(&state).unwrap()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ids_differing_in_punctuation_get_own_logs() {
+        let dir = std::env::temp_dir().join(format!("aero-work-store-{}", uuid::Uuid::new_v4()));
+        let store = SessionStore::new(&dir);
+        for id in ["a/b", "a_b", "a.b", "../a_b"] {
+            store.save(&SessionState::new(id.to_string(), "/project".to_string()));
+        }
+        drop(store);
+
+        let mut ids: Vec<_> = SessionStore::new(&dir).load_all().into_iter().map(|state| state.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["../a_b", "a.b", "a/b", "a_b"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -2,15 +2,29 @@
 use std::sync::Arc;
 
 #[cfg(not(target_os = "android"))]
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot};
 
 #[cfg(not(target_os = "android"))]
-use crate::acp::{AcpClient, PermissionRequest, SessionId, SessionNotification};
+use crate::acp::{
+    PermissionOutcome, PermissionRequest, RequestId, SessionId, SessionNotification, TaggedAgentRequest,
+};
 #[cfg(not(target_os = "android"))]
-use crate::core::session_registry::SessionRegistry;
+use crate::core::agent::{AgentEvent, AgentManager};
+#[cfg(not(target_os = "android"))]
+use crate::core::config::{AgentConfig, ConfigManager};
+#[cfg(not(target_os = "android"))]
+use crate::core::agent_fs::AgentFileSystem;
+#[cfg(not(target_os = "android"))]
+use crate::core::agent_log::{AgentLog, AgentLogEntry};
+#[cfg(not(target_os = "android"))]
+use crate::core::agent_terminal::AgentTerminalManager;
+#[cfg(not(target_os = "android"))]
+use crate::core::session_registry::{branches_path, SessionRegistry};
 #[cfg(not(target_os = "android"))]
 use crate::core::session_state_manager::SessionStateManager;
 #[cfg(not(target_os = "android"))]
+use crate::core::session_store::{sessions_dir, SessionStore};
+#[cfg(not(target_os = "android"))]
 use crate::core::terminal::{TerminalManager, TerminalOutput};
 
 /// Notification for session activation changes
@@ -23,11 +37,27 @@ pub struct SessionActivated {
 /// Desktop AppState - full featured with agent, terminal, sessions
 #[cfg(not(target_os = "android"))]
 pub struct AppState {
-    pub client: Arc<RwLock<Option<AcpClient>>>,
+    /// Running ACP agents and the session -> agent routing
+    pub agent_manager: Arc<AgentManager>,
     pub notification_tx: mpsc::Sender<SessionNotification>,
     pub notification_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<SessionNotification>>>>,
+    /// Requests to apply every queued notification, answered once they are applied
+    pub notification_flush_tx: mpsc::Sender<oneshot::Sender<()>>,
+    pub notification_flush_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<oneshot::Sender<()>>>>>,
     pub permission_tx: mpsc::Sender<PermissionRequest>,
     pub permission_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<PermissionRequest>>>>,
+    /// Requests from the agent served by the client (fs/*)
+    pub agent_request_tx: mpsc::Sender<TaggedAgentRequest>,
+    pub agent_request_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<TaggedAgentRequest>>>>,
+    /// Agent crashes reported by the agent supervisor
+    pub agent_event_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<AgentEvent>>>>,
+    /// Agent stderr and lifecycle events
+    pub agent_log: Arc<AgentLog>,
+    pub agent_log_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<AgentLogEntry>>>>,
+    /// File system access for the agent (editor buffers, write log)
+    pub agent_fs: Arc<AgentFileSystem>,
+    /// Terminals created by the agent via ACP `terminal/*` requests
+    pub agent_terminal_manager: Arc<AgentTerminalManager>,
     pub terminal_manager: Arc<TerminalManager>,
     pub terminal_output_rx: Arc<parking_lot::RwLock<Option<mpsc::Receiver<TerminalOutput>>>>,
     /// Session registry for managing session metadata across clients
@@ -48,17 +78,47 @@ pub struct AppState {
 #[cfg(not(target_os = "android"))]
 impl AppState {
     pub fn new() -> Self {
+        let mut state = Self::with_agent_config(&ConfigManager::new().config().agent);
+        // Session states survive restarts; `with_agent_config` alone keeps them in memory
+        state.session_state_manager = Arc::new(SessionStateManager::with_store(SessionStore::new(sessions_dir())));
+        state.session_registry = Arc::new(SessionRegistry::with_branches_file(branches_path()));
+        state
+    }
+
+    /// State with the given agents instead of the ones from the config file
+    pub fn with_agent_config(agent_config: &AgentConfig) -> Self {
         let (notification_tx, notification_rx) = mpsc::channel(100);
+        let (notification_flush_tx, notification_flush_rx) = mpsc::channel(100);
         let (permission_tx, permission_rx) = mpsc::channel(100);
+        let (agent_request_tx, agent_request_rx) = mpsc::channel(100);
+        let (agent_event_tx, agent_event_rx) = mpsc::channel(100);
+        let (agent_log_tx, agent_log_rx) = mpsc::channel(100);
+        let agent_log = Arc::new(AgentLog::new(agent_log_tx));
         let (terminal_output_tx, terminal_output_rx) = mpsc::channel(100);
         let (session_activated_tx, session_activated_rx) = mpsc::channel(100);
 
         Self {
-            client: Arc::new(RwLock::new(None)),
+            agent_manager: Arc::new(AgentManager::new(
+                agent_config,
+                notification_tx.clone(),
+                permission_tx.clone(),
+                agent_request_tx.clone(),
+                agent_event_tx,
+                agent_log.clone(),
+            )),
             notification_tx,
             notification_rx: Arc::new(parking_lot::RwLock::new(Some(notification_rx))),
+            notification_flush_tx,
+            notification_flush_rx: Arc::new(parking_lot::RwLock::new(Some(notification_flush_rx))),
             permission_tx,
             permission_rx: Arc::new(parking_lot::RwLock::new(Some(permission_rx))),
+            agent_request_tx,
+            agent_request_rx: Arc::new(parking_lot::RwLock::new(Some(agent_request_rx))),
+            agent_event_rx: Arc::new(parking_lot::RwLock::new(Some(agent_event_rx))),
+            agent_log,
+            agent_log_rx: Arc::new(parking_lot::RwLock::new(Some(agent_log_rx))),
+            agent_fs: Arc::new(AgentFileSystem::new()),
+            agent_terminal_manager: Arc::new(AgentTerminalManager::new(terminal_output_tx.clone())),
             terminal_manager: Arc::new(TerminalManager::new(terminal_output_tx)),
             terminal_output_rx: Arc::new(parking_lot::RwLock::new(Some(terminal_output_rx))),
             session_registry: Arc::new(SessionRegistry::new()),
@@ -71,6 +131,59 @@ impl AppState {
         }
     }
 
+    /// Wait until the session notifications received so far are applied to the session
+    /// states, e.g. the history an agent replays before it answers `session/load`
+    pub async fn flush_notifications(&self) {
+        // Nobody answers until the WebSocket event forwarding takes the receiver
+        if self.notification_flush_rx.read().is_some() {
+            return;
+        }
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.notification_flush_tx.send(done_tx).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+
+    /// Switch a session to one of the models it offers; both the Tauri command and the
+    /// WebSocket handler go through here so unknown model IDs never reach the agent
+    pub async fn set_session_model(&self, session_id: &SessionId, model_id: &str) -> Result<(), String> {
+        if !self.session_state_manager.has_model(session_id, model_id) {
+            return Err(format!("Model {} is not available for session {}", model_id, session_id));
+        }
+
+        self.agent_manager
+            .set_session_model(session_id, model_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // ACP has no notification for model changes, so update the state ourselves
+        self.session_state_manager.set_current_model(session_id, model_id);
+        Ok(())
+    }
+
+    /// Answer the pending permission request of a session (the latest one without a
+    /// session ID); the answer goes to the agent instance that asked
+    pub async fn respond_permission(
+        &self,
+        session_id: Option<&str>,
+        request_id: RequestId,
+        outcome: PermissionOutcome,
+    ) -> Result<(), String> {
+        let request = session_id
+            .and_then(|id| self.session_state_manager.get_pending_permission(&id.to_string()))
+            .or_else(|| {
+                self.get_pending_permission()
+                    .filter(|request| session_id.is_none_or(|id| id == request.session_id))
+            })
+            .filter(|request| request.request_id == request_id)
+            .ok_or_else(|| format!("No pending permission request {}", request_id))?;
+
+        self.agent_manager
+            .respond_permission(&request.agent_id, request_id, outcome)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
     /// Set the pending permission request
     pub fn set_pending_permission(&self, request: Option<PermissionRequest>) {
         let mut pending = self.pending_permission.write();
@@ -221,9 +221,9 @@ async fn find_available_port(preferred_port: u16) -> Result<(tokio::net::TcpList
 pub fn run() {
     use tauri::Manager;
     use crate::commands::{
-        cancel_session, connect_agent, create_directory, create_file, create_session, delete_path,
-        disconnect_agent, initialize_agent, list_directory, read_file, rename_path, respond_permission,
-        send_prompt, set_session_mode, write_file,
+        authenticate_agent, cancel_session, connect_agent, create_directory, create_file, create_session, delete_path,
+        disconnect_agent, get_agent_log, initialize_agent, list_agents, list_directory, read_file, rename_path, respond_permission,
+        send_prompt, set_session_mode, set_session_model, write_file,
         resume_session, fork_session, list_sessions, get_session_info,
         create_terminal, write_terminal, resize_terminal, kill_terminal, list_terminals,
     };
@@ -246,10 +246,14 @@ pub fn run() {
             connect_agent,
             disconnect_agent,
             initialize_agent,
+            authenticate_agent,
+            list_agents,
+            get_agent_log,
             create_session,
             send_prompt,
             cancel_session,
             set_session_mode,
+            set_session_model,
             respond_permission,
             // Session management
             resume_session,
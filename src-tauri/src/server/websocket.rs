@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use axum::{
     extract::{
@@ -11,11 +12,19 @@ use axum::{
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::acp::{AcpError, InitializeResponse, NewSessionResponse, PermissionOutcome, PromptResponse, SessionId};
-use crate::core::{AgentManager, AppState, ClientId, SessionState};
+use crate::acp::{
+    AcpError, AgentRequest, ContentBlock, CreateTerminalResponse, InitializeResponse, McpServer,
+    NewSessionResponse, PermissionOutcome, PromptResponse, ReadTextFileResponse, SessionId, SessionModeState,
+    SessionModelState, StopReason, TaggedAgentRequest, AUTH_REQUIRED_ERROR_CODE,
+};
+use crate::core::{
+    AgentEvent, AppState, ClientId, QueuedPrompt, SequencedUpdate, SessionState, SessionStateUpdate,
+    SharedSessionStateManager, TerminalCaller,
+};
 
 #[derive(Debug, Deserialize)]
 struct JsonRpcRequest {
@@ -116,7 +125,9 @@ impl WebSocketServer {
         let addr = format!("0.0.0.0:{}", preferred_port);
         match tokio::net::TcpListener::bind(&addr).await {
             Ok(listener) => {
-                return Ok((listener, preferred_port));
+                // Port 0 asks the OS for any free port
+                let port = listener.local_addr()?.port();
+                return Ok((listener, port));
             }
             Err(e) => {
                 warn!("Port {} is occupied: {}, trying alternative ports...", preferred_port, e);
@@ -145,30 +156,29 @@ impl WebSocketServer {
     }
 
     async fn start_event_forwarding(state: Arc<AppState>, event_tx: broadcast::Sender<String>) {
-        // Forward session notifications and apply to SessionStateManager
+        // Apply session notifications to SessionStateManager; subscribed clients get the
+        // resulting deltas as `session/state_update` (see `forward_session_updates`)
         let notification_rx = state.notification_rx.write().take();
-        if let Some(mut rx) = notification_rx {
-            let tx = event_tx.clone();
+        let flush_rx = state.notification_flush_rx.write().take();
+        if let (Some(mut rx), Some(mut flush_rx)) = (notification_rx, flush_rx) {
             let session_state_manager = state.session_state_manager.clone();
             tokio::spawn(async move {
-                while let Some(notification) = rx.recv().await {
-                    // Apply update to SessionStateManager (single source of truth)
-                    session_state_manager.apply_update(
-                        &notification.session_id,
-                        notification.update.clone(),
-                    );
-
-                    // Forward to all clients (backward compatibility)
-                    let msg = JsonRpcNotification {
-                        jsonrpc: "2.0".to_string(),
-                        method: "session/update".to_string(),
-                        params: serde_json::json!({
-                            "sessionId": notification.session_id,
-                            "update": notification.update,
-                        }),
-                    };
-                    if let Ok(json) = serde_json::to_string(&msg) {
-                        let _ = tx.send(json);
+                loop {
+                    tokio::select! {
+                        notification = rx.recv() => match notification {
+                            Some(notification) => {
+                                session_state_manager.apply_update(&notification.session_id, notification.update);
+                            }
+                            None => break,
+                        },
+                        Some(done) = flush_rx.recv() => {
+                            // What is queued now arrived before the flush was asked for
+                            for _ in 0..rx.len() {
+                                let Ok(notification) = rx.try_recv() else { break };
+                                session_state_manager.apply_update(&notification.session_id, notification.update);
+                            }
+                            let _ = done.send(());
+                        }
                     }
                 }
             });
@@ -192,10 +202,10 @@ impl WebSocketServer {
                                 option_id: allow_option.option_id.clone(),
                             };
                             // Respond to the permission request
-                            let client_guard = state_clone.client.read().await;
-                            if let Some(ref client) = *client_guard {
-                                let _ = client.respond_permission(request.request_id.clone(), outcome).await;
-                            }
+                            let _ = state_clone
+                                .agent_manager
+                                .respond_permission(&request.agent_id, request.request_id.clone(), outcome)
+                                .await;
                             continue; // Skip forwarding to clients
                         }
                     }
@@ -239,6 +249,56 @@ impl WebSocketServer {
             });
         }
 
+        // Serve requests from the agent (fs/*) and broadcast file changes
+        let agent_request_rx = state.agent_request_rx.write().take();
+        if let Some(mut rx) = agent_request_rx {
+            let tx = event_tx.clone();
+            let state_clone = state.clone();
+            tokio::spawn(async move {
+                while let Some(request) = rx.recv().await {
+                    let state = state_clone.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        handle_agent_request(&state, &tx, request).await;
+                    });
+                }
+            });
+        }
+
+        // Restart crashed agents and recover their sessions
+        let agent_event_rx = state.agent_event_rx.write().take();
+        if let Some(mut rx) = agent_event_rx {
+            let tx = event_tx.clone();
+            let state_clone = state.clone();
+            tokio::spawn(async move {
+                while let Some(event) = rx.recv().await {
+                    let state = state_clone.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        handle_agent_event(&state, &tx, event).await;
+                    });
+                }
+            });
+        }
+
+        // Stream agent stderr and lifecycle events
+        let agent_log_rx = state.agent_log_rx.write().take();
+        if let Some(mut rx) = agent_log_rx {
+            let tx = event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(entry) = rx.recv().await {
+                    let msg = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "agent/log".to_string(),
+                        params: serde_json::to_value(&entry).unwrap_or_default(),
+                    };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        let _ = tx.send(json);
+                    }
+                }
+            });
+        }
+
         // Forward terminal output
         let terminal_rx = state.terminal_output_rx.write().take();
         if let Some(mut rx) = terminal_rx {
@@ -287,7 +347,10 @@ struct ServerState {
 /// Per-client state for WebSocket connections
 struct ClientState {
     client_id: ClientId,
-    subscribed_sessions: std::sync::RwLock<std::collections::HashSet<SessionId>>,
+    /// Task forwarding the updates of each subscribed session
+    subscribed_sessions: std::sync::RwLock<HashMap<SessionId, JoinHandle<()>>>,
+    /// Messages to send to this client
+    ws_tx: mpsc::Sender<String>,
     /// Current working directory (project) for this client
     /// Used to filter broadcasts - clients only receive updates for their current project
     current_cwd: std::sync::RwLock<Option<String>>,
@@ -297,6 +360,283 @@ async fn health_handler() -> &'static str {
     "OK"
 }
 
+/// Serve a request from the agent and send the response back to it
+async fn handle_agent_request(
+    state: &Arc<AppState>,
+    event_tx: &broadcast::Sender<String>,
+    tagged: TaggedAgentRequest,
+) {
+    let TaggedAgentRequest { agent_id, request } = tagged;
+    let request_id = request.request_id().clone();
+    // An agent may only act for its own sessions
+    let owner = state.agent_manager.instance_for_session(request.session_id());
+    let result = if owner.as_deref() == Some(agent_id.as_str()) {
+        serve_agent_request(state, event_tx, &agent_id, &request).await
+    } else {
+        Err(format!("Session {} does not belong to agent {}", request.session_id(), agent_id))
+    };
+
+    // Answer on the connection the request came in on
+    let manager = &state.agent_manager;
+    let sent = match result {
+        Ok(value) => manager.respond(&agent_id, request_id, value).await,
+        Err(message) => {
+            warn!("Agent request failed for session {}: {}", request.session_id(), message);
+            manager.respond_error(&agent_id, request_id, -32603, message).await
+        }
+    };
+    if let Err(e) = sent {
+        warn!("Failed to respond to agent request: {}", e);
+    }
+}
+
+/// Result of an agent request, served in the sandbox of the agent instance that sent it
+async fn serve_agent_request(
+    state: &Arc<AppState>,
+    event_tx: &broadcast::Sender<String>,
+    agent_id: &str,
+    request: &AgentRequest,
+) -> Result<serde_json::Value, String> {
+    match request {
+        AgentRequest::ReadTextFile { params, .. } => state
+            .agent_fs
+            .read_text_file(params)
+            .await
+            .map(|content| serde_json::to_value(ReadTextFileResponse { content }).unwrap_or_default()),
+        AgentRequest::WriteTextFile { params, .. } => {
+            let sandbox = state.agent_manager.sandbox_for_instance(agent_id);
+            match state.agent_fs.write_text_file(params, sandbox.as_ref()).await {
+                Ok(record) => {
+                    // Let clients reload open editors and refresh the file tree
+                    let msg = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "fs/file_changed".to_string(),
+                        params: serde_json::to_value(&record).unwrap_or_default(),
+                    };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        let _ = event_tx.send(json);
+                    }
+                    Ok(serde_json::json!({}))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        AgentRequest::CreateTerminal { params, .. } => {
+            // Agents usually omit cwd; run in the session's project directory
+            let mut params = params.clone();
+            if params.cwd.is_none() {
+                params.cwd = state
+                    .session_registry
+                    .get_session_info(&params.session_id)
+                    .map(|info| info.cwd)
+                    .filter(|cwd| !cwd.is_empty());
+            }
+
+            let sandbox = state.agent_manager.sandbox_for_instance(agent_id);
+            match state.agent_terminal_manager.create_terminal(agent_id, &params, sandbox.as_ref()) {
+                Ok(info) => {
+                    let msg = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "agent_terminal/created".to_string(),
+                        params: serde_json::to_value(&info).unwrap_or_default(),
+                    };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        let _ = event_tx.send(json);
+                    }
+
+                    // Announce the exit status once the command finishes
+                    let state = state.clone();
+                    let tx = event_tx.clone();
+                    let terminal_id = info.id.clone();
+                    let session_id = info.session_id.clone();
+                    tokio::spawn(async move {
+                        if let Ok(exit_status) =
+                            state.agent_terminal_manager.wait_for_exit(TerminalCaller::Client, &terminal_id).await
+                        {
+                            let msg = JsonRpcNotification {
+                                jsonrpc: "2.0".to_string(),
+                                method: "agent_terminal/exited".to_string(),
+                                params: serde_json::json!({
+                                    "terminalId": terminal_id,
+                                    "sessionId": session_id,
+                                    "exitStatus": exit_status,
+                                }),
+                            };
+                            if let Ok(json) = serde_json::to_string(&msg) {
+                                let _ = tx.send(json);
+                            }
+                        }
+                    });
+
+                    Ok(serde_json::to_value(CreateTerminalResponse { terminal_id: info.id })
+                        .unwrap_or_default())
+                }
+                Err(e) => Err(e),
+            }
+        }
+        AgentRequest::TerminalOutput { params, .. } => state
+            .agent_terminal_manager
+            .output(TerminalCaller::agent(agent_id, params), &params.terminal_id)
+            .map(|output| serde_json::to_value(output).unwrap_or_default()),
+        AgentRequest::WaitForTerminalExit { params, .. } => state
+            .agent_terminal_manager
+            .wait_for_exit(TerminalCaller::agent(agent_id, params), &params.terminal_id)
+            .await
+            .map(|status| serde_json::to_value(status).unwrap_or_default()),
+        AgentRequest::KillTerminal { params, .. } => state
+            .agent_terminal_manager
+            .kill_terminal(TerminalCaller::agent(agent_id, params), &params.terminal_id)
+            .map(|_| serde_json::json!({})),
+        AgentRequest::ReleaseTerminal { params, .. } => state
+            .agent_terminal_manager
+            .release_terminal(TerminalCaller::agent(agent_id, params), &params.terminal_id)
+            .map(|_| serde_json::json!({})),
+    }
+}
+
+/// React to agent lifecycle events: restart a crashed agent and resume its active sessions,
+/// or ask clients to log in
+async fn handle_agent_event(
+    state: &Arc<AppState>,
+    event_tx: &broadcast::Sender<String>,
+    event: AgentEvent,
+) {
+    match event {
+        AgentEvent::Crashed { agent_id, status } => {
+            let msg = JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "agent/crashed".to_string(),
+                params: serde_json::json!({ "agentId": agent_id, "status": status }),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                let _ = event_tx.send(json);
+            }
+
+            let manager = &state.agent_manager;
+            let sessions: Vec<_> = state
+                .session_registry
+                .get_active_sessions()
+                .into_iter()
+                .filter(|s| manager.instance_for_session(&s.id).as_deref() == Some(agent_id.as_str()))
+                .collect();
+
+            // Commands the dead process started can no longer be waited for or released
+            state.agent_terminal_manager.release_agent(&agent_id);
+
+            // Permission requests from the dead process can no longer be answered
+            for session in &sessions {
+                state.session_state_manager.set_pending_permission(&session.id, None);
+                if state
+                    .get_pending_permission()
+                    .map(|p| p.session_id == session.id)
+                    .unwrap_or(false)
+                {
+                    state.set_pending_permission(None);
+                }
+            }
+
+            match manager.restart_agent(&agent_id).await {
+                Ok(_) => {
+                    let mut recovered = Vec::new();
+                    let mut failed = Vec::new();
+                    for session in &sessions {
+                        match manager.recover_session(&session.id, &session.cwd).await {
+                            Ok(_) => {
+                                state.session_registry.update_status(&session.id, crate::core::SessionStatus::Idle);
+                                recovered.push(session.id.clone());
+                            }
+                            Err(e) => {
+                                warn!("Failed to recover session {}: {}", session.id, e);
+                                state.session_registry.update_status(&session.id, crate::core::SessionStatus::Error);
+                                failed.push(session.id.clone());
+                            }
+                        }
+                    }
+                    info!(
+                        "ACP agent {} restarted, recovered {} of {} sessions",
+                        agent_id,
+                        recovered.len(),
+                        sessions.len()
+                    );
+
+                    let msg = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "agent/restarted".to_string(),
+                        params: serde_json::json!({
+                            "agentId": agent_id,
+                            "recoveredSessions": recovered,
+                            "failedSessions": failed,
+                        }),
+                    };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        let _ = event_tx.send(json);
+                    }
+                }
+                Err(e) => {
+                    warn!("Giving up restarting ACP agent {}: {}", agent_id, e);
+                    for session in &sessions {
+                        state.session_registry.update_status(&session.id, crate::core::SessionStatus::Error);
+                    }
+
+                    let msg = JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "agent/restart_failed".to_string(),
+                        params: serde_json::json!({ "agentId": agent_id, "error": e.to_string() }),
+                    };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        let _ = event_tx.send(json);
+                    }
+                }
+            }
+
+            if !sessions.is_empty() {
+                broadcast_sessions_update(state, event_tx, None);
+            }
+        }
+        AgentEvent::AuthRequired { agent_id, auth_methods } => {
+            // Any client (desktop or phone) can answer with `authenticate`
+            let msg = JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "agent/auth_required".to_string(),
+                params: serde_json::json!({ "agentId": agent_id, "authMethods": auth_methods }),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                let _ = event_tx.send(json);
+            }
+        }
+    }
+}
+
+/// Parse the `content` parameter of `send_prompt`
+/// Accepts plain text or an array of ACP content blocks (images, resources, links)
+fn parse_prompt_content(params: &serde_json::Value) -> Result<Vec<ContentBlock>, String> {
+    parse_prompt_param(params, "content")
+}
+
+/// Parse a prompt parameter: a string or an array of content blocks
+fn parse_prompt_param(params: &serde_json::Value, name: &str) -> Result<Vec<ContentBlock>, String> {
+    let content = params.get(name).ok_or_else(|| format!("Missing {} parameter", name))?;
+    if let Some(text) = content.as_str() {
+        return Ok(vec![ContentBlock::Text { text: text.to_string() }]);
+    }
+
+    let prompt: Vec<ContentBlock> = serde_json::from_value(content.clone())
+        .map_err(|e| format!("Invalid {} parameter: {}", name, e))?;
+    if prompt.is_empty() {
+        return Err("Prompt content is empty".to_string());
+    }
+    Ok(prompt)
+}
+
+/// Parse the optional `mcpServers` parameter of session commands
+fn parse_mcp_servers(params: &serde_json::Value) -> Result<Vec<McpServer>, String> {
+    match params.get("mcpServers") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid mcpServers parameter: {}", e)),
+        _ => Ok(Vec::new()),
+    }
+}
+
 /// Broadcast session list update to all clients
 /// Called when session status changes (running/idle/pending) or sessions are added/removed
 fn broadcast_sessions_update(
@@ -325,11 +665,15 @@ async fn ws_handler(
 async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
     let (mut sender, mut receiver) = socket.split();
 
+    // Channel for sending messages to WebSocket
+    let (ws_tx, mut ws_rx) = mpsc::channel::<String>(100);
+
     // Generate unique client ID for this connection
     let client_id = Uuid::new_v4().to_string();
     let client_state = Arc::new(ClientState {
         client_id: client_id.clone(),
-        subscribed_sessions: std::sync::RwLock::new(std::collections::HashSet::new()),
+        subscribed_sessions: std::sync::RwLock::new(HashMap::new()),
+        ws_tx: ws_tx.clone(),
         current_cwd: std::sync::RwLock::new(None),
     });
 
@@ -338,9 +682,6 @@ async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
     // Subscribe to broadcast events
     let mut event_rx = state.event_tx.subscribe();
 
-    // Channel for sending messages to WebSocket
-    let (ws_tx, mut ws_rx) = mpsc::channel::<String>(100);
-
     // NOTE: Don't push pending permission here - client will discover it
     // from SessionState.pendingPermission when it fetches session state
 
@@ -356,25 +697,6 @@ async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
 
                 // Filter based on message type and client's current cwd
                 let should_send = match method {
-                    Some("session/update") | Some("session/state_update") => {
-                        // Extract sessionId from the notification
-                        if let Some(session_id) = parsed.get("params")
-                            .and_then(|p| p.get("sessionId"))
-                            .and_then(|s| s.as_str()) {
-                            // Get session's cwd
-                            if let Some(session_info) = state_clone.app_state.session_registry.get_session_info(session_id) {
-                                let session_cwd = session_info.cwd.clone();
-                                // Get client's current cwd
-                                let client_cwd = client_state_clone.current_cwd.read().unwrap().clone();
-                                // Only send if cwds match (or client has no cwd set)
-                                client_cwd.is_none() || client_cwd.as_deref() == Some(&session_cwd)
-                            } else {
-                                false // Session not found, don't send
-                            }
-                        } else {
-                            false // No sessionId, don't send
-                        }
-                    }
                     Some("permission/request") => {
                         // Extract sessionId from permission request
                         if let Some(session_id) = parsed.get("params")
@@ -463,7 +785,8 @@ async fn handle_socket(socket: WebSocket, state: Arc<ServerState>) {
     // Clean up: unsubscribe from all sessions
     {
         let subscribed = client_state.subscribed_sessions.read().unwrap();
-        for session_id in subscribed.iter() {
+        for (session_id, forwarder) in subscribed.iter() {
+            forwarder.abort();
             state.app_state.session_state_manager.unsubscribe(&client_state.client_id, session_id);
         }
     }
@@ -490,11 +813,23 @@ async fn handle_message(text: &str, state: &Arc<ServerState>, client_state: &Arc
 
     match result {
         Ok(value) => serde_json::to_string(&JsonRpcResponse::success(request.id, value)),
-        Err(e) => serde_json::to_string(&JsonRpcResponse::error(request.id, -32603, e)),
+        Err(e) => serde_json::to_string(&JsonRpcResponse::error(request.id, error_code(&e), e)),
     }
     .unwrap_or_default()
 }
 
+/// JSON-RPC error code for a failed request
+/// Refusals until login keep ACP's code, so clients know to send them again after
+/// `agent/authenticated`.
+fn error_code(message: &str) -> i32 {
+    let auth_required = AcpError::AuthenticationRequired(String::new()).to_string();
+    if message.starts_with(auth_required.trim_end()) {
+        AUTH_REQUIRED_ERROR_CODE
+    } else {
+        -32603
+    }
+}
+
 async fn dispatch_method(
     method: &str,
     params: Option<serde_json::Value>,
@@ -513,7 +848,12 @@ async fn dispatch_method(
             let auto_resume = params.get("autoResume")
                 .and_then(|v| v.as_bool())
                 .unwrap_or(true);
-            let session_state = subscribe_session_handler(state, client_state, session_id, auto_resume, event_tx).await?;
+            // Resuming clients get the updates they missed instead of the whole state
+            if let Some(since_seq) = params.get("sinceSeq").and_then(|v| v.as_u64()) {
+                let updates = resume_subscription_handler(state, client_state, session_id, since_seq, auto_resume).await?;
+                return Ok(serde_json::json!({ "sessionId": session_id, "updates": updates }));
+            }
+            let session_state = subscribe_session_handler(state, client_state, session_id, auto_resume).await?;
             serde_json::to_value(session_state).map_err(|e| e.to_string())
         }
         "unsubscribe_session" => {
@@ -533,6 +873,13 @@ async fn dispatch_method(
             let session_state = get_session_state_handler(state, session_id, auto_resume).await?;
             serde_json::to_value(session_state).map_err(|e| e.to_string())
         }
+        "replay_recording" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let session_state = crate::core::replay_recording(session_id)?;
+            serde_json::to_value(session_state).map_err(|e| e.to_string())
+        }
         "get_client_id" => {
             Ok(serde_json::json!({ "clientId": client_state.client_id }))
         }
@@ -554,26 +901,9 @@ async fn dispatch_method(
             let enabled = params.get("enabled")
                 .and_then(|v| v.as_bool())
                 .ok_or("Missing enabled parameter")?;
+            // Subscribers are told by the manager
             let success = state.session_state_manager.set_dangerous_mode(&session_id.to_string(), enabled);
 
-            // Broadcast update to all WebSocket clients
-            if success {
-                let msg = JsonRpcNotification {
-                    jsonrpc: "2.0".to_string(),
-                    method: "session/state_update".to_string(),
-                    params: serde_json::json!({
-                        "sessionId": session_id,
-                        "update": {
-                            "updateType": "dangerous_mode_updated",
-                            "dangerousMode": enabled
-                        }
-                    }),
-                };
-                if let Ok(json) = serde_json::to_string(&msg) {
-                    let _ = event_tx.send(json);
-                }
-            }
-
             Ok(serde_json::json!({ "success": success, "dangerousMode": enabled }))
         }
         "get_dangerous_mode" => {
@@ -594,9 +924,68 @@ async fn dispatch_method(
             Ok(serde_json::Value::Null)
         }
         "initialize" => {
-            let response = initialize_handler(state).await?;
+            let agent_id = params.get("agentId").and_then(|v| v.as_str());
+            let response = initialize_handler(state, agent_id).await?;
             serde_json::to_value(response).map_err(|e| e.to_string())
         }
+        "authenticate" => {
+            let agent_id = params.get("agentId")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| state.agent_manager.default_agent_id());
+            let method_id = params.get("methodId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing methodId parameter")?;
+            state.agent_manager.authenticate(&agent_id, method_id).await.map_err(|e| e.to_string())?;
+
+            let msg = JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "agent/authenticated".to_string(),
+                params: serde_json::json!({ "agentId": agent_id }),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                let _ = event_tx.send(json);
+            }
+            Ok(serde_json::Value::Null)
+        }
+        "list_agents" => {
+            Ok(serde_json::json!({
+                "agents": state.agent_manager.list_agents(),
+                "defaultAgentId": state.agent_manager.default_agent_id(),
+            }))
+        }
+        "set_default_agent" => {
+            let agent_id = params.get("agentId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing agentId parameter")?;
+            state.agent_manager.set_default_agent(agent_id).map_err(|e| e.to_string())?;
+            Ok(serde_json::Value::Null)
+        }
+        "stop_agent" => {
+            let agent_id = params.get("agentId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing agentId parameter")?;
+            state.agent_manager.stop_agent(agent_id).await.map_err(|e| e.to_string())?;
+            state.agent_terminal_manager.release_agent(agent_id);
+
+            // The agent's sessions are no longer active
+            for session in state.session_registry.get_active_sessions() {
+                if session.agent_id.as_deref() == Some(agent_id) {
+                    state.session_state_manager.remove_session(&session.id);
+                    state.session_registry.unregister_session(&session.id);
+                }
+            }
+            broadcast_sessions_update(state, event_tx, None);
+            Ok(serde_json::Value::Null)
+        }
+        "get_agent_log" => {
+            let agent_id = params.get("agentId").and_then(|v| v.as_str());
+            let since_seq = params.get("sinceSeq").and_then(|v| v.as_u64());
+            let limit = params.get("limit").and_then(|v| v.as_u64()).map(|v| v as usize);
+            Ok(serde_json::json!({
+                "entries": state.agent_log.entries(agent_id, since_seq, limit),
+            }))
+        }
         "respond_permission" => {
             let request_id = params.get("requestId").cloned().unwrap_or_default();
             let session_id = params.get("sessionId")
@@ -605,6 +994,8 @@ async fn dispatch_method(
             let outcome: PermissionOutcome = serde_json::from_value(
                 params.get("outcome").cloned().unwrap_or_default()
             ).map_err(|e| e.to_string())?;
+            respond_permission_handler(state, session_id.as_deref(), request_id.clone(), outcome).await?;
+
             // Clear pending permission from session state
             if let Some(ref sid) = session_id {
                 state.session_state_manager.set_pending_permission(sid, None);
@@ -617,7 +1008,6 @@ async fn dispatch_method(
             }
             // Also clear global state for backward compatibility
             state.set_pending_permission(None);
-            respond_permission_handler(state, request_id.clone(), outcome).await?;
 
             // Broadcast permission resolved to all clients so they can close their dialogs
             let msg = JsonRpcNotification {
@@ -640,21 +1030,67 @@ async fn dispatch_method(
             let cwd = params.get("cwd")
                 .and_then(|v| v.as_str())
                 .ok_or("Missing cwd parameter")?;
-            let response = create_session_handler(state, cwd, event_tx).await?;
+            let agent_id = params.get("agentId").and_then(|v| v.as_str());
+            let mcp_servers = parse_mcp_servers(&params)?;
+            let response = create_session_handler(state, agent_id, cwd, mcp_servers, event_tx).await?;
             serde_json::to_value(response).map_err(|e| e.to_string())
         }
         "send_prompt" => {
             let session_id = params.get("sessionId")
                 .and_then(|v| v.as_str())
                 .ok_or("Missing sessionId parameter")?;
-            let content = params.get("content")
+            let prompt = parse_prompt_content(&params)?;
+            let message_id = params.get("messageId")
                 .and_then(|v| v.as_str())
-                .ok_or("Missing content parameter")?;
+                .map(|s| s.to_string());
+            send_prompt_handler(state, session_id, prompt, message_id, None, event_tx).await
+        }
+        "steer_session" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let prompt = parse_prompt_content(&params)?;
             let message_id = params.get("messageId")
                 .and_then(|v| v.as_str())
                 .map(|s| s.to_string());
-            let response = send_prompt_handler(state, session_id, content, message_id, event_tx).await?;
-            serde_json::to_value(response).map_err(|e| e.to_string())
+            steer_session_handler(state, session_id, prompt, message_id, event_tx).await
+        }
+        "update_queued_prompt" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let queued_prompt_id = params.get("queuedPromptId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing queuedPromptId parameter")?;
+            let prompt = parse_prompt_content(&params)?;
+            let queue = state.session_state_manager
+                .update_queued_prompt(&session_id.to_string(), queued_prompt_id, prompt)?;
+            Ok(serde_json::json!({ "queuedPrompts": queue }))
+        }
+        "reorder_queued_prompts" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let ids: Vec<String> = params.get("queuedPromptIds")
+                .cloned()
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(|e| format!("Invalid queuedPromptIds parameter: {}", e))?
+                .ok_or("Missing queuedPromptIds parameter")?;
+            let queue = state.session_state_manager
+                .reorder_queued_prompts(&session_id.to_string(), &ids)?;
+            Ok(serde_json::json!({ "queuedPrompts": queue }))
+        }
+        "cancel_queued_prompt" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let queued_prompt_id = params.get("queuedPromptId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing queuedPromptId parameter")?;
+            let queue = state.session_state_manager
+                .cancel_queued_prompt(&session_id.to_string(), queued_prompt_id)?;
+            Ok(serde_json::json!({ "queuedPrompts": queue }))
         }
         "cancel_session" => {
             let session_id = params.get("sessionId")
@@ -680,6 +1116,16 @@ async fn dispatch_method(
             set_session_mode_handler(state, session_id, mode_id).await?;
             Ok(serde_json::Value::Null)
         }
+        "set_session_model" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let model_id = params.get("modelId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing modelId parameter")?;
+            set_session_model_handler(state, session_id, model_id).await?;
+            Ok(serde_json::Value::Null)
+        }
         "list_sessions" => {
             let cwd = params.get("cwd").and_then(|v| v.as_str());
             let limit = params.get("limit").and_then(|v| v.as_u64()).map(|v| v as usize);
@@ -694,7 +1140,12 @@ async fn dispatch_method(
             let cwd = params.get("cwd")
                 .and_then(|v| v.as_str())
                 .ok_or("Missing cwd parameter")?;
-            let response = resume_session_handler(state, session_id, cwd).await?;
+            // Sessions from disk run on the default agent unless one is named
+            if let Some(agent_id) = params.get("agentId").and_then(|v| v.as_str()) {
+                state.agent_manager.bind_session(session_id, agent_id);
+            }
+            let mcp_servers = parse_mcp_servers(&params)?;
+            let response = resume_session_handler(state, session_id, cwd, mcp_servers).await?;
             serde_json::to_value(response).map_err(|e| e.to_string())
         }
         "fork_session" => {
@@ -704,9 +1155,20 @@ async fn dispatch_method(
             let cwd = params.get("cwd")
                 .and_then(|v| v.as_str())
                 .ok_or("Missing cwd parameter")?;
-            let response = fork_session_handler(state, session_id, cwd).await?;
+            let mcp_servers = parse_mcp_servers(&params)?;
+            let response = fork_session_handler(state, session_id, cwd, mcp_servers).await?;
             serde_json::to_value(response).map_err(|e| e.to_string())
         }
+        "rewind_session" => {
+            let session_id = params.get("sessionId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing sessionId parameter")?;
+            let message_id = params.get("messageId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing messageId parameter")?;
+            let prompt = parse_prompt_param(&params, "newContent")?;
+            rewind_session_handler(state, session_id, message_id, prompt, event_tx).await
+        }
         "get_session_info" => {
             let session_id = params.get("sessionId")
                 .and_then(|v| v.as_str())
@@ -817,22 +1279,65 @@ async fn dispatch_method(
             serde_json::to_value(info).map_err(|e| e.to_string())
         }
 
-        // Terminal commands
-        "create_terminal" => {
-            let cwd = params.get("cwd").and_then(|v| v.as_str());
-            let cols = params.get("cols").and_then(|v| v.as_u64()).unwrap_or(80) as u16;
-            let rows = params.get("rows").and_then(|v| v.as_u64()).unwrap_or(24) as u16;
-            let terminal_id = create_terminal_handler(state, cwd, cols, rows).await?;
-            Ok(serde_json::Value::String(terminal_id))
-        }
-        "write_terminal" => {
-            let terminal_id = params.get("terminalId")
+        // Editor buffers served to the agent and agent write history
+        "set_editor_buffer" => {
+            let path = params.get("path")
                 .and_then(|v| v.as_str())
-                .ok_or("Missing terminalId parameter")?;
-            let data = params.get("data")
+                .ok_or("Missing path parameter")?;
+            let content = params.get("content")
                 .and_then(|v| v.as_str())
-                .ok_or("Missing data parameter")?;
-            write_terminal_handler(state, terminal_id, data).await?;
+                .ok_or("Missing content parameter")?;
+            state.agent_fs.set_buffer(path.to_string(), content.to_string());
+            Ok(serde_json::Value::Null)
+        }
+        "clear_editor_buffer" => {
+            let path = params.get("path")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing path parameter")?;
+            state.agent_fs.clear_buffer(path);
+            Ok(serde_json::Value::Null)
+        }
+        "list_agent_file_writes" => {
+            let session_id = params.get("sessionId").and_then(|v| v.as_str());
+            let writes = state.agent_fs.list_writes(session_id);
+            Ok(serde_json::json!({ "writes": writes }))
+        }
+        "list_agent_terminals" => {
+            let session_id = params.get("sessionId").and_then(|v| v.as_str());
+            let terminals = state.agent_terminal_manager.list_terminals(session_id);
+            Ok(serde_json::json!({ "terminals": terminals }))
+        }
+        "get_agent_terminal_output" => {
+            let terminal_id = params.get("terminalId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing terminalId parameter")?;
+            let output = state.agent_terminal_manager.output(TerminalCaller::Client, terminal_id)?;
+            Ok(serde_json::to_value(output).unwrap_or_default())
+        }
+        "kill_agent_terminal" => {
+            let terminal_id = params.get("terminalId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing terminalId parameter")?;
+            state.agent_terminal_manager.kill_terminal(TerminalCaller::Client, terminal_id)?;
+            Ok(serde_json::Value::Null)
+        }
+
+        // Terminal commands
+        "create_terminal" => {
+            let cwd = params.get("cwd").and_then(|v| v.as_str());
+            let cols = params.get("cols").and_then(|v| v.as_u64()).unwrap_or(80) as u16;
+            let rows = params.get("rows").and_then(|v| v.as_u64()).unwrap_or(24) as u16;
+            let terminal_id = create_terminal_handler(state, cwd, cols, rows).await?;
+            Ok(serde_json::Value::String(terminal_id))
+        }
+        "write_terminal" => {
+            let terminal_id = params.get("terminalId")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing terminalId parameter")?;
+            let data = params.get("data")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing data parameter")?;
+            write_terminal_handler(state, terminal_id, data).await?;
             Ok(serde_json::Value::Null)
         }
         "resize_terminal" => {
@@ -1006,7 +1511,6 @@ async fn subscribe_session_handler(
     client_state: &Arc<ClientState>,
     session_id: &str,
     auto_resume: bool,
-    event_tx: &broadcast::Sender<String>,
 ) -> Result<SessionState, String> {
     let session_id = session_id.to_string();
 
@@ -1016,12 +1520,8 @@ async fn subscribe_session_handler(
         &session_id,
     );
 
-    if let Some((session_state, _rx)) = result {
-        // Track subscription in client state
-        {
-            let mut subscribed = client_state.subscribed_sessions.write().unwrap();
-            subscribed.insert(session_id.clone());
-        }
+    if let Some((session_state, rx)) = result {
+        track_subscription(state, client_state, session_id.clone(), session_state.seq, rx);
 
         info!(
             "Client {} subscribed to session {}",
@@ -1036,7 +1536,9 @@ async fn subscribe_session_handler(
 
     // Session not in memory - try auto-resume if enabled
     if !auto_resume {
-        return Err(format!("Session not found: {}", session_id));
+        // Inactive sessions are shown as last seen
+        return state.session_state_manager.stored_state(&session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id));
     }
 
     info!("Session {} not in memory, attempting auto-resume...", session_id);
@@ -1047,97 +1549,142 @@ async fn subscribe_session_handler(
 
     let cwd = session_info.cwd.clone();
 
-    // Ensure ACP agent is running before resuming session
-    ensure_agent_connected(state).await?;
 
-    // Resume the session via ACP agent
-    let manager = AgentManager::new(state.client.clone());
-    let response = manager.resume_session(&session_id, &cwd).await
+    // Restore the session via ACP agent (JSONL history is loaded in the background)
+    let response = restore_session(state, &session_id, &cwd, vec![], true).await
         .map_err(|e| format!("Failed to resume session: {}", e))?;
 
     info!("Auto-resumed session: {} -> {}", session_id, response.session_id);
 
-    // Register in session registry
-    state.session_registry.register_session(
-        response.session_id.clone(),
-        cwd.clone(),
-        response.modes.clone(),
-        response.models.clone(),
-    );
-
-    // Create EMPTY session state first (for immediate response)
-    let initial_state = state.session_state_manager.create_session(
-        response.session_id.clone(),
-        cwd.clone(),
-        response.modes,
-        response.models,
-    );
-
     // Set as current active session
     state.set_current_session(Some(response.session_id.clone())).await;
 
     // Subscribe client to the session
-    let result = state.session_state_manager.subscribe(
+    let (initial_state, rx) = state.session_state_manager.subscribe(
         client_state.client_id.clone(),
         &response.session_id,
-    );
-
-    if result.is_none() {
-        return Err(format!("Failed to subscribe to resumed session: {}", response.session_id));
-    }
-
-    // Track subscription in client state
-    {
-        let mut subscribed = client_state.subscribed_sessions.write().unwrap();
-        subscribed.insert(response.session_id.clone());
-    }
+    )
+    .ok_or_else(|| format!("Failed to subscribe to resumed session: {}", response.session_id))?;
+    track_subscription(state, client_state, response.session_id.clone(), initial_state.seq, rx);
 
     info!(
-        "Client {} subscribed to auto-resumed session {} (loading history in background)",
+        "Client {} subscribed to auto-resumed session {}",
         client_state.client_id, response.session_id
     );
 
-    // Spawn background task to load history
-    let state_clone = state.clone();
-    let original_session_id = session_id.clone();
-    let new_session_id = response.session_id.clone();
-    let event_tx_clone = event_tx.clone();
-
-    tokio::spawn(async move {
-        // Load historical chat items from JSONL file
-        let chat_items = state_clone.session_registry.load_chat_items(&original_session_id);
+    Ok(initial_state)
+}
 
-        if chat_items.is_empty() {
-            debug!("No historical chat items to load for session {}", original_session_id);
-            return;
-        }
+/// Subscribe to a session from update `since_seq` on
+/// Returns the missed updates, or a single `FullState` update when they can't be replayed
+async fn resume_subscription_handler(
+    state: &Arc<AppState>,
+    client_state: &Arc<ClientState>,
+    session_id: &str,
+    since_seq: u64,
+    auto_resume: bool,
+) -> Result<Vec<SequencedUpdate>, String> {
+    let session_key = session_id.to_string();
+    let result = state.session_state_manager.subscribe_since(
+        client_state.client_id.clone(),
+        &session_key,
+        since_seq,
+    );
 
-        info!("Background: Loaded {} historical chat items for session {}", chat_items.len(), original_session_id);
+    if let Some((updates, rx)) = result {
+        let last_seq = updates.last().map(|u| u.seq).unwrap_or(since_seq);
+        track_subscription(state, client_state, session_key, last_seq, rx);
+        info!(
+            "Client {} resumed session {} with {} updates",
+            client_state.client_id, session_id, updates.len()
+        );
+        return Ok(updates);
+    }
 
-        // Update session state with history
-        state_clone.session_state_manager.load_history(&new_session_id, chat_items);
+    // Not active: subscribe as usual and send the state it ends up with
+    let session_state = subscribe_session_handler(state, client_state, session_id, auto_resume).await?;
+    Ok(vec![SequencedUpdate {
+        seq: session_state.seq,
+        update: SessionStateUpdate::FullState {
+            state: Box::new(session_state),
+        },
+    }])
+}
+
+/// Forward the updates of a subscribed session to the client
+/// `last_seq` is the sequence number of the last update the client has. A client that
+/// subscribes again keeps its running forwarder, which sends every update after the ones it
+/// already sent; the client skips those it got in the state.
+fn track_subscription(
+    state: &Arc<AppState>,
+    client_state: &Arc<ClientState>,
+    session_id: SessionId,
+    last_seq: u64,
+    rx: broadcast::Receiver<SequencedUpdate>,
+) {
+    let mut subscribed = client_state.subscribed_sessions.write().unwrap();
+    if subscribed.get(&session_id).is_some_and(|forwarder| !forwarder.is_finished()) {
+        return;
+    }
+    let forwarder = tokio::spawn(forward_session_updates(
+        state.session_state_manager.clone(),
+        client_state.client_id.clone(),
+        client_state.ws_tx.clone(),
+        session_id.clone(),
+        last_seq,
+        rx,
+    ));
+    subscribed.insert(session_id, forwarder);
+}
+
+/// Send a session's updates to one client as `session/state_update` notifications, in order
+/// A client that falls behind the broadcast channel is caught up from the update log.
+async fn forward_session_updates(
+    manager: SharedSessionStateManager,
+    client_id: ClientId,
+    ws_tx: mpsc::Sender<String>,
+    session_id: SessionId,
+    mut last_seq: u64,
+    mut rx: broadcast::Receiver<SequencedUpdate>,
+) {
+    loop {
+        let updates = match rx.recv().await {
+            Ok(update) => vec![update],
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                debug!("Client {} missed {} updates of session {}, catching up", client_id, missed, session_id);
+                match manager.subscribe_since(client_id.clone(), &session_id, last_seq) {
+                    Some((updates, new_rx)) => {
+                        rx = new_rx;
+                        updates
+                    }
+                    None => return,
+                }
+            }
+            // The session was deleted
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
 
-        // Broadcast full state update to all subscribers
-        if let Some(updated_state) = state_clone.session_state_manager.get_state(&new_session_id) {
+        for update in updates {
+            // Catching up can repeat updates that were already sent
+            if update.seq <= last_seq {
+                continue;
+            }
+            last_seq = update.seq;
             let msg = JsonRpcNotification {
                 jsonrpc: "2.0".to_string(),
                 method: "session/state_update".to_string(),
                 params: serde_json::json!({
-                    "sessionId": new_session_id,
-                    "update": {
-                        "updateType": "full_state",
-                        "state": updated_state
-                    }
+                    "sessionId": session_id,
+                    "update": update,
                 }),
             };
             if let Ok(json) = serde_json::to_string(&msg) {
-                let _ = event_tx_clone.send(json);
+                if ws_tx.send(json).await.is_err() {
+                    return;
+                }
             }
-            info!("Background: Broadcasted full state for session {}", new_session_id);
         }
-    });
-
-    Ok(initial_state)
+    }
 }
 
 fn unsubscribe_session_handler(
@@ -1150,10 +1697,9 @@ fn unsubscribe_session_handler(
     // Unsubscribe from session
     state.session_state_manager.unsubscribe(&client_state.client_id, &session_id);
 
-    // Remove from client tracking
-    {
-        let mut subscribed = client_state.subscribed_sessions.write().unwrap();
-        subscribed.remove(&session_id);
+    // Stop forwarding its updates
+    if let Some(forwarder) = client_state.subscribed_sessions.write().unwrap().remove(&session_id) {
+        forwarder.abort();
     }
 
     debug!(
@@ -1177,7 +1723,9 @@ async fn get_session_state_handler(
 
     // Session not in memory - try auto-resume if enabled
     if !auto_resume {
-        return Err(format!("Session not found: {}", session_id));
+        // Inactive sessions are shown as last seen
+        return state.session_state_manager.stored_state(&session_id_str)
+            .ok_or_else(|| format!("Session not found: {}", session_id));
     }
 
     info!("Session {} not in memory, attempting auto-resume for get_state...", session_id);
@@ -1188,37 +1736,13 @@ async fn get_session_state_handler(
 
     let cwd = session_info.cwd.clone();
 
-    // Ensure ACP agent is running before resuming session
-    ensure_agent_connected(state).await?;
 
-    // Resume the session via ACP agent
-    let manager = AgentManager::new(state.client.clone());
-    let response = manager.resume_session(session_id, &cwd).await
+    // Restore the session via ACP agent, with its history
+    let response = restore_session(state, session_id, &cwd, vec![], false).await
         .map_err(|e| format!("Failed to resume session: {}", e))?;
 
     info!("Auto-resumed session for get_state: {} -> {}", session_id, response.session_id);
 
-    // Register in session registry
-    state.session_registry.register_session(
-        response.session_id.clone(),
-        cwd.clone(),
-        response.modes.clone(),
-        response.models.clone(),
-    );
-
-    // Load historical chat items from JSONL file
-    let chat_items = state.session_registry.load_chat_items(session_id);
-    info!("Loaded {} historical chat items for session {}", chat_items.len(), session_id);
-
-    // Create session state with historical chat items
-    state.session_state_manager.create_session_with_history(
-        response.session_id.clone(),
-        cwd,
-        response.modes,
-        response.models,
-        chat_items,
-    );
-
     // Set as current active session
     state.set_current_session(Some(response.session_id.clone())).await;
 
@@ -1227,209 +1751,28 @@ async fn get_session_state_handler(
         .ok_or_else(|| format!("Failed to get state for resumed session: {}", response.session_id))
 }
 
-/// Bundled agent paths configuration (shared runtime approach)
-#[cfg(feature = "bundled-agent")]
-struct BundledAgentPaths {
-    /// Path to the Bun runtime binary
-    bun_runtime: Option<String>,
-    /// Path to the ACP adapter JS bundle (claude-code-agent.js)
-    acp_agent_js: Option<String>,
-    /// Path to the Claude Code CLI wrapper script (claude-code-cli)
-    claude_cli_wrapper: Option<String>,
-}
-
-/// Find bundled agent files (shared Bun runtime + JS bundles).
-#[cfg(feature = "bundled-agent")]
-fn find_bundled_agents() -> BundledAgentPaths {
-    let mut paths = BundledAgentPaths {
-        bun_runtime: None,
-        acp_agent_js: None,
-        claude_cli_wrapper: None,
-    };
-
-    if let Ok(exe_path) = std::env::current_exe() {
-        let exe_dir = exe_path.parent().unwrap_or(std::path::Path::new("."));
-
-        // Possible locations for bundled files
-        let candidate_dirs = [
-            // macOS .app bundle: Contents/MacOS/../Resources/
-            exe_dir.join("../Resources"),
-            // Next to executable
-            exe_dir.to_path_buf(),
-            // In resources subdirectory (for development)
-            exe_dir.join("resources"),
-            // From project root during development
-            exe_dir.join("../resources"),
-        ];
-
-        for dir in &candidate_dirs {
-            // Look for Bun runtime
-            if paths.bun_runtime.is_none() {
-                let bun_path = dir.join("bun-runtime");
-                if let Ok(canonical) = bun_path.canonicalize() {
-                    if canonical.exists() && canonical.is_file() {
-                        info!("Found bundled Bun runtime at: {:?}", canonical);
-                        paths.bun_runtime = Some(canonical.to_string_lossy().to_string());
-                    }
-                }
-            }
-
-            // Look for ACP agent JS bundle
-            if paths.acp_agent_js.is_none() {
-                let agent_path = dir.join("claude-code-agent.js");
-                if let Ok(canonical) = agent_path.canonicalize() {
-                    if canonical.exists() && canonical.is_file() {
-                        info!("Found bundled ACP agent JS at: {:?}", canonical);
-                        paths.acp_agent_js = Some(canonical.to_string_lossy().to_string());
-                    }
-                }
-            }
-
-            // Look for Claude Code CLI wrapper script
-            if paths.claude_cli_wrapper.is_none() {
-                let cli_path = dir.join("claude-code-cli");
-                if let Ok(canonical) = cli_path.canonicalize() {
-                    if canonical.exists() && canonical.is_file() {
-                        info!("Found bundled Claude CLI wrapper at: {:?}", canonical);
-                        paths.claude_cli_wrapper = Some(canonical.to_string_lossy().to_string());
-                    }
-                }
-            }
-
-            // If all found, no need to continue
-            if paths.bun_runtime.is_some() && paths.acp_agent_js.is_some() && paths.claude_cli_wrapper.is_some() {
-                break;
-            }
-        }
-    }
-
-    paths
-}
-
-/// Find the agent command to use and any environment variables needed.
-///
-/// When `bundled-agent` feature is enabled:
-/// - Uses bundled bun-runtime + claude-code-agent.js
-/// - Sets CLAUDE_CODE_EXECUTABLE to bundled claude-code-cli wrapper
-///
-/// When `bundled-agent` feature is disabled (default):
-/// - Uses npx @zed-industries/claude-code-acp directly
-/// - Requires Node.js/npx to be installed on the system
-fn find_agent_command() -> (String, Vec<String>, Option<Vec<(String, String)>>) {
-    #[cfg(feature = "bundled-agent")]
-    {
-        let bundled = find_bundled_agents();
-
-        // If we have both bun runtime and ACP agent JS, use bundled approach
-        if let (Some(bun_path), Some(acp_js_path)) = (bundled.bun_runtime.clone(), bundled.acp_agent_js) {
-            let mut env_vars = Vec::new();
-
-            // If we have bundled Claude CLI wrapper, set it as the executable path
-            if let Some(cli_wrapper_path) = bundled.claude_cli_wrapper {
-                // The wrapper script is a single executable file that the SDK can check exists
-                env_vars.push(("CLAUDE_CODE_EXECUTABLE".to_string(), cli_wrapper_path));
-            }
-
-            let env_vars_opt = if env_vars.is_empty() { None } else { Some(env_vars) };
-            info!("Using bundled agent: {} {}", bun_path, acp_js_path);
-            return (bun_path, vec![acp_js_path], env_vars_opt);
-        }
-
-        // Bundled feature enabled but files not found - warn and fallback
-        warn!("bundled-agent feature enabled but bundled files not found, falling back to npx");
-    }
-
-    // Use npx (default mode, or fallback when bundled files not found)
-    info!("Using npx @zed-industries/claude-code-acp");
-    ("npx".to_string(), vec!["@zed-industries/claude-code-acp".to_string()], None)
-}
-
-/// Ensure ACP agent is running, start if not connected
-/// This is called lazily when creating/resuming/forking sessions
-async fn ensure_agent_connected(state: &Arc<AppState>) -> Result<(), String> {
-    use crate::acp::AcpClient;
-    use crate::core::ModelConfig;
-
-    // Check if already connected
-    {
-        let guard = state.client.read().await;
-        if let Some(ref client) = *guard {
-            if client.is_connected() {
-                return Ok(());
-            }
-        }
-    }
-
-    // Not connected, create new connection
-    info!("Starting ACP agent (lazy initialization)...");
-    let notification_tx = state.notification_tx.clone();
-    let permission_tx = state.permission_tx.clone();
-
-    // Log active provider (config is synced to ~/.claude/settings.json when user saves)
-    let model_config = ModelConfig::load().unwrap_or_default();
-    info!("Active provider: {}", model_config.active_provider);
-
-    let mut client = AcpClient::new(notification_tx, permission_tx);
-
-    // Try to find bundled agent first, fallback to npx
-    let (command, args, env_vars) = find_agent_command();
-    info!("Using agent command: {} {:?}", command, args);
-
-    client
-        .connect(&command, &args.iter().map(|s| s.as_str()).collect::<Vec<_>>(), env_vars)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    // Initialize the agent
-    let init_response = client.initialize().await.map_err(|e| e.to_string())?;
-    info!("ACP agent initialized: {:?}", init_response.agent_info);
-
-    {
-        let mut guard = state.client.write().await;
-        *guard = Some(client);
-    }
-
-    info!("ACP agent started and ready");
-    Ok(())
-}
-
-async fn connect_handler(state: &Arc<AppState>) -> Result<(), String> {
-    // connect is now a no-op, ACP agent is started lazily when needed
+async fn connect_handler(_state: &Arc<AppState>) -> Result<(), String> {
+    // connect is a no-op, ACP agents are started lazily when needed
     info!("WebSocket: Client connected (ACP agent will start when session is created/resumed)");
-    let manager = AgentManager::new(state.client.clone());
-    let notification_tx = state.notification_tx.clone();
-    let permission_tx = state.permission_tx.clone();
-
-    manager
-        .connect(notification_tx, permission_tx)
-        .await
-        .map_err(|e: AcpError| e.to_string())?;
-
     Ok(())
 }
 
 async fn disconnect_handler(state: &Arc<AppState>) -> Result<(), String> {
     info!("WebSocket: Disconnecting from ACP agent...");
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
     manager.disconnect().await.map_err(|e: AcpError| e.to_string())?;
     info!("WebSocket: Disconnected from ACP agent");
     Ok(())
 }
 
-async fn initialize_handler(state: &Arc<AppState>) -> Result<InitializeResponse, String> {
-    // Initialize is now a no-op since we return cached info
-    // Real initialization happens lazily in ensure_agent_connected
+async fn initialize_handler(state: &Arc<AppState>, agent_id: Option<&str>) -> Result<InitializeResponse, String> {
+    // Initialize is a no-op since we return cached info
+    // Real initialization happens lazily in AgentManager::start_agent
     info!("WebSocket: Initialize called (agent will start when session is created/resumed)");
 
-    // Check if agent is already connected and return its info
-    {
-        let guard = state.client.read().await;
-        if let Some(ref client) = *guard {
-            if client.is_connected() {
-                // Agent already running, get its info
-                return client.initialize().await.map_err(|e: AcpError| e.to_string());
-            }
-        }
+    // Agent already running, return its info
+    if let Some(response) = state.agent_manager.initialize_response(agent_id) {
+        return Ok(response);
     }
 
     // Agent not running yet, return empty response
@@ -1444,22 +1787,26 @@ async fn initialize_handler(state: &Arc<AppState>) -> Result<InitializeResponse,
 
 async fn respond_permission_handler(
     state: &Arc<AppState>,
+    session_id: Option<&str>,
     request_id: serde_json::Value,
     outcome: PermissionOutcome,
 ) -> Result<(), String> {
     info!("WebSocket: Responding to permission request id={:?}", request_id);
-    let manager = AgentManager::new(state.client.clone());
-    manager.respond_permission(request_id, outcome).await.map_err(|e: AcpError| e.to_string())
+    state.respond_permission(session_id, request_id, outcome).await
 }
 
-async fn create_session_handler(state: &Arc<AppState>, cwd: &str, event_tx: &broadcast::Sender<String>) -> Result<NewSessionResponse, String> {
+async fn create_session_handler(
+    state: &Arc<AppState>,
+    agent_id: Option<&str>,
+    cwd: &str,
+    mcp_servers: Vec<McpServer>,
+    event_tx: &broadcast::Sender<String>,
+) -> Result<NewSessionResponse, String> {
     info!("WebSocket: Creating new session in {}", cwd);
 
-    // Ensure ACP agent is running before creating session
-    ensure_agent_connected(state).await?;
 
-    let manager = AgentManager::new(state.client.clone());
-    let response = manager.create_session(cwd).await.map_err(|e: AcpError| e.to_string())?;
+    let manager = &state.agent_manager;
+    let response = manager.create_session(agent_id, cwd, mcp_servers).await.map_err(|e: AcpError| e.to_string())?;
 
     // Register session in the registry
     state.session_registry.register_session(
@@ -1468,6 +1815,9 @@ async fn create_session_handler(state: &Arc<AppState>, cwd: &str, event_tx: &bro
         response.modes.clone(),
         response.models.clone(),
     );
+    if let Some(agent_id) = state.agent_manager.agent_for_session(&response.session_id) {
+        state.session_registry.set_agent_id(&response.session_id, agent_id);
+    }
 
     // Create session state in SessionStateManager (single source of truth)
     state.session_state_manager.create_session(
@@ -1487,8 +1837,199 @@ async fn create_session_handler(state: &Arc<AppState>, cwd: &str, event_tx: &bro
     Ok(response)
 }
 
-async fn send_prompt_handler(state: &Arc<AppState>, session_id: &str, content: &str, message_id: Option<String>, event_tx: &broadcast::Sender<String>) -> Result<PromptResponse, String> {
+/// Send a prompt, or queue it if the session is running a turn
+/// Returns the `PromptResponse`, or `{ queued, queuedPromptId }` for a queued prompt.
+/// Prompts queued during a turn are sent one after another once it ends.
+/// `context` goes to the agent with the prompt, as in `run_prompt_turn`.
+async fn send_prompt_handler(
+    state: &Arc<AppState>,
+    session_id: &str,
+    prompt: Vec<ContentBlock>,
+    message_id: Option<String>,
+    context: Option<String>,
+    event_tx: &broadcast::Sender<String>,
+) -> Result<serde_json::Value, String> {
+    let session_key = session_id.to_string();
+    // A queued prompt is sent later, when there is nobody to tell it was refused
+    state
+        .agent_manager
+        .check_prompt_content(session_id, &prompt)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(queued) = state
+        .session_state_manager
+        .start_turn_or_queue(&session_key, prompt.clone(), message_id.clone())
+    {
+        return Ok(serde_json::json!({ "queued": true, "queuedPromptId": queued.id }));
+    }
+
+    let result = run_prompt_turn(state, session_id, prompt, message_id, context, event_tx).await;
+
+    if let Some(next) = state.session_state_manager.finish_turn(&session_key) {
+        let state = state.clone();
+        let event_tx = event_tx.clone();
+        let stop_reason = result.as_ref().ok().map(|r| r.stop_reason.clone());
+        tokio::spawn(async move {
+            drain_prompt_queue(&state, &session_key, next, stop_reason, &event_tx).await;
+        });
+    }
+
+    let response = result?;
+    serde_json::to_value(response).map_err(|e| e.to_string())
+}
+
+/// Send queued prompts in order until the queue is empty
+/// `stop_reason` is how the turn before `first` ended.
+async fn drain_prompt_queue(
+    state: &Arc<AppState>,
+    session_id: &str,
+    first: QueuedPrompt,
+    mut stop_reason: Option<StopReason>,
+    event_tx: &broadcast::Sender<String>,
+) {
+    let session_key = session_id.to_string();
+    let mut next = Some(first);
+    while let Some(queued) = next {
+        let context = if queued.steer {
+            Some(announce_steering(state, session_id, &queued.prompt, stop_reason.as_ref()))
+        } else {
+            None
+        };
+
+        info!("WebSocket: Sending queued prompt {} to session {}", queued.id, session_id);
+        let message_id = queued.message_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        stop_reason = match run_prompt_turn(state, session_id, queued.prompt, Some(message_id.clone()), context, event_tx).await {
+            Ok(response) => Some(response.stop_reason),
+            Err(e) => {
+                // Nobody waits for the response of a queued prompt; tell the subscribers
+                warn!("Queued prompt {} for session {} failed: {}", queued.id, session_id, e);
+                let sent = state.session_state_manager.has_message(&session_key, &message_id);
+                state
+                    .session_state_manager
+                    .report_prompt_failure(&session_key, sent.then_some(message_id), e);
+                None
+            }
+        };
+        next = state.session_state_manager.finish_turn(&session_key);
+    }
+}
+
+/// Interrupt the running turn with a correction
+/// The turn is cancelled and the correction jumps the prompt queue; once the agent stops
+/// the correction is sent with a summary of the interrupted work. An idle session just
+/// gets the correction as a prompt.
+async fn steer_session_handler(
+    state: &Arc<AppState>,
+    session_id: &str,
+    prompt: Vec<ContentBlock>,
+    message_id: Option<String>,
+    event_tx: &broadcast::Sender<String>,
+) -> Result<serde_json::Value, String> {
+    let session_key = session_id.to_string();
+    if !state.session_state_manager.is_turn_running(&session_key) {
+        return send_prompt_handler(state, session_id, prompt, message_id, None, event_tx).await;
+    }
+    // Refused before the running turn is cancelled for it
+    state
+        .agent_manager
+        .check_prompt_content(session_id, &prompt)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Cancelled before the correction is queued: a turn ending on its own in between
+    // would otherwise send the correction, and the cancel would stop it instead
+    info!("WebSocket: Steering session {}", session_id);
+    state.agent_manager.cancel(session_id).await.map_err(|e| e.to_string())?;
+    let Some(queued) = state
+        .session_state_manager
+        .queue_steering(&session_key, prompt.clone(), message_id.clone())
+    else {
+        return send_prompt_handler(state, session_id, prompt, message_id, None, event_tx).await;
+    };
+
+    // The agent waits for an answer to a pending permission request before it can stop
+    if let Some(request) = state.session_state_manager.get_pending_permission(&session_key) {
+        state.session_state_manager.set_pending_permission(&session_key, None);
+        if state.get_pending_permission().map(|p| p.session_id == session_key).unwrap_or(false) {
+            state.set_pending_permission(None);
+        }
+        let request_id = serde_json::to_value(&request.request_id).map_err(|e| e.to_string())?;
+        state
+            .agent_manager
+            .respond_permission(&request.agent_id, request_id.clone(), PermissionOutcome::Cancelled)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let msg = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "permission/resolved".to_string(),
+            params: serde_json::json!({
+                "requestId": request_id,
+                "sessionId": session_id,
+            }),
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            let _ = event_tx.send(json);
+        }
+    }
+
+    Ok(serde_json::json!({ "steered": true, "queuedPromptId": queued.id }))
+}
+
+/// Tell clients a turn was steered; returns the context sent to the agent with the correction
+fn announce_steering(
+    state: &Arc<AppState>,
+    session_id: &str,
+    correction: &[ContentBlock],
+    stop_reason: Option<&StopReason>,
+) -> String {
+    // Only a cancelled turn has unfinished work to summarize
+    let summary = match stop_reason {
+        Some(StopReason::Cancelled) => state.session_state_manager.turn_summary(&session_id.to_string()),
+        _ => None,
+    };
+    let correction_text: String = correction
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    state
+        .session_state_manager
+        .announce_steering(&session_id.to_string(), correction_text, summary.clone());
+
+    match summary {
+        Some(summary) => format!(
+            "[The user interrupted your previous turn to correct it. It was stopped after:\n{}\nContinue from there, following the correction above.]",
+            summary
+        ),
+        None => "[The user sent this correction while you were working on the previous request.]".to_string(),
+    }
+}
+
+/// Run one prompt turn: record the user message, send the prompt and wait for the response
+/// `context` is appended to the prompt for the agent only, not shown as part of the message.
+async fn run_prompt_turn(
+    state: &Arc<AppState>,
+    session_id: &str,
+    prompt: Vec<ContentBlock>,
+    message_id: Option<String>,
+    context: Option<String>,
+    event_tx: &broadcast::Sender<String>,
+) -> Result<PromptResponse, String> {
     info!("WebSocket: Sending prompt to session {}", session_id);
+    // Before the message is added: a prompt the agent can't take must not end up in the chat
+    state
+        .agent_manager
+        .check_prompt_content(session_id, &prompt)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut agent_prompt = prompt.clone();
+    if let Some(context) = context {
+        agent_prompt.push(ContentBlock::Text { text: context });
+    }
 
     // Get session cwd for filtering broadcasts
     let session_cwd = state.session_registry.get_session_info(session_id)
@@ -1500,36 +2041,13 @@ async fn send_prompt_handler(state: &Arc<AppState>, session_id: &str, content: &
 
     // Add user message to SessionStateManager (single source of truth)
     // If message_id is provided (from frontend optimistic update), use it to avoid duplicates
-    state.session_state_manager.add_user_message(&session_id.to_string(), content.to_string(), message_id.clone());
+    let message_id = message_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    state.session_state_manager.add_user_prompt(&session_id.to_string(), &prompt, Some(message_id.clone()));
 
-    // Broadcast user message to all WebSocket clients
-    if let Some(session_state) = state.session_state_manager.get_state(&session_id.to_string()) {
-        // Get the last chat item which should be the user message we just added
-        if let Some(last_item) = session_state.chat_items.last() {
-            let msg = JsonRpcNotification {
-                jsonrpc: "2.0".to_string(),
-                method: "session/state_update".to_string(),
-                params: serde_json::json!({
-                    "sessionId": session_id,
-                    "update": {
-                        "updateType": "message_added",
-                        "message": match last_item {
-                            crate::core::session_state::ChatItem::Message { message } => serde_json::to_value(message).ok(),
-                            _ => None,
-                        }
-                    }
-                }),
-            };
-            if let Ok(json) = serde_json::to_string(&msg) {
-                let _ = event_tx.send(json);
-            }
-        }
-    }
-
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
 
     // Try to send prompt, auto-resume if session not found in ACP agent
-    let response = match manager.prompt(session_id, content).await {
+    let response = match manager.prompt(session_id, agent_prompt.clone()).await {
         Ok(resp) => resp,
         Err(e) => {
             // Check if error is "Session not found" - need to resume
@@ -1555,67 +2073,23 @@ async fn send_prompt_handler(state: &Arc<AppState>, session_id: &str, content: &
 
                 let cwd = session_info.cwd;
 
-                // Ensure ACP agent is running before resuming
-                ensure_agent_connected(state).await?;
 
-                // Resume the session
-                let resume_response = manager.resume_session(session_id, &cwd).await
+                // Restore the session with its history
+                let resume_response = restore_session(state, session_id, &cwd, vec![], false).await
                     .map_err(|e| format!("Failed to auto-resume session: {}", e))?;
 
                 info!("WebSocket: Auto-resumed session {} -> {}", session_id, resume_response.session_id);
 
-                // Update registry and state manager with potentially new session ID
-                state.session_registry.register_session(
-                    resume_response.session_id.clone(),
-                    cwd.clone(),
-                    resume_response.modes.clone(),
-                    resume_response.models.clone(),
-                );
-
-                // Load historical chat items from JSONL file
-                let history_items = state.session_registry.load_chat_items(session_id);
-                info!("Loaded {} historical chat items for auto-resumed session {}", history_items.len(), session_id);
-
-                // Create/update session state with historical chat items
-                state.session_state_manager.create_session_with_history(
-                    resume_response.session_id.clone(),
-                    cwd,
-                    resume_response.modes,
-                    resume_response.models,
-                    history_items,
-                );
-
-                // Re-add the user message to the new session state
-                state.session_state_manager.add_user_message(&resume_response.session_id, content.to_string(), message_id.clone());
-
-                // Broadcast user message to all WebSocket clients
-                if let Some(session_state) = state.session_state_manager.get_state(&resume_response.session_id) {
-                    if let Some(last_item) = session_state.chat_items.last() {
-                        let msg = JsonRpcNotification {
-                            jsonrpc: "2.0".to_string(),
-                            method: "session/state_update".to_string(),
-                            params: serde_json::json!({
-                                "sessionId": resume_response.session_id,
-                                "update": {
-                                    "updateType": "message_added",
-                                    "message": match last_item {
-                                        crate::core::session_state::ChatItem::Message { message } => serde_json::to_value(message).ok(),
-                                        _ => None,
-                                    }
-                                }
-                            }),
-                        };
-                        if let Ok(json) = serde_json::to_string(&msg) {
-                            let _ = event_tx.send(json);
-                        }
-                    }
+                // A restored stored state already has the user message; a fresh one doesn't
+                if !state.session_state_manager.ends_with_message(&resume_response.session_id, &message_id) {
+                    state.session_state_manager.add_user_prompt(&resume_response.session_id, &prompt, Some(message_id));
                 }
 
                 // Set as current session
                 state.set_current_session(Some(resume_response.session_id.clone())).await;
 
                 // Retry the prompt with the resumed session
-                manager.prompt(&resume_response.session_id, content).await
+                manager.prompt(&resume_response.session_id, agent_prompt).await
                     .map_err(|e| format!("Failed to send prompt after resume: {}", e))?
             } else {
                 return Err(e.to_string());
@@ -1634,7 +2108,7 @@ async fn send_prompt_handler(state: &Arc<AppState>, session_id: &str, content: &
 
 async fn cancel_session_handler(state: &Arc<AppState>, session_id: &str) -> Result<(), String> {
     info!("WebSocket: Cancelling session {}", session_id);
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
     manager.cancel(session_id).await.map_err(|e: AcpError| e.to_string())
 }
 
@@ -1657,14 +2131,15 @@ async fn stop_session_handler(
     if let Some(status) = current_status {
         if status == crate::core::SessionStatus::Running || status == crate::core::SessionStatus::Pending {
             info!("Session {} is {:?}, cancelling first...", session_id, status);
-            let manager = AgentManager::new(state.client.clone());
+            let manager = &state.agent_manager;
             // Ignore cancel errors (session might already be done)
             let _ = manager.cancel(session_id).await;
         }
     }
 
-    // Remove session from memory (SessionStateManager)
+    // Remove session from memory (SessionStateManager) and stop its commands
     state.session_state_manager.remove_session(&session_id.to_string());
+    state.agent_terminal_manager.release_session(session_id);
 
     // Unregister from active sessions (this sets active=false in list_sessions output)
     state.session_registry.unregister_session(&session_id.to_string());
@@ -1684,10 +2159,15 @@ async fn stop_session_handler(
 
 async fn set_session_mode_handler(state: &Arc<AppState>, session_id: &str, mode_id: &str) -> Result<(), String> {
     info!("WebSocket: Setting session {} mode to {}", session_id, mode_id);
-    let manager = AgentManager::new(state.client.clone());
+    let manager = &state.agent_manager;
     manager.set_session_mode(session_id, mode_id).await.map_err(|e: AcpError| e.to_string())
 }
 
+async fn set_session_model_handler(state: &Arc<AppState>, session_id: &str, model_id: &str) -> Result<(), String> {
+    info!("WebSocket: Setting session {} model to {}", session_id, model_id);
+    state.set_session_model(&session_id.to_string(), model_id).await
+}
+
 use crate::core::{ListSessionsResponse, SessionInfo};
 
 async fn list_sessions_handler(
@@ -1704,14 +2184,118 @@ async fn list_sessions_handler(
     response
 }
 
-async fn resume_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &str) -> Result<NewSessionResponse, String> {
-    info!("WebSocket: Resuming session {} in {}", session_id, cwd);
+/// Reattach to an existing session and rebuild its SessionState
+///
+/// A session with a stored state gets that state back. Otherwise, agents advertising
+/// `loadSession` replay the history as `session/update` notifications, which the
+/// notification forwarder applies to the fresh state, and other sessions are resumed
+/// with history read from Claude Code's JSONL files, in the background when
+/// `background_history` is set (the full state is broadcast once loaded).
+async fn restore_session(
+    state: &Arc<AppState>,
+    session_id: &str,
+    cwd: &str,
+    mcp_servers: Vec<McpServer>,
+    background_history: bool,
+) -> Result<NewSessionResponse, AcpError> {
+    let manager = &state.agent_manager;
+    let session_id = session_id.to_string();
 
-    // Ensure ACP agent is running before resuming session
-    ensure_agent_connected(state).await?;
+    // An active state is stored again while the session is reattached
+    state.session_state_manager.remove_session(&session_id);
+    let has_stored_state = state.session_state_manager.stored_state(&session_id).is_some();
+
+    let supports_load = manager.session_capabilities(&session_id).await?.supports_load_session();
+    let response = if supports_load && has_stored_state {
+        // The stored state already has the history; replayed updates are applied while
+        // the session has no state, so they are dropped
+        let loaded = manager.load_session(&session_id, cwd, mcp_servers).await?;
+        state.flush_notifications().await;
+        restore_stored_state(state, &session_id, &session_id, loaded.modes.clone(), loaded.models.clone()).await;
+        info!("Loaded session {} with its stored state", session_id);
+
+        NewSessionResponse {
+            session_id: session_id.clone(),
+            modes: loaded.modes,
+            models: loaded.models,
+        }
+    } else if supports_load {
+        // Fresh state first so replayed updates have somewhere to go
+        state.session_state_manager.create_session(session_id.clone(), cwd.to_string(), None, None);
+
+        let loaded = match manager.load_session(&session_id, cwd, mcp_servers).await {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                state.session_state_manager.remove_session(&session_id);
+                return Err(e);
+            }
+        };
+        // The replayed history must be in the state before it is returned
+        state.flush_notifications().await;
+        state.session_state_manager.set_modes_and_models(
+            &session_id,
+            loaded.modes.clone(),
+            loaded.models.clone(),
+        );
+        info!("Loaded session {} with replayed history", session_id);
+
+        NewSessionResponse {
+            session_id: session_id.clone(),
+            modes: loaded.modes,
+            models: loaded.models,
+        }
+    } else {
+        let response = manager.resume_session(&session_id, cwd, mcp_servers).await?;
+
+        if has_stored_state {
+            restore_stored_state(
+                state,
+                &session_id,
+                &response.session_id,
+                response.modes.clone(),
+                response.models.clone(),
+            )
+            .await;
+        } else if background_history {
+            state.session_state_manager.create_session(
+                response.session_id.clone(),
+                cwd.to_string(),
+                response.modes.clone(),
+                response.models.clone(),
+            );
+
+            let state = state.clone();
+            let original_session_id = session_id.clone();
+            let new_session_id = response.session_id.clone();
+            tokio::spawn(async move {
+                // Load historical chat items from JSONL file
+                let chat_items = state.session_registry.load_chat_items(&original_session_id);
 
-    let manager = AgentManager::new(state.client.clone());
-    let response = manager.resume_session(session_id, cwd).await.map_err(|e: AcpError| e.to_string())?;
+                if chat_items.is_empty() {
+                    debug!("No historical chat items to load for session {}", original_session_id);
+                    return;
+                }
+
+                info!("Background: Loaded {} historical chat items for session {}", chat_items.len(), original_session_id);
+
+                // Update session state with history; subscribers get the full state
+                state.session_state_manager.load_history(&new_session_id, chat_items);
+            });
+        } else {
+            // Load historical chat items from JSONL file
+            let chat_items = state.session_registry.load_chat_items(&session_id);
+            info!("Loaded {} historical chat items for session {}", chat_items.len(), session_id);
+
+            state.session_state_manager.create_session_with_history(
+                response.session_id.clone(),
+                cwd.to_string(),
+                response.modes.clone(),
+                response.models.clone(),
+                chat_items,
+            );
+        }
+        response
+    };
 
     // Register session in the registry
     state.session_registry.register_session(
@@ -1720,19 +2304,60 @@ async fn resume_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &s
         response.modes.clone(),
         response.models.clone(),
     );
+    if let Some(agent_id) = manager.agent_for_session(&response.session_id) {
+        state.session_registry.set_agent_id(&response.session_id, agent_id);
+    }
 
-    // Load historical chat items from JSONL file
-    let chat_items = state.session_registry.load_chat_items(session_id);
-    info!("Loaded {} historical chat items for session {}", chat_items.len(), session_id);
+    Ok(response)
+}
 
-    // Create session state with historical chat items
-    state.session_state_manager.create_session_with_history(
-        response.session_id.clone(),
-        cwd.to_string(),
-        response.modes.clone(),
-        response.models.clone(),
-        chat_items,
-    );
+/// Make the stored state of `stored_id` active as `session_id`, then ask the agent for the
+/// mode and model that were selected before
+async fn restore_stored_state(
+    state: &Arc<AppState>,
+    stored_id: &str,
+    session_id: &str,
+    modes: Option<SessionModeState>,
+    models: Option<SessionModelState>,
+) {
+    let Some(stored) = state.session_state_manager.restore_session(
+        &stored_id.to_string(),
+        session_id.to_string(),
+        modes.clone(),
+        models.clone(),
+    ) else {
+        return;
+    };
+
+    if let (Some(before), Some(now)) = (stored.modes, modes) {
+        if before.current_mode_id != now.current_mode_id
+            && now.available_modes.iter().any(|m| m.id == before.current_mode_id)
+        {
+            if let Err(e) = set_session_mode_handler(state, session_id, &before.current_mode_id).await {
+                warn!("Failed to restore mode of session {}: {}", session_id, e);
+            }
+        }
+    }
+    if let (Some(before), Some(now)) = (stored.models, models) {
+        if before.current_model_id != now.current_model_id {
+            if let Err(e) = set_session_model_handler(state, session_id, &before.current_model_id).await {
+                warn!("Failed to restore model of session {}: {}", session_id, e);
+            }
+        }
+    }
+}
+
+async fn resume_session_handler(
+    state: &Arc<AppState>,
+    session_id: &str,
+    cwd: &str,
+    mcp_servers: Vec<McpServer>,
+) -> Result<NewSessionResponse, String> {
+    info!("WebSocket: Resuming session {} in {}", session_id, cwd);
+
+    let response = restore_session(state, session_id, cwd, mcp_servers, false)
+        .await
+        .map_err(|e: AcpError| e.to_string())?;
 
     // Set as current active session and broadcast to all clients
     state.set_current_session(Some(response.session_id.clone())).await;
@@ -1741,14 +2366,17 @@ async fn resume_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &s
     Ok(response)
 }
 
-async fn fork_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &str) -> Result<NewSessionResponse, String> {
+async fn fork_session_handler(
+    state: &Arc<AppState>,
+    session_id: &str,
+    cwd: &str,
+    mcp_servers: Vec<McpServer>,
+) -> Result<NewSessionResponse, String> {
     info!("WebSocket: Forking session {} in {}", session_id, cwd);
 
-    // Ensure ACP agent is running before forking session
-    ensure_agent_connected(state).await?;
 
-    let manager = AgentManager::new(state.client.clone());
-    let response = manager.fork_session(session_id, cwd).await.map_err(|e: AcpError| e.to_string())?;
+    let manager = &state.agent_manager;
+    let response = manager.fork_session(session_id, cwd, mcp_servers).await.map_err(|e: AcpError| e.to_string())?;
 
     // Register new session in the registry
     state.session_registry.register_session(
@@ -1757,6 +2385,9 @@ async fn fork_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &str
         response.modes.clone(),
         response.models.clone(),
     );
+    if let Some(agent_id) = state.agent_manager.agent_for_session(&response.session_id) {
+        state.session_registry.set_agent_id(&response.session_id, agent_id);
+    }
 
     // Load historical chat items from JSONL file
     let chat_items = state.session_registry.load_chat_items(session_id);
@@ -1778,6 +2409,108 @@ async fn fork_session_handler(state: &Arc<AppState>, session_id: &str, cwd: &str
     Ok(response)
 }
 
+/// Branch a session at one of its user messages and send an edited version of it
+/// The agent session is forked and the branch keeps the chat before the message; the
+/// original session is left as it was. Returns the branch once it exists, while the
+/// edited prompt runs on it.
+/// ACP can't fork at a message, so the fork's agent context still holds the replaced
+/// message and what followed; the agent is only told to disregard them. The response
+/// says so in `replacedTurnsInAgentContext`.
+async fn rewind_session_handler(
+    state: &Arc<AppState>,
+    session_id: &str,
+    message_id: &str,
+    prompt: Vec<ContentBlock>,
+    event_tx: &broadcast::Sender<String>,
+) -> Result<serde_json::Value, String> {
+    /// Characters of the replaced message quoted to the agent
+    const REPLACED_MESSAGE_CHARS: usize = 300;
+
+    info!("WebSocket: Rewinding session {} to message {}", session_id, message_id);
+    let source = session_id.to_string();
+    let (history, replaced) = state.session_state_manager.history_before(&source, message_id)?;
+    let cwd = state
+        .session_state_manager
+        .get_state(&source)
+        .or_else(|| state.session_state_manager.stored_state(&source))
+        .map(|s| s.cwd)
+        .ok_or_else(|| format!("Session not found: {}", session_id))?;
+
+    // The edited prompt is sent after the response, when there is nobody to tell it was refused
+    state
+        .agent_manager
+        .check_fork_prompt_content(session_id, &cwd, &prompt)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mcp_servers = state.agent_manager.session_mcp_servers(session_id);
+    let response = state
+        .agent_manager
+        .fork_session(session_id, &cwd, mcp_servers)
+        .await
+        .map_err(|e: AcpError| e.to_string())?;
+    let branch_id = response.session_id.clone();
+
+    state.session_registry.register_session(
+        branch_id.clone(),
+        cwd.clone(),
+        response.modes.clone(),
+        response.models.clone(),
+    );
+    if let Some(agent_id) = state.agent_manager.agent_for_session(&branch_id) {
+        state.session_registry.set_agent_id(&branch_id, agent_id);
+    }
+    let branch_point = crate::core::BranchPoint {
+        session_id: source,
+        message_id: message_id.to_string(),
+    };
+    state.session_registry.link_branch(&branch_id, branch_point.clone());
+
+    state.session_state_manager.create_session_with_history(
+        branch_id.clone(),
+        cwd.clone(),
+        response.modes.clone(),
+        response.models.clone(),
+        history,
+    );
+
+    state.set_current_session(Some(branch_id.clone())).await;
+    broadcast_sessions_update(state, event_tx, Some(&cwd));
+
+    // The forked agent session still holds the replaced message and what followed it
+    let context = format!(
+        "[The user went back to their earlier message \"{}\" and replaced it with this one. Disregard that message and everything after it.]",
+        crate::core::session_registry::truncate_string(replaced.content.trim(), REPLACED_MESSAGE_CHARS)
+    );
+    {
+        let state = state.clone();
+        let event_tx = event_tx.clone();
+        let branch_id = branch_id.clone();
+        tokio::spawn(async move {
+            let message_id = Uuid::new_v4().to_string();
+            if let Err(e) =
+                send_prompt_handler(&state, &branch_id, prompt, Some(message_id.clone()), Some(context), &event_tx).await
+            {
+                // Nobody waits for the edited prompt's response; tell the branch's subscribers
+                warn!("Edited prompt for branch {} failed: {}", branch_id, e);
+                let sent = state.session_state_manager.has_message(&branch_id, &message_id);
+                state
+                    .session_state_manager
+                    .report_prompt_failure(&branch_id, sent.then_some(message_id), e);
+            }
+        });
+    }
+
+    info!("WebSocket: Branched session {} -> {}", session_id, branch_id);
+    Ok(serde_json::json!({
+        "sessionId": branch_id,
+        "modes": response.modes,
+        "models": response.models,
+        "branchedFrom": branch_point,
+        "replacedTurnsInAgentContext": true,
+    }))
+}
+
 async fn get_session_info_handler(state: &Arc<AppState>, session_id: &str) -> Result<SessionInfo, String> {
     info!("WebSocket: Getting session info: {}", session_id);
     state.session_registry.get_session_info(session_id)
@@ -1786,8 +2519,9 @@ async fn get_session_info_handler(state: &Arc<AppState>, session_id: &str) -> Re
 
 fn delete_session_handler(state: &Arc<AppState>, session_id: &str) -> Result<bool, String> {
     info!("WebSocket: Deleting session: {}", session_id);
-    // Also remove from session state manager if present
-    state.session_state_manager.remove_session(&session_id.to_string());
+    // Also remove from session state manager, including its stored state
+    state.session_state_manager.delete_session(&session_id.to_string());
+    state.agent_terminal_manager.release_session(session_id);
     state.session_registry.delete_session(session_id)
 }
 
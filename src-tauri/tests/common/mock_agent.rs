@@ -0,0 +1,358 @@
+//! Scriptable fake ACP agent served over TCP.
+//!
+//! Answers the ACP handshake and session methods, and plays one scripted turn per
+//! `session/prompt`: recorded `session/update`s, permission requests and errors.
+//! A `session/cancel` ends the running turn with `cancelled` after its current step.
+//! With `requires_auth`, session requests are refused until `authenticate` is called.
+//! Every message it receives is kept so tests can assert on the client's traffic.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// One step of a scripted prompt turn
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Send a `session/update` with this `update` object
+    Update(Value),
+    /// Ask for permission and wait for the client's answer
+    Permission { tool_call: Value, options: Value },
+    /// Send a request (fs/*, terminal/*) with these params and wait for the client's answer
+    Request { method: String, params: Value },
+    /// End the turn with a JSON-RPC error instead of a stop reason
+    Fail { code: i32, message: String },
+}
+
+impl Step {
+    /// Steps replaying a recorded turn: one `session/update` params object per line
+    pub fn recorded(path: impl AsRef<Path>) -> Vec<Step> {
+        let content = std::fs::read_to_string(path.as_ref()).expect("read fixture");
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let params: Value = serde_json::from_str(line).expect("fixture line is JSON");
+                Step::Update(params["update"].clone())
+            })
+            .collect()
+    }
+
+    pub fn text(text: &str) -> Step {
+        Step::Update(json!({
+            "sessionUpdate": "agent_message_chunk",
+            "content": { "type": "text", "text": text },
+        }))
+    }
+
+    pub fn request(method: &str, params: Value) -> Step {
+        Step::Request {
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    /// Permission request with "allow" and "reject" options
+    pub fn permission(tool_call_id: &str, title: &str) -> Step {
+        Step::Permission {
+            tool_call: json!({ "toolCallId": tool_call_id, "title": title }),
+            options: json!([
+                { "optionId": "allow", "name": "Allow", "kind": "allow_once" },
+                { "optionId": "reject", "name": "Reject", "kind": "reject_once" },
+            ]),
+        }
+    }
+}
+
+/// How the mock agent behaves
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    /// `agentCapabilities` returned from `initialize`
+    pub capabilities: Value,
+    /// Turns played by successive prompts; further prompts just end the turn
+    pub turns: Vec<Vec<Step>>,
+    /// Updates replayed by `session/load`
+    pub history: Vec<Value>,
+    /// Offer a "login" auth method and refuse session requests until it is used
+    pub requires_auth: bool,
+    /// `models` returned from `session/new`
+    pub models: Option<Value>,
+    /// Start of the IDs `session/new` hands out, numbered from 1
+    pub session_prefix: String,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self {
+            capabilities: json!({}),
+            session_prefix: "mock-session".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn capabilities(mut self, capabilities: Value) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn turn(mut self, steps: Vec<Step>) -> Self {
+        self.turns.push(steps);
+        self
+    }
+
+    pub fn history(mut self, updates: Vec<Value>) -> Self {
+        self.history = updates;
+        self
+    }
+
+    pub fn requires_auth(mut self) -> Self {
+        self.requires_auth = true;
+        self
+    }
+
+    pub fn session_prefix(mut self, prefix: &str) -> Self {
+        self.session_prefix = prefix.to_string();
+        self
+    }
+
+    pub fn models(mut self, models: Value) -> Self {
+        self.models = Some(models);
+        self
+    }
+}
+
+struct Shared {
+    script: Script,
+    turns: Mutex<VecDeque<Vec<Step>>>,
+    received: Mutex<Vec<Value>>,
+    next_session: AtomicU64,
+    /// Sessions whose running turn got `session/cancel`
+    cancelled: Mutex<HashSet<String>>,
+    authenticated: AtomicBool,
+}
+
+pub struct MockAgent {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl MockAgent {
+    /// Listen on a free local port; every connection plays the same script
+    pub async fn start(script: Script) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock agent");
+        let addr = listener.local_addr().unwrap();
+        let shared = Arc::new(Shared {
+            turns: Mutex::new(script.turns.clone().into()),
+            script,
+            received: Mutex::new(Vec::new()),
+            next_session: AtomicU64::new(1),
+            cancelled: Mutex::new(HashSet::new()),
+            authenticated: AtomicBool::new(false),
+        });
+
+        let accept_shared = shared.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accept_shared.clone()));
+            }
+        });
+
+        Self { addr, shared }
+    }
+
+    /// Address for an agent definition's `address`
+    pub fn address(&self) -> String {
+        format!("tcp://{}", self.addr)
+    }
+
+    /// Every message received from the client, in order
+    pub fn received(&self) -> Vec<Value> {
+        self.shared.received.lock().clone()
+    }
+
+    /// Received requests and notifications with this method
+    pub fn received_method(&self, method: &str) -> Vec<Value> {
+        self.received()
+            .into_iter()
+            .filter(|msg| msg["method"] == method)
+            .collect()
+    }
+
+    /// Received responses (answers to the agent's own requests)
+    pub fn received_responses(&self) -> Vec<Value> {
+        self.received()
+            .into_iter()
+            .filter(|msg| msg.get("method").is_none())
+            .collect()
+    }
+}
+
+type Waiters = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+async fn serve(stream: TcpStream, shared: Arc<Shared>) {
+    let (reader, mut writer) = stream.into_split();
+    let (out_tx, mut out_rx) = mpsc::channel::<Value>(100);
+    tokio::spawn(async move {
+        while let Some(msg) = out_rx.recv().await {
+            let line = format!("{}\n", msg);
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Answers to requests the agent sent (permission requests)
+    let waiters: Waiters = Arc::new(Mutex::new(HashMap::new()));
+    let next_request = Arc::new(AtomicU64::new(1000));
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(msg) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        shared.received.lock().push(msg.clone());
+
+        let Some(method) = msg["method"].as_str().map(str::to_string) else {
+            if let Some(id) = msg["id"].as_u64() {
+                if let Some(waiter) = waiters.lock().remove(&id) {
+                    let _ = waiter.send(msg);
+                }
+            }
+            continue;
+        };
+        // Notifications (session/cancel, $/cancel_request) need no answer
+        let Some(id) = msg.get("id").cloned() else {
+            if method == "session/cancel" {
+                if let Some(session_id) = msg["params"]["sessionId"].as_str() {
+                    shared.cancelled.lock().insert(session_id.to_string());
+                }
+            }
+            continue;
+        };
+
+        let shared = shared.clone();
+        let out_tx = out_tx.clone();
+        let waiters = waiters.clone();
+        let next_request = next_request.clone();
+        tokio::spawn(async move {
+            let params = msg["params"].clone();
+            let needs_auth = shared.script.requires_auth && !shared.authenticated.load(Ordering::SeqCst);
+            let reply = match method.as_str() {
+                "initialize" => {
+                    let auth_methods = if shared.script.requires_auth {
+                        json!([{ "id": "login", "name": "Log in" }])
+                    } else {
+                        json!([])
+                    };
+                    Ok(json!({
+                        "protocolVersion": 1,
+                        "agentInfo": { "name": "mock-agent", "version": "0.0.0" },
+                        "agentCapabilities": shared.script.capabilities,
+                        "authMethods": auth_methods,
+                    }))
+                }
+                "authenticate" => {
+                    shared.authenticated.store(true, Ordering::SeqCst);
+                    Ok(json!({}))
+                }
+                other if needs_auth && other.starts_with("session/") => {
+                    Err((-32000, "Authentication required".to_string()))
+                }
+                "session/new" | "session/fork" => {
+                    let n = shared.next_session.fetch_add(1, Ordering::SeqCst);
+                    Ok(json!({
+                        "sessionId": format!("{}-{}", shared.script.session_prefix, n),
+                        "models": shared.script.models,
+                    }))
+                }
+                "session/resume" => Ok(json!({ "sessionId": params["sessionId"] })),
+                "session/load" => {
+                    for update in &shared.script.history {
+                        send_update(&out_tx, &params["sessionId"], update.clone()).await;
+                    }
+                    Ok(json!({}))
+                }
+                "session/set_mode" | "session/set_model" => Ok(json!({})),
+                "session/prompt" => {
+                    let steps = shared.turns.lock().pop_front().unwrap_or_default();
+                    play_turn(&shared, &out_tx, &waiters, &next_request, &params["sessionId"], steps).await
+                }
+                other => Err((-32601, format!("Method not found: {}", other))),
+            };
+
+            let response = match reply {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => {
+                    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+                }
+            };
+            let _ = out_tx.send(response).await;
+        });
+    }
+}
+
+/// Play the steps of a turn; a `session/cancel` ends it with `cancelled` after the current step
+async fn play_turn(
+    shared: &Shared,
+    out_tx: &mpsc::Sender<Value>,
+    waiters: &Waiters,
+    next_request: &AtomicU64,
+    session_id: &Value,
+    steps: Vec<Step>,
+) -> Result<Value, (i32, String)> {
+    let session_key = session_id.as_str().unwrap_or_default().to_string();
+    shared.cancelled.lock().remove(&session_key);
+    for step in steps {
+        match step {
+            Step::Update(update) => send_update(out_tx, session_id, update).await,
+            Step::Permission { tool_call, options } => {
+                let params = json!({ "sessionId": session_id, "toolCall": tool_call, "options": options });
+                if send_request(out_tx, waiters, next_request, "session/request_permission", params).await.is_none() {
+                    return Err((-32603, "Permission request dropped".to_string()));
+                }
+            }
+            Step::Request { method, params } => {
+                if send_request(out_tx, waiters, next_request, &method, params).await.is_none() {
+                    return Err((-32603, format!("{} request dropped", method)));
+                }
+            }
+            Step::Fail { code, message } => return Err((code, message)),
+        }
+        if shared.cancelled.lock().remove(&session_key) {
+            return Ok(json!({ "stopReason": "cancelled" }));
+        }
+    }
+    Ok(json!({ "stopReason": "end_turn" }))
+}
+
+async fn send_update(out_tx: &mpsc::Sender<Value>, session_id: &Value, update: Value) {
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "session/update",
+        "params": { "sessionId": session_id, "update": update },
+    });
+    let _ = out_tx.send(notification).await;
+}
+
+/// Send a request to the client and wait for its response
+async fn send_request(
+    out_tx: &mpsc::Sender<Value>,
+    waiters: &Waiters,
+    next_request: &AtomicU64,
+    method: &str,
+    params: Value,
+) -> Option<Value> {
+    let id = next_request.fetch_add(1, Ordering::SeqCst);
+    let (answer_tx, answer_rx) = oneshot::channel();
+    waiters.lock().insert(id, answer_tx);
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    let _ = out_tx.send(request).await;
+    answer_rx.await.ok()
+}
@@ -0,0 +1,213 @@
+//! Shared harness for the integration tests: a `WebSocketServer` wired to the
+//! mock agent, and a WebSocket client that speaks the server's JSON-RPC.
+
+#![allow(dead_code)]
+
+pub mod mock_agent;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aero_work_lib::core::{AgentConfig, AgentDefinition, AppState, SessionStateManager, SessionStore};
+use aero_work_lib::server::WebSocketServer;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use mock_agent::MockAgent;
+
+/// ID of the mock agent in the test config
+pub const MOCK_AGENT_ID: &str = "mock";
+
+/// How long to wait for a response or notification before failing the test
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Start a server whose only (and default) agent is the mock agent
+pub async fn start_server(agent: &MockAgent) -> (Arc<AppState>, WsClient) {
+    serve(AppState::with_agent_config(&mock_agent_config(agent))).await
+}
+
+/// Like `start_server`, with session states persisted to a store in `dir`
+pub async fn start_server_with_store(agent: &MockAgent, dir: &Path) -> (Arc<AppState>, WsClient) {
+    let mut state = AppState::with_agent_config(&mock_agent_config(agent));
+    state.session_state_manager = Arc::new(SessionStateManager::with_store(SessionStore::new(dir)));
+    serve(state).await
+}
+
+/// Start a server with its own agent config (see `mock_agent_definition`)
+pub async fn start_server_with_config(config: &AgentConfig) -> (Arc<AppState>, WsClient) {
+    serve(AppState::with_agent_config(config)).await
+}
+
+/// Definition of an agent served by a mock agent
+pub fn mock_agent_definition(id: &str, agent: &MockAgent) -> AgentDefinition {
+    AgentDefinition {
+        id: id.to_string(),
+        name: format!("Mock Agent {}", id),
+        command: String::new(),
+        args: vec![],
+        env: Default::default(),
+        cwd: None,
+        address: Some(agent.address()),
+    }
+}
+
+fn mock_agent_config(agent: &MockAgent) -> AgentConfig {
+    AgentConfig {
+        agents: vec![mock_agent_definition(MOCK_AGENT_ID, agent)],
+        default_agent: Some(MOCK_AGENT_ID.to_string()),
+        ..Default::default()
+    }
+}
+
+async fn serve(state: AppState) -> (Arc<AppState>, WsClient) {
+    let state = Arc::new(state);
+
+    let server = WebSocketServer::new(state.clone());
+    tokio::spawn(async move {
+        let _ = server.start(0).await;
+    });
+
+    // The port is published just before the server starts accepting
+    let port = tokio::time::timeout(WAIT_TIMEOUT, async {
+        loop {
+            let port = state.get_ws_port();
+            if port != 0 {
+                return port;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("server did not start");
+
+    let client = WsClient::connect(port).await;
+    (state, client)
+}
+
+/// Working directory for test sessions (the mock agent never touches it)
+pub fn temp_cwd() -> String {
+    std::env::temp_dir().to_string_lossy().to_string()
+}
+
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// WebSocket client for the server's JSON-RPC protocol
+pub struct WsClient {
+    sink: WsSink,
+    incoming: mpsc::UnboundedReceiver<Value>,
+    /// Messages read while waiting for something else
+    backlog: Vec<Value>,
+    next_id: u64,
+}
+
+impl WsClient {
+    pub async fn connect(port: u16) -> Self {
+        let url = format!("ws://127.0.0.1:{}/ws", port);
+        let (ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .expect("connect to server");
+        let (sink, mut stream) = ws.split();
+
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if let Message::Text(text) = message {
+                    if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                        let _ = incoming_tx.send(value);
+                    }
+                }
+            }
+        });
+
+        Self {
+            sink,
+            incoming,
+            backlog: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Send a request without waiting for its response
+    pub async fn send(&mut self, method: &str, params: Value) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.sink
+            .send(Message::Text(request.to_string()))
+            .await
+            .expect("send request");
+        id
+    }
+
+    /// Response to request `id`: the result, or the error message
+    pub async fn response(&mut self, id: u64) -> Result<Value, String> {
+        let response = self
+            .next_matching(|msg| msg.get("method").is_none() && msg["id"] == id)
+            .await;
+        match response.get("error") {
+            Some(error) => Err(error["message"].as_str().unwrap_or_default().to_string()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+
+    pub async fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.send(method, params).await;
+        self.response(id).await
+    }
+
+    /// Error object of a request that is expected to fail
+    pub async fn request_error(&mut self, method: &str, params: Value) -> Value {
+        let id = self.send(method, params).await;
+        let response = self
+            .next_matching(|msg| msg.get("method").is_none() && msg["id"] == id)
+            .await;
+        assert!(response.get("error").is_some(), "{} succeeded: {}", method, response);
+        response["error"].clone()
+    }
+
+    /// Next notification with this method, returning its params
+    pub async fn notification(&mut self, method: &str) -> Value {
+        self.notification_where(method, |_| true).await
+    }
+
+    /// Next notification with this method whose params match
+    pub async fn notification_where(&mut self, method: &str, matches: impl Fn(&Value) -> bool) -> Value {
+        let msg = self
+            .next_matching(|msg| msg["method"] == method && matches(&msg["params"]))
+            .await;
+        msg["params"].clone()
+    }
+
+    /// Every `session/state_update` received so far for a session, in order
+    pub fn session_updates(&self, session_id: &str) -> Vec<Value> {
+        self.backlog
+            .iter()
+            .filter(|msg| msg["method"] == "session/state_update" && msg["params"]["sessionId"] == session_id)
+            .map(|msg| msg["params"]["update"].clone())
+            .collect()
+    }
+
+    async fn next_matching(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        if let Some(pos) = self.backlog.iter().position(&matches) {
+            return self.backlog.remove(pos);
+        }
+
+        let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+        loop {
+            let msg = tokio::time::timeout_at(deadline, self.incoming.recv())
+                .await
+                .expect("timed out waiting for message")
+                .expect("connection closed");
+            if matches(&msg) {
+                return msg;
+            }
+            self.backlog.push(msg);
+        }
+    }
+}
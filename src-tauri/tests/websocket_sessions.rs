@@ -0,0 +1,1052 @@
+//! End-to-end tests: WebSocket client -> `WebSocketServer` -> `AcpClient` -> mock agent.
+
+mod common;
+
+use aero_work_lib::acp::{SessionNotification, SessionUpdate};
+use aero_work_lib::core::{AgentConfig, SandboxConfig};
+use serde_json::{json, Value};
+
+use common::mock_agent::{MockAgent, Script, Step};
+use common::{
+    mock_agent_definition, start_server, start_server_with_config, start_server_with_store, temp_cwd, MOCK_AGENT_ID,
+};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Text of the last assistant message in a session state
+fn last_assistant_text(state: &Value) -> String {
+    state["chatItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|item| item["type"] == "message" && item["message"]["role"] == "assistant")
+        .map(|item| item["message"]["content"].as_str().unwrap().to_string())
+        .unwrap_or_default()
+}
+
+/// Message chunks "0 ", "1 ", ... as `session/update` objects; longer than the
+/// notification channel, they keep the forwarder busy after the agent is done
+fn numbered_chunks(count: usize) -> Vec<Value> {
+    (0..count)
+        .map(|i| json!({ "sessionUpdate": "agent_message_chunk", "content": { "type": "text", "text": format!("{} ", i) } }))
+        .collect()
+}
+
+/// The text of `numbered_chunks(count)`
+fn numbered_text(count: usize) -> String {
+    (0..count).map(|i| format!("{} ", i)).collect()
+}
+
+#[tokio::test]
+async fn test_prompt_streams_recorded_updates() {
+    let agent = MockAgent::start(Script::new().turn(Step::recorded(fixture("prompt_turn.jsonl")))).await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    assert_eq!(session_id, "mock-session-1");
+    client
+        .request("subscribe_session", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+
+    let result = client
+        .request("send_prompt", json!({ "sessionId": session_id, "content": "What is this crate?" }))
+        .await
+        .unwrap();
+    assert_eq!(result["stopReason"], "end_turn");
+
+    // The agent saw the prompt text
+    let prompts = agent.received_method("session/prompt");
+    assert_eq!(prompts.len(), 1);
+    assert_eq!(prompts[0]["params"]["prompt"][0]["text"], "What is this crate?");
+
+    // Every recorded update reached the subscriber as a state delta, in order
+    // (the last chunk is consumed here)
+    let last = client
+        .notification_where("session/state_update", |p| {
+            p["update"]["content"] == "demo." && p["sessionId"] == session_id.as_str()
+        })
+        .await;
+    let updates = client.session_updates(&session_id);
+    let kinds: Vec<Value> = updates.iter().map(|u| u["updateType"].clone()).collect();
+    assert_eq!(
+        kinds,
+        vec!["message_added", "thought_added", "tool_call_added", "tool_call_updated", "message_added"]
+    );
+    assert_eq!(updates[0]["message"]["content"], "What is this crate?");
+    // Numbered without gaps
+    let seqs: Vec<u64> = updates
+        .iter()
+        .chain([&last["update"]])
+        .map(|u| u["seq"].as_u64().unwrap())
+        .collect();
+    assert!(seqs.windows(2).all(|w| w[1] == w[0] + 1), "unexpected seqs: {:?}", seqs);
+
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert_eq!(last_assistant_text(&state), "The crate is called demo.");
+    // Reasoning is its own item, not an assistant message
+    let thought = state["chatItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["type"] == "thought")
+        .expect("thought in state");
+    assert_eq!(thought["thought"]["content"], "Looking at the project layout.");
+    let tool_call = state["chatItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["type"] == "tool_call")
+        .expect("tool call in state");
+    assert_eq!(tool_call["toolCall"]["status"], "completed");
+}
+
+#[tokio::test]
+async fn test_updates_only_reach_subscribed_sessions() {
+    let agent = MockAgent::start(
+        Script::new()
+            .turn(vec![Step::text("Unwatched.")])
+            .turn(vec![Step::text("Watched.")]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let mut session_ids = Vec::new();
+    for _ in 0..2 {
+        let session = client
+            .request("create_session", json!({ "cwd": temp_cwd() }))
+            .await
+            .unwrap();
+        session_ids.push(session["sessionId"].as_str().unwrap().to_string());
+    }
+    let (unwatched, watched) = (&session_ids[0], &session_ids[1]);
+    client
+        .request("subscribe_session", json!({ "sessionId": watched }))
+        .await
+        .unwrap();
+
+    for session_id in [unwatched, watched] {
+        client
+            .request("send_prompt", json!({ "sessionId": session_id, "content": "Hello" }))
+            .await
+            .unwrap();
+    }
+    client
+        .notification_where("session/state_update", |p| p["update"]["message"]["content"] == "Watched.")
+        .await;
+    assert!(client.session_updates(unwatched).is_empty());
+}
+
+#[tokio::test]
+async fn test_permission_request_routed_to_agent() {
+    let agent = MockAgent::start(
+        Script::new().turn(vec![Step::permission("call-1", "Run cargo test"), Step::text("Tests pass.")]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+
+    let prompt_id = client
+        .send("send_prompt", json!({ "sessionId": session_id, "content": "Run the tests" }))
+        .await;
+
+    let request = client.notification("permission/request").await;
+    assert_eq!(request["sessionId"], session_id.as_str());
+    assert_eq!(request["toolCall"]["title"], "Run cargo test");
+
+    // The pending request is part of the session state until answered
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert_eq!(state["pendingPermission"]["requestId"], request["requestId"]);
+
+    client
+        .request(
+            "respond_permission",
+            json!({
+                "requestId": request["requestId"],
+                "sessionId": session_id,
+                "outcome": { "outcome": "selected", "optionId": "allow" },
+            }),
+        )
+        .await
+        .unwrap();
+    let resolved = client.notification("permission/resolved").await;
+    assert_eq!(resolved["requestId"], request["requestId"]);
+
+    let result = client.response(prompt_id).await.unwrap();
+    assert_eq!(result["stopReason"], "end_turn");
+
+    let answers = agent.received_responses();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0]["id"], request["requestId"]);
+    assert_eq!(answers[0]["result"]["outcome"]["outcome"], "selected");
+    assert_eq!(answers[0]["result"]["outcome"]["optionId"], "allow");
+
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert!(state["pendingPermission"].is_null());
+    assert_eq!(last_assistant_text(&state), "Tests pass.");
+}
+
+#[tokio::test]
+async fn test_permission_answer_goes_to_requesting_agent() {
+    let agent = MockAgent::start(Script::new().turn(vec![Step::permission("call-1", "Edit main.rs")])).await;
+    let other_agent = MockAgent::start(
+        Script::new()
+            .session_prefix("other-session")
+            .turn(vec![Step::permission("call-2", "Run cargo test")]),
+    )
+    .await;
+    let config = AgentConfig {
+        agents: vec![
+            mock_agent_definition(MOCK_AGENT_ID, &agent),
+            mock_agent_definition("other", &other_agent),
+        ],
+        default_agent: Some(MOCK_AGENT_ID.to_string()),
+        ..Default::default()
+    };
+    let (_state, mut client) = start_server_with_config(&config).await;
+
+    let mut prompts = Vec::new();
+    let mut requests = Vec::new();
+    for agent_id in [MOCK_AGENT_ID, "other"] {
+        let session = client
+            .request("create_session", json!({ "cwd": temp_cwd(), "agentId": agent_id }))
+            .await
+            .unwrap();
+        let session_id = session["sessionId"].clone();
+        prompts.push(client.send("send_prompt", json!({ "sessionId": session_id, "content": "Go" })).await);
+        requests.push(
+            client
+                .notification_where("permission/request", |params| params["sessionId"] == session_id)
+                .await,
+        );
+    }
+    // Both agents number their requests the same way
+    assert_eq!(requests[0]["requestId"], requests[1]["requestId"]);
+    assert_eq!(requests[1]["agentId"], "other");
+
+    client
+        .request(
+            "respond_permission",
+            json!({
+                "requestId": requests[1]["requestId"],
+                "sessionId": requests[1]["sessionId"],
+                "outcome": { "outcome": "selected", "optionId": "allow" },
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(client.response(prompts[1]).await.unwrap()["stopReason"], "end_turn");
+    assert_eq!(other_agent.received_responses().len(), 1);
+    assert!(agent.received_responses().is_empty());
+
+    client
+        .request(
+            "respond_permission",
+            json!({
+                "requestId": requests[0]["requestId"],
+                "sessionId": requests[0]["sessionId"],
+                "outcome": { "outcome": "selected", "optionId": "reject" },
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(client.response(prompts[0]).await.unwrap()["stopReason"], "end_turn");
+    let answers = agent.received_responses();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0]["result"]["outcome"]["optionId"], "reject");
+}
+
+#[tokio::test]
+async fn test_agent_error_fails_prompt() {
+    let agent = MockAgent::start(Script::new().turn(vec![Step::Fail {
+        code: -32603,
+        message: "model overloaded".to_string(),
+    }]))
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let error = client
+        .request("send_prompt", json!({ "sessionId": session["sessionId"], "content": "Hello" }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("model overloaded"), "unexpected error: {}", error);
+
+    // The session is usable again afterwards
+    let result = client
+        .request("send_prompt", json!({ "sessionId": session["sessionId"], "content": "Again" }))
+        .await
+        .unwrap();
+    assert_eq!(result["stopReason"], "end_turn");
+}
+
+#[tokio::test]
+async fn test_unsupported_prompt_content_leaves_no_message() {
+    let agent = MockAgent::start(Script::new()).await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let content = json!([
+        { "type": "text", "text": "What is in this picture?" },
+        { "type": "image", "data": "iVBORw0KGgo=", "mimeType": "image/png" },
+    ]);
+    let error = client
+        .request("send_prompt", json!({ "sessionId": session["sessionId"], "content": content }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("image"), "unexpected error: {}", error);
+    assert!(agent.received_method("session/prompt").is_empty());
+
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session["sessionId"] }))
+        .await
+        .unwrap();
+    assert!(state["chatItems"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_unknown_model_rejected() {
+    let models = json!({
+        "currentModelId": "fast",
+        "availableModels": [
+            { "modelId": "fast", "name": "Fast" },
+            { "modelId": "smart", "name": "Smart" },
+        ],
+    });
+    let agent = MockAgent::start(Script::new().models(models)).await;
+    let (state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+
+    let error = client
+        .request("set_session_model", json!({ "sessionId": session_id, "modelId": "huge" }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("not available"), "unexpected error: {}", error);
+    // The Tauri command shares the check
+    assert!(state.set_session_model(&session_id, "huge").await.is_err());
+    assert!(agent.received_method("session/set_model").is_empty());
+
+    client
+        .request("set_session_model", json!({ "sessionId": session_id, "modelId": "smart" }))
+        .await
+        .unwrap();
+    assert_eq!(agent.received_method("session/set_model")[0]["params"]["modelId"], "smart");
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert_eq!(state["models"]["currentModelId"], "smart");
+}
+
+#[tokio::test]
+async fn test_auth_required_until_authenticated() {
+    let agent = MockAgent::start(
+        Script::new()
+            .requires_auth()
+            .capabilities(json!({ "sessionCapabilities": { "fork": {} } })),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    // Refused at once with ACP's auth code, so clients retry after `agent/authenticated`,
+    // and the agent's auth methods sent to clients
+    let error = client.request_error("create_session", json!({ "cwd": temp_cwd() })).await;
+    assert_eq!(error["code"], -32000);
+    assert!(error["message"].as_str().unwrap().contains("Authentication required"), "unexpected error: {}", error);
+    let auth = client.notification("agent/auth_required").await;
+    assert_eq!(auth["agentId"], MOCK_AGENT_ID);
+    assert_eq!(auth["authMethods"][0]["id"], "login");
+    let awaiting_auth = |agents: Value| {
+        let agents = agents["agents"].as_array().unwrap().clone();
+        agents.into_iter().find(|a| a["id"] == MOCK_AGENT_ID).unwrap()["awaitingAuth"].clone()
+    };
+    let agents = client.request("list_agents", json!({})).await.unwrap();
+    assert_eq!(awaiting_auth(agents), true);
+
+    // Forks are refused the same way
+    let error = client
+        .request_error("fork_session", json!({ "sessionId": "old-session", "cwd": temp_cwd() }))
+        .await;
+    assert_eq!(error["code"], -32000);
+
+    client
+        .request("authenticate", json!({ "agentId": MOCK_AGENT_ID, "methodId": "login" }))
+        .await
+        .unwrap();
+    assert_eq!(agent.received_method("authenticate")[0]["params"]["methodId"], "login");
+    client.notification("agent/authenticated").await;
+
+    // The retried request goes through
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    assert_eq!(session["sessionId"], "mock-session-1");
+    assert_eq!(agent.received_method("session/new").len(), 2);
+    let agents = client.request("list_agents", json!({})).await.unwrap();
+    assert_eq!(awaiting_auth(agents), false);
+}
+
+#[tokio::test]
+async fn test_resume_replays_history_with_session_load() {
+    let history = Step::recorded(fixture("prompt_turn.jsonl"))
+        .into_iter()
+        .map(|step| match step {
+            Step::Update(update) => update,
+            _ => unreachable!(),
+        })
+        .collect();
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "loadSession": true }))
+            .history(history),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+    let cwd = temp_cwd();
+
+    let response = client
+        .request("resume_session", json!({ "sessionId": "previous-session", "cwd": cwd }))
+        .await
+        .unwrap();
+    assert_eq!(response["sessionId"], "previous-session");
+
+    let loads = agent.received_method("session/load");
+    assert_eq!(loads.len(), 1);
+    assert_eq!(loads[0]["params"]["sessionId"], "previous-session");
+    assert_eq!(loads[0]["params"]["cwd"], cwd.as_str());
+    assert!(agent.received_method("session/resume").is_empty());
+
+    // The replayed history is applied by the time the load returns
+    let state = client
+        .request("get_session_state", json!({ "sessionId": "previous-session", "autoResume": false }))
+        .await
+        .unwrap();
+    assert_eq!(last_assistant_text(&state), "The crate is called demo.");
+}
+
+#[tokio::test]
+async fn test_load_returns_after_whole_history_is_applied() {
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "loadSession": true }))
+            .history(numbered_chunks(500)),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    client
+        .request("resume_session", json!({ "sessionId": "previous-session", "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let state = client
+        .request("get_session_state", json!({ "sessionId": "previous-session", "autoResume": false }))
+        .await
+        .unwrap();
+    assert_eq!(last_assistant_text(&state), numbered_text(500));
+}
+
+#[tokio::test]
+async fn test_flush_applies_queued_notifications() {
+    let agent = MockAgent::start(Script::new()).await;
+    let (state, _client) = start_server(&agent).await;
+    let session_id = "flushed-session".to_string();
+    state
+        .session_state_manager
+        .create_session(session_id.clone(), temp_cwd(), None, None);
+
+    for chunk in numbered_chunks(100) {
+        let update: SessionUpdate = serde_json::from_value(chunk).unwrap();
+        state
+            .notification_tx
+            .send(SessionNotification { session_id: session_id.clone(), update })
+            .await
+            .unwrap();
+    }
+    state.flush_notifications().await;
+
+    let session = serde_json::to_value(state.session_state_manager.get_state(&session_id).unwrap()).unwrap();
+    assert_eq!(last_assistant_text(&session), numbered_text(100));
+}
+
+// Threaded like the app, so the replay can still be in flight when the load returns
+#[tokio::test(flavor = "multi_thread")]
+async fn test_reload_keeps_stored_history_once() {
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "loadSession": true }))
+            .history(numbered_chunks(500)),
+    )
+    .await;
+    let store_dir = std::env::temp_dir().join(format!("aero-work-reload-{}", uuid::Uuid::new_v4()));
+    let (_state, mut client) = start_server_with_store(&agent, &store_dir).await;
+    let resume = json!({ "sessionId": "previous-session", "cwd": temp_cwd() });
+    let get_state = json!({ "sessionId": "previous-session", "autoResume": false });
+
+    client.request("resume_session", resume.clone()).await.unwrap();
+    let first = client.request("get_session_state", get_state.clone()).await.unwrap();
+
+    // Loading again restores the stored state; the agent's replay must not be added to it
+    client.request("resume_session", resume).await.unwrap();
+    let second = client.request("get_session_state", get_state).await.unwrap();
+    assert_eq!(agent.received_method("session/load").len(), 2);
+    assert_eq!(second["chatItems"], first["chatItems"]);
+    assert_eq!(last_assistant_text(&second), numbered_text(500));
+
+    let stored = aero_work_lib::core::SessionStore::new(&store_dir).load_all();
+    assert_eq!(serde_json::to_value(&stored[0].chat_items).unwrap(), first["chatItems"]);
+    let _ = std::fs::remove_dir_all(&store_dir);
+}
+
+#[tokio::test]
+async fn test_auto_resume_keeps_one_user_message() {
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "loadSession": true }))
+            .turn(vec![Step::Fail { code: -32603, message: "Session not found".to_string() }])
+            .turn(vec![Step::text("Back again.")]),
+    )
+    .await;
+    let store_dir = std::env::temp_dir().join(format!("aero-work-auto-resume-{}", uuid::Uuid::new_v4()));
+    let (_state, mut client) = start_server_with_store(&agent, &store_dir).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request("send_prompt", json!({ "sessionId": session_id, "content": "Still there?" }))
+        .await
+        .unwrap();
+    assert_eq!(agent.received_method("session/load").len(), 1);
+    assert_eq!(agent.received_method("session/prompt").len(), 2);
+
+    // The restored state already had the message when the prompt was retried
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    let user_messages: Vec<&Value> = state["chatItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|item| item["type"] == "message" && item["message"]["role"] == "user")
+        .collect();
+    assert_eq!(user_messages.len(), 1);
+    assert_eq!(user_messages[0]["message"]["content"], "Still there?");
+    assert_eq!(last_assistant_text(&state), "Back again.");
+    let _ = std::fs::remove_dir_all(&store_dir);
+}
+
+#[tokio::test]
+async fn test_resume_without_load_uses_session_resume() {
+    let agent = MockAgent::start(Script::new().capabilities(json!({ "sessionCapabilities": { "resume": {} } }))).await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let response = client
+        .request("resume_session", json!({ "sessionId": "previous-session", "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    assert_eq!(response["sessionId"], "previous-session");
+    assert_eq!(agent.received_method("session/resume").len(), 1);
+    assert!(agent.received_method("session/load").is_empty());
+
+    // Prompts go to the resumed session
+    client
+        .request("send_prompt", json!({ "sessionId": "previous-session", "content": "Continue" }))
+        .await
+        .unwrap();
+    let prompts = agent.received_method("session/prompt");
+    assert_eq!(prompts[0]["params"]["sessionId"], "previous-session");
+}
+
+#[tokio::test]
+async fn test_resume_rejected_when_agent_lacks_capability() {
+    let agent = MockAgent::start(Script::new()).await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let error = client
+        .request("resume_session", json!({ "sessionId": "previous-session", "cwd": temp_cwd() }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("session/resume"), "unexpected error: {}", error);
+    assert!(agent.received_method("session/resume").is_empty());
+}
+
+#[tokio::test]
+async fn test_prompts_sent_during_turn_are_queued() {
+    let agent = MockAgent::start(
+        Script::new()
+            .turn(vec![Step::permission("call-1", "Edit file"), Step::text("Edited.")])
+            .turn(vec![Step::text("Second done.")]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request("subscribe_session", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+
+    // The first turn blocks on the permission request
+    let first_id = client
+        .send("send_prompt", json!({ "sessionId": session_id, "content": "First" }))
+        .await;
+    let request = client.notification("permission/request").await;
+
+    let queued = client
+        .request("send_prompt", json!({ "sessionId": session_id, "content": "Second" }))
+        .await
+        .unwrap();
+    assert_eq!(queued["queued"], true);
+    let cancelled = client
+        .request("send_prompt", json!({ "sessionId": session_id, "content": "Never mind" }))
+        .await
+        .unwrap();
+    let update = client
+        .notification_where("session/state_update", |p| {
+            p["update"]["updateType"] == "queued_prompts_updated"
+                && p["update"]["queuedPrompts"].as_array().map(|q| q.len()) == Some(2)
+        })
+        .await;
+    assert_eq!(update["update"]["queuedPrompts"][0]["id"], queued["queuedPromptId"]);
+
+    let queue = client
+        .request(
+            "cancel_queued_prompt",
+            json!({ "sessionId": session_id, "queuedPromptId": cancelled["queuedPromptId"] }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(queue["queuedPrompts"].as_array().unwrap().len(), 1);
+    assert_eq!(agent.received_method("session/prompt").len(), 1);
+
+    client
+        .request(
+            "respond_permission",
+            json!({
+                "requestId": request["requestId"],
+                "sessionId": session_id,
+                "outcome": { "outcome": "selected", "optionId": "allow" },
+            }),
+        )
+        .await
+        .unwrap();
+    let result = client.response(first_id).await.unwrap();
+    assert_eq!(result["stopReason"], "end_turn");
+
+    // The queued prompt is sent on its own once the first turn ends
+    client
+        .notification_where("session/state_update", |p| p["update"]["message"]["content"] == "Second done.")
+        .await;
+    let prompts = agent.received_method("session/prompt");
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[1]["params"]["prompt"][0]["text"], "Second");
+
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert!(state["queuedPrompts"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_queued_prompt_checked_and_failure_reported() {
+    let agent = MockAgent::start(
+        Script::new()
+            .turn(vec![Step::permission("call-1", "Edit file"), Step::text("Edited.")])
+            .turn(vec![Step::Fail { code: -32603, message: "Overloaded".to_string() }]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request("subscribe_session", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    let first_id = client
+        .send("send_prompt", json!({ "sessionId": session_id, "content": "First" }))
+        .await;
+    let request = client.notification("permission/request").await;
+
+    // Content the agent can't take is refused now, not when the queue gets to it
+    let content = json!([{ "type": "image", "data": "iVBORw0KGgo=", "mimeType": "image/png" }]);
+    let error = client
+        .request("send_prompt", json!({ "sessionId": session_id, "content": content }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("image"), "unexpected error: {}", error);
+    let error = client
+        .request("steer_session", json!({ "sessionId": session_id, "content": content }))
+        .await
+        .unwrap_err();
+    assert!(error.contains("image"), "unexpected error: {}", error);
+    assert!(agent.received_method("session/cancel").is_empty());
+
+    let queued = client
+        .request(
+            "send_prompt",
+            json!({ "sessionId": session_id, "content": "Second", "messageId": "second" }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(queued["queued"], true);
+    client
+        .request(
+            "respond_permission",
+            json!({
+                "requestId": request["requestId"],
+                "sessionId": session_id,
+                "outcome": { "outcome": "selected", "optionId": "allow" },
+            }),
+        )
+        .await
+        .unwrap();
+    client.response(first_id).await.unwrap();
+
+    // Nobody waits for the queued prompt's response, so subscribers get its failure
+    let update = client
+        .notification_where("session/state_update", |p| p["update"]["updateType"] == "prompt_failed")
+        .await;
+    assert_eq!(update["update"]["failure"]["messageId"], "second");
+    assert!(update["update"]["failure"]["error"].as_str().unwrap().contains("Overloaded"));
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    let last = state["chatItems"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(last["type"], "prompt_failure");
+}
+
+#[tokio::test]
+async fn test_steer_interrupts_turn_and_reprompts() {
+    let agent = MockAgent::start(
+        Script::new()
+            .turn(vec![
+                Step::text("Deleting the tests."),
+                Step::permission("call-1", "Delete tests/"),
+                Step::text("Never reached."),
+            ])
+            .turn(vec![Step::text("Keeping the tests.")]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request("subscribe_session", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+
+    let first_id = client
+        .send("send_prompt", json!({ "sessionId": session_id, "content": "Clean up the repo" }))
+        .await;
+    client.notification("permission/request").await;
+
+    let steered = client
+        .request("steer_session", json!({ "sessionId": session_id, "content": "Don't delete the tests" }))
+        .await
+        .unwrap();
+    assert_eq!(steered["steered"], true);
+
+    // The interrupted turn ends as cancelled, and its permission request was answered
+    let result = client.response(first_id).await.unwrap();
+    assert_eq!(result["stopReason"], "cancelled");
+    assert_eq!(agent.received_method("session/cancel").len(), 1);
+    assert_eq!(agent.received_responses()[0]["result"]["outcome"]["outcome"], "cancelled");
+
+    let update = client
+        .notification_where("session/state_update", |p| p["update"]["updateType"] == "turn_steered")
+        .await;
+    assert_eq!(update["update"]["steering"]["correction"], "Don't delete the tests");
+    assert!(update["update"]["steering"]["summary"].as_str().unwrap().contains("Deleting the tests."));
+
+    // The correction is re-prompted with the summary for the agent only
+    client
+        .notification_where("session/state_update", |p| p["update"]["message"]["content"] == "Keeping the tests.")
+        .await;
+    let prompts = agent.received_method("session/prompt");
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[1]["params"]["prompt"][0]["text"], "Don't delete the tests");
+    assert!(prompts[1]["params"]["prompt"][1]["text"].as_str().unwrap().contains("Deleting the tests."));
+
+    let state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    let items = state["chatItems"].as_array().unwrap();
+    let user_messages: Vec<&Value> = items
+        .iter()
+        .filter(|item| item["type"] == "message" && item["message"]["role"] == "user")
+        .collect();
+    assert_eq!(user_messages.last().unwrap()["message"]["content"], "Don't delete the tests");
+    // The steering note stays in the chat, just before the correction
+    let steering = items.iter().position(|item| item["type"] == "steering").expect("steering in state");
+    assert_eq!(items[steering]["steering"]["correction"], "Don't delete the tests");
+    assert_eq!(items[steering + 1]["message"]["content"], "Don't delete the tests");
+}
+
+#[tokio::test]
+async fn test_rewind_branches_from_edited_message() {
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "sessionCapabilities": { "fork": {} } }))
+            .turn(vec![Step::text("Roses are red.")])
+            .turn(vec![Step::text("An old silent pond.")]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request(
+            "send_prompt",
+            json!({ "sessionId": session_id, "content": "Write a poem", "messageId": "msg-1" }),
+        )
+        .await
+        .unwrap();
+
+    let branch = client
+        .request(
+            "rewind_session",
+            json!({ "sessionId": session_id, "messageId": "msg-1", "newContent": "Write a haiku" }),
+        )
+        .await
+        .unwrap();
+    let branch_id = branch["sessionId"].as_str().unwrap().to_string();
+    assert_ne!(branch_id, session_id);
+    assert_eq!(branch["branchedFrom"], json!({ "sessionId": session_id, "messageId": "msg-1" }));
+    assert_eq!(branch["replacedTurnsInAgentContext"], true);
+    let forks = agent.received_method("session/fork");
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0]["params"]["sessionId"], session_id.as_str());
+
+    let subscribed = client
+        .request("subscribe_session", json!({ "sessionId": branch_id, "sinceSeq": 0 }))
+        .await
+        .unwrap();
+    let is_reply = |update: &Value| update["message"]["content"] == "An old silent pond.";
+    if !subscribed["updates"].as_array().unwrap().iter().any(is_reply) {
+        client
+            .notification_where("session/state_update", |p| is_reply(&p["update"]))
+            .await;
+    }
+
+    // The edited prompt goes to the fork, telling the agent what it replaces
+    let prompts = agent.received_method("session/prompt");
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[1]["params"]["sessionId"], branch_id.as_str());
+    assert_eq!(prompts[1]["params"]["prompt"][0]["text"], "Write a haiku");
+    assert!(prompts[1]["params"]["prompt"][1]["text"].as_str().unwrap().contains("Write a poem"));
+
+    let branch_state = client
+        .request("get_session_state", json!({ "sessionId": branch_id }))
+        .await
+        .unwrap();
+    let items = branch_state["chatItems"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["message"]["content"], "Write a haiku");
+    assert_eq!(last_assistant_text(&branch_state), "An old silent pond.");
+
+    // The original keeps its history, and both sessions know about the branch
+    let original_state = client
+        .request("get_session_state", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert_eq!(original_state["chatItems"][0]["message"]["content"], "Write a poem");
+    assert_eq!(last_assistant_text(&original_state), "Roses are red.");
+
+    let original_info = client
+        .request("get_session_info", json!({ "sessionId": session_id }))
+        .await
+        .unwrap();
+    assert_eq!(original_info["branches"], json!([branch_id]));
+    let branch_info = client
+        .request("get_session_info", json!({ "sessionId": branch_id }))
+        .await
+        .unwrap();
+    assert_eq!(branch_info["branchedFrom"]["sessionId"], session_id.as_str());
+}
+
+#[tokio::test]
+async fn test_rewind_checks_prompt_and_reports_failure() {
+    let agent = MockAgent::start(
+        Script::new()
+            .capabilities(json!({ "sessionCapabilities": { "fork": {} } }))
+            .turn(vec![Step::text("Roses are red.")])
+            .turn(vec![Step::Fail { code: -32603, message: "Overloaded".to_string() }]),
+    )
+    .await;
+    let (_state, mut client) = start_server(&agent).await;
+
+    let session = client
+        .request("create_session", json!({ "cwd": temp_cwd() }))
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_str().unwrap().to_string();
+    client
+        .request(
+            "send_prompt",
+            json!({ "sessionId": session_id, "content": "Write a poem", "messageId": "msg-1" }),
+        )
+        .await
+        .unwrap();
+
+    // Content the agent can't take is refused before anything is forked
+    let content = json!([{ "type": "image", "data": "iVBORw0KGgo=", "mimeType": "image/png" }]);
+    let error = client
+        .request(
+            "rewind_session",
+            json!({ "sessionId": session_id, "messageId": "msg-1", "newContent": content }),
+        )
+        .await
+        .unwrap_err();
+    assert!(error.contains("image"), "unexpected error: {}", error);
+    assert!(agent.received_method("session/fork").is_empty());
+
+    let branch = client
+        .request(
+            "rewind_session",
+            json!({ "sessionId": session_id, "messageId": "msg-1", "newContent": "Write a haiku" }),
+        )
+        .await
+        .unwrap();
+    let branch_id = branch["sessionId"].as_str().unwrap().to_string();
+
+    // Nobody waits for the edited prompt's response, so the branch's subscribers get its failure
+    let subscribed = client
+        .request("subscribe_session", json!({ "sessionId": branch_id, "sinceSeq": 0 }))
+        .await
+        .unwrap();
+    let is_failure = |update: &Value| update["updateType"] == "prompt_failed";
+    let failure = match subscribed["updates"].as_array().unwrap().iter().find(|u| is_failure(u)) {
+        Some(update) => update.clone(),
+        None => {
+            client
+                .notification_where("session/state_update", |p| is_failure(&p["update"]))
+                .await["update"]
+                .clone()
+        }
+    };
+    assert!(failure["failure"]["error"].as_str().unwrap().contains("Overloaded"));
+    assert!(failure["failure"]["messageId"].is_string());
+}
+
+#[tokio::test]
+async fn test_agent_requests_served_for_own_sessions_only() {
+    let workspace = std::env::temp_dir().join(format!("aero-work-sandbox-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&workspace).unwrap();
+    let outside = std::env::temp_dir().join(format!("aero-work-outside-{}.txt", uuid::Uuid::new_v4()));
+    let write = |session_id: &str, path: &std::path::Path| {
+        Step::request(
+            "fs/write_text_file",
+            json!({ "sessionId": session_id, "path": path, "content": "written" }),
+        )
+    };
+    let agent = MockAgent::start(Script::new().turn(vec![
+        // A session of the other agent
+        write("other-session-1", &workspace.join("cross.txt")),
+        // Its own session, outside its workspace
+        write("mock-session-1", &outside),
+        write("mock-session-1", &workspace.join("inside.txt")),
+    ]))
+    .await;
+    let other_agent = MockAgent::start(Script::new().session_prefix("other-session")).await;
+    let config = AgentConfig {
+        agents: vec![
+            mock_agent_definition(MOCK_AGENT_ID, &agent),
+            mock_agent_definition("other", &other_agent),
+        ],
+        default_agent: Some(MOCK_AGENT_ID.to_string()),
+        // Remote agents are not spawned in the sandbox, but their requests are served in it
+        sandbox: SandboxConfig {
+            enabled: true,
+            writable_paths: vec![],
+            allow_network: true,
+        },
+        ..Default::default()
+    };
+    let (_state, mut client) = start_server_with_config(&config).await;
+
+    let other = client
+        .request("create_session", json!({ "cwd": temp_cwd(), "agentId": "other" }))
+        .await
+        .unwrap();
+    assert_eq!(other["sessionId"], "other-session-1");
+    let session = client
+        .request("create_session", json!({ "cwd": workspace }))
+        .await
+        .unwrap();
+    assert_eq!(session["sessionId"], "mock-session-1");
+    client
+        .request(
+            "send_prompt",
+            json!({ "sessionId": session["sessionId"], "content": [{ "type": "text", "text": "Write" }] }),
+        )
+        .await
+        .unwrap();
+
+    assert!(!workspace.join("cross.txt").exists());
+    assert!(!outside.exists());
+    assert_eq!(std::fs::read_to_string(workspace.join("inside.txt")).unwrap(), "written");
+    // Every answer went back to the agent that asked, none to the one whose session was named
+    let responses = agent.received_responses();
+    assert_eq!(responses.len(), 3);
+    assert!(responses[0]["error"]["message"].as_str().unwrap().contains("does not belong"));
+    assert!(responses[1].get("error").is_some());
+    assert!(responses[2].get("result").is_some());
+    assert!(other_agent.received_responses().is_empty());
+
+    let _ = std::fs::remove_dir_all(&workspace);
+}
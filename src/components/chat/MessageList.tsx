@@ -2,9 +2,9 @@ import { useEffect, useRef, useState, useCallback } from "react";
 import { useTranslation } from "react-i18next";
 import { cn } from "@/lib/utils";
 import { Button } from "@/components/ui/button";
-import type { Message, ChatItem } from "@/types/acp";
+import type { Message, ChatItem, Thought, Steering, PromptFailure } from "@/types/acp";
 import { ToolCallCard } from "./ToolCallCard";
-import { Bot, User, Copy, Check, ArrowDown, AlertCircle } from "lucide-react";
+import { Bot, User, Copy, Check, ArrowDown, AlertCircle, Brain, ChevronRight, CornerDownRight } from "lucide-react";
 import ReactMarkdown from "react-markdown";
 import remarkGfm from "remark-gfm";
 import { Prism as SyntaxHighlighter } from "react-syntax-highlighter";
@@ -96,10 +96,11 @@ export function MessageList({
     scrollToBottom(false);
   }, []);
 
-  // Check if the last item is an assistant message (for loading indicator)
+  // Check if the last item is agent output being streamed (for loading indicator)
   const lastItem = chatItems[chatItems.length - 1];
   const lastIsAssistantMessage =
-    lastItem?.type === "message" && lastItem.message.role === "assistant";
+    (lastItem?.type === "message" && lastItem.message.role === "assistant") ||
+    lastItem?.type === "thought";
 
   return (
     <div className="h-full relative overflow-hidden">
@@ -120,6 +121,12 @@ export function MessageList({
           {chatItems.map((item) => {
             if (item.type === "message") {
               return <MessageBubble key={item.message.id} message={item.message} />;
+            } else if (item.type === "thought") {
+              return <ThoughtBlock key={item.thought.id} thought={item.thought} />;
+            } else if (item.type === "steering") {
+              return <SteeringNote key={item.steering.id} steering={item.steering} />;
+            } else if (item.type === "prompt_failure") {
+              return <PromptFailureNote key={item.failure.id} failure={item.failure} />;
             } else {
               return (
                 <ToolCallCard
@@ -172,6 +179,58 @@ export function MessageList({
   );
 }
 
+/** Agent reasoning, collapsed to one line until expanded */
+function ThoughtBlock({ thought }: { thought: Thought }) {
+  const { t } = useTranslation();
+  const [expanded, setExpanded] = useState(false);
+
+  return (
+    <div className="flex items-start gap-3">
+      <div className="flex-shrink-0 w-8 h-8 rounded-full flex items-center justify-center bg-muted">
+        <Brain className="w-4 h-4 text-muted-foreground" />
+      </div>
+      <div className="flex-1 max-w-[80%] text-sm text-muted-foreground">
+        <button
+          onClick={() => setExpanded((value) => !value)}
+          className="flex items-center gap-1 py-1.5 hover:text-foreground transition-colors"
+        >
+          <ChevronRight className={cn("w-4 h-4 transition-transform", expanded && "rotate-90")} />
+          {t("chat.thinking")}
+        </button>
+        {expanded && (
+          <div className="whitespace-pre-wrap border-l-2 border-border pl-3 italic">
+            {thought.content}
+          </div>
+        )}
+      </div>
+    </div>
+  );
+}
+
+/** Where a correction interrupted the agent; hovering shows what the interrupted turn had done */
+function SteeringNote({ steering }: { steering: Steering }) {
+  const { t } = useTranslation();
+
+  return (
+    <div className="flex items-center gap-2 py-1 text-xs text-muted-foreground" title={steering.summary}>
+      <CornerDownRight className="w-3.5 h-3.5" />
+      {t("chat.steered")}
+    </div>
+  );
+}
+
+/** A prompt that failed while nobody was waiting for it, e.g. a queued prompt */
+function PromptFailureNote({ failure }: { failure: PromptFailure }) {
+  const { t } = useTranslation();
+
+  return (
+    <div className="flex items-start gap-2 py-1 text-xs text-destructive">
+      <AlertCircle className="w-3.5 h-3.5 mt-0.5 flex-shrink-0" />
+      <span className="break-words">{t("chat.promptFailed", { error: failure.error })}</span>
+    </div>
+  );
+}
+
 function MessageBubble({ message }: { message: Message }) {
   const { t } = useTranslation();
   const isUser = message.role === "user";
@@ -14,6 +14,8 @@ function getStatusBadgeStyles(status: SessionStatus): string {
       return "bg-orange-500/20 text-orange-600 dark:text-orange-400"; // Orange for waiting
     case "idle":
       return "bg-green-500/20 text-green-600 dark:text-green-400"; // Green for ready
+    case "error":
+      return "bg-red-500/20 text-red-600 dark:text-red-400"; // Red for failed recovery
     case "stopped":
     default:
       return "bg-gray-500/20 text-gray-600 dark:text-gray-400"; // Gray for stopped
@@ -29,6 +31,8 @@ function getStatusLabelKey(status: SessionStatus): string {
       return "session.status.pending";
     case "idle":
       return "session.status.ready";
+    case "error":
+      return "session.status.error";
     case "stopped":
     default:
       return "session.status.stopped";
@@ -0,0 +1,91 @@
+import { useState } from "react";
+import { useTranslation } from "react-i18next";
+import {
+  Dialog,
+  DialogContent,
+  DialogDescription,
+  DialogFooter,
+  DialogHeader,
+  DialogTitle,
+} from "@/components/ui/dialog";
+import { Button } from "@/components/ui/button";
+import { useAgentStore } from "@/stores/agentStore";
+import { agentAPI } from "@/services/api";
+import { KeyRound } from "lucide-react";
+
+/**
+ * Dialog shown when an agent refuses requests until the user logs in.
+ * Requests it refused are sent again once any client (desktop or phone) logs in.
+ */
+export function AuthDialog() {
+  const { t } = useTranslation();
+  const authRequest = useAgentStore((state) => state.authRequest);
+  const [pendingMethod, setPendingMethod] = useState<string | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  const handleMethod = async (methodId: string) => {
+    setPendingMethod(methodId);
+    setError(null);
+    try {
+      // The dialog closes on `agent/authenticated`
+      await agentAPI.authenticate(methodId);
+    } catch (e) {
+      setError(e instanceof Error ? e.message : String(e));
+    } finally {
+      setPendingMethod(null);
+    }
+  };
+
+  const handleDismiss = () => {
+    setError(null);
+    agentAPI.dismissAuth();
+  };
+
+  if (!authRequest) return null;
+
+  return (
+    <Dialog open={!!authRequest} onOpenChange={(open) => !open && handleDismiss()}>
+      <DialogContent className="max-w-[calc(100vw-2rem)] sm:max-w-md">
+        <DialogHeader>
+          <DialogTitle className="flex items-center gap-2">
+            <KeyRound className="w-5 h-5 text-primary flex-shrink-0" />
+            <span className="truncate">{t("auth.title")}</span>
+          </DialogTitle>
+          <DialogDescription className="text-xs sm:text-sm">
+            {t("auth.description", { agentId: authRequest.agentId })}
+          </DialogDescription>
+        </DialogHeader>
+
+        <div className="flex flex-col gap-2">
+          {authRequest.authMethods.map((method) => (
+            <Button
+              key={method.id}
+              variant="outline"
+              disabled={pendingMethod !== null}
+              onClick={() => handleMethod(method.id)}
+              className="h-auto flex flex-col items-start py-2 text-left"
+            >
+              <span className="text-sm">{method.name}</span>
+              {method.description && (
+                <span className="text-xs text-muted-foreground whitespace-normal">
+                  {method.description}
+                </span>
+              )}
+            </Button>
+          ))}
+          {error && (
+            <p className="text-xs text-destructive break-words">
+              {t("auth.failed", { error })}
+            </p>
+          )}
+        </div>
+
+        <DialogFooter>
+          <Button variant="ghost" size="sm" onClick={handleDismiss}>
+            {t("common.cancel")}
+          </Button>
+        </DialogFooter>
+      </DialogContent>
+    </Dialog>
+  );
+}
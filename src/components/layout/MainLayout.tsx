@@ -6,6 +6,7 @@ import { ChatView } from "@/components/chat";
 import { EditorPanel } from "@/components/editor";
 import { TerminalPanel } from "@/components/terminal";
 import { PermissionDialog } from "@/components/common/PermissionDialog";
+import { AuthDialog } from "@/components/common/AuthDialog";
 import { SettingsPage } from "@/components/settings";
 import { useFileStore } from "@/stores/fileStore";
 import { useTerminalStore } from "@/stores/terminalStore";
@@ -84,6 +85,7 @@ function DesktopLayout() {
       </main>
       <StatusBar />
       <PermissionDialog />
+      <AuthDialog />
     </div>
   );
 }
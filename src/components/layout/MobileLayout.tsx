@@ -7,6 +7,7 @@ import { SettingsPage } from "@/components/settings";
 import { XTerminal } from "@/components/terminal/XTerminal";
 import { FileTree } from "@/components/editor/FileTree";
 import { PermissionDialog } from "@/components/common/PermissionDialog";
+import { AuthDialog } from "@/components/common/AuthDialog";
 import { WsSetupDialog } from "@/components/common/WsSetupDialog";
 import { useMobileNavStore, type MobileView } from "@/stores/mobileNavStore";
 import { useAgentStore } from "@/stores/agentStore";
@@ -675,6 +676,7 @@ export function MobileLayout() {
 
       <MobileTabBar />
       <PermissionDialog />
+      <AuthDialog />
       <WsSetupDialog
         open={showWsSetup}
         onOpenChange={setShowWsSetup}
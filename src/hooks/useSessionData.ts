@@ -2,9 +2,9 @@
  * useSessionData Hook
  *
  * Provides session data with robust client-server synchronization:
- * 1. Fetches full state from server on mount/session change
- * 2. Listens for incremental updates via notifications
- * 3. Re-fetches on reconnection for recovery
+ * 1. Subscribes to the session on mount/session change, getting its full state
+ * 2. Applies the numbered updates of the subscription (session/state_update) in order
+ * 3. Resumes the subscription from the last update on reconnection
  *
  * The server is the single source of truth. This hook manages
  * local cache and keeps it in sync with server state.
@@ -14,10 +14,9 @@ import { useState, useEffect, useCallback, useRef } from "react";
 import type {
   SessionId,
   SessionState,
-  SessionUpdate,
   SessionStateUpdate,
+  SequencedStateUpdate,
   Message,
-  ToolCall,
 } from "@/types/acp";
 import { getTransport } from "@/services/transport";
 import type { WebSocketTransport } from "@/services/transport/websocket";
@@ -49,6 +48,10 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
 
   // Track current session to avoid race conditions
   const currentSessionRef = useRef<SessionId | null>(null);
+  // Sequence number of the last update applied, to resume the subscription from
+  const lastSeqRef = useRef(0);
+  // Updates that arrived before the subscription returned the state
+  const pendingUpdatesRef = useRef<SequencedStateUpdate[] | null>(null);
 
   /**
    * Fetch full session state from server
@@ -105,6 +108,37 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
           };
         }
 
+        case "thought_chunk": {
+          // Only append if the LAST item is a thought (preserve ordering)
+          const newChatItems = [...prev.chatItems];
+          const lastItem = newChatItems[newChatItems.length - 1];
+
+          if (lastItem && lastItem.type === "thought") {
+            newChatItems[newChatItems.length - 1] = {
+              type: "thought",
+              thought: {
+                ...lastItem.thought,
+                content: lastItem.thought.content + update.content,
+              },
+            };
+            return { ...prev, chatItems: newChatItems, updatedAt: Date.now() };
+          }
+          return prev;
+        }
+
+        case "thought_added": {
+          const exists = prev.chatItems.some(
+            (item) => item.type === "thought" && item.thought.id === update.thought.id
+          );
+          if (exists) return prev;
+
+          return {
+            ...prev,
+            chatItems: [...prev.chatItems, { type: "thought", thought: update.thought }],
+            updatedAt: Date.now(),
+          };
+        }
+
         case "tool_call_added": {
           const exists = prev.chatItems.some(
             (item) => item.type === "tool_call" && item.toolCall.toolCallId === update.toolCall.toolCallId
@@ -128,6 +162,32 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
           return { ...prev, chatItems: newChatItems, updatedAt: Date.now() };
         }
 
+        case "turn_steered": {
+          const exists = prev.chatItems.some(
+            (item) => item.type === "steering" && item.steering.id === update.steering.id
+          );
+          if (exists) return prev;
+
+          return {
+            ...prev,
+            chatItems: [...prev.chatItems, { type: "steering", steering: update.steering }],
+            updatedAt: Date.now(),
+          };
+        }
+
+        case "prompt_failed": {
+          const exists = prev.chatItems.some(
+            (item) => item.type === "prompt_failure" && item.failure.id === update.failure.id
+          );
+          if (exists) return prev;
+
+          return {
+            ...prev,
+            chatItems: [...prev.chatItems, { type: "prompt_failure", failure: update.failure }],
+            updatedAt: Date.now(),
+          };
+        }
+
         case "plan_updated": {
           return { ...prev, plan: update.plan, updatedAt: Date.now() };
         }
@@ -145,156 +205,60 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
           };
         }
 
-        case "full_state": {
-          return update.state;
-        }
-
-        case "dangerous_mode_updated": {
-          return { ...prev, dangerousMode: update.dangerousMode, updatedAt: Date.now() };
-        }
-
-        case "noop":
-        default:
-          return prev;
-      }
-    });
-  }, []);
-
-  /**
-   * Apply incremental update to local state
-   */
-  const applyUpdate = useCallback((update: SessionUpdate) => {
-    setState((prev) => {
-      if (!prev) return prev;
-
-      switch (update.sessionUpdate) {
-        case "user_message_chunk":
-        case "agent_message_chunk": {
-          if (update.content.type !== "text") return prev;
-
-          const role = update.sessionUpdate === "user_message_chunk" ? "user" : "assistant";
-          const text = update.content.text;
-
-          // Only append if the LAST item is a message with same role (preserve ordering)
-          const newChatItems = [...prev.chatItems];
-          const lastItem = newChatItems[newChatItems.length - 1];
-
-          if (lastItem && lastItem.type === "message" && lastItem.message.role === role) {
-            // Append to existing message
-            newChatItems[newChatItems.length - 1] = {
-              type: "message",
-              message: {
-                ...lastItem.message,
-                content: lastItem.message.content + text,
-              },
-            };
-            return { ...prev, chatItems: newChatItems, updatedAt: Date.now() };
-          }
-
-          // Create new message (last item is not a message with same role)
-          const newMessage: Message = {
-            id: generateUUID(),
-            role,
-            content: text,
-            timestamp: Date.now(),
-          };
+        case "current_model_updated": {
+          if (!prev.models) return prev;
           return {
             ...prev,
-            chatItems: [...prev.chatItems, { type: "message", message: newMessage }],
+            models: { ...prev.models, currentModelId: update.modelId },
             updatedAt: Date.now(),
           };
         }
 
-        case "tool_call": {
-          // Check if tool call already exists
-          const existingIndex = prev.chatItems.findIndex(
-            (item) => item.type === "tool_call" && item.toolCall.toolCallId === update.toolCallId
-          );
+        case "session_info_updated": {
+          return { ...prev, title: update.title ?? prev.title, updatedAt: Date.now() };
+        }
 
-          if (existingIndex >= 0) {
-            // Update existing tool call (merge fields)
-            const newChatItems = [...prev.chatItems];
-            const existing = newChatItems[existingIndex] as { type: "tool_call"; toolCall: ToolCall };
-            newChatItems[existingIndex] = {
-              type: "tool_call",
-              toolCall: {
-                ...existing.toolCall,
-                title: update.title ?? existing.toolCall.title,
-                kind: update.kind ?? existing.toolCall.kind,
-                status: update.status ?? existing.toolCall.status,
-                rawInput: update.rawInput ?? existing.toolCall.rawInput,
-                rawOutput: update.rawOutput ?? existing.toolCall.rawOutput,
-                content: update.content ?? existing.toolCall.content,
-                locations: update.locations ?? existing.toolCall.locations,
-              },
-            };
-            return { ...prev, chatItems: newChatItems, updatedAt: Date.now() };
-          }
+        case "usage_updated": {
+          return { ...prev, usage: update.usage, updatedAt: Date.now() };
+        }
 
-          // New tool call - add it
-          // Note: session/state_update may also send tool_call_added, applyStateUpdate handles dedup
-          const newToolCall: ToolCall = {
-            toolCallId: update.toolCallId,
-            title: update.title ?? "",
-            kind: update.kind,
-            status: update.status,
-            rawInput: update.rawInput,
-            rawOutput: update.rawOutput,
-            content: update.content,
-            locations: update.locations,
-          };
-          return {
-            ...prev,
-            chatItems: [...prev.chatItems, { type: "tool_call", toolCall: newToolCall }],
-            updatedAt: Date.now(),
-          };
+        case "config_options_updated": {
+          return { ...prev, configOptions: update.configOptions, updatedAt: Date.now() };
         }
 
-        case "tool_call_update": {
-          const newChatItems = prev.chatItems.map((item) => {
-            if (item.type === "tool_call" && item.toolCall.toolCallId === update.toolCallId) {
-              return {
-                type: "tool_call" as const,
-                toolCall: {
-                  ...item.toolCall,
-                  title: update.title ?? item.toolCall.title,
-                  kind: update.kind ?? item.toolCall.kind,
-                  status: update.status ?? item.toolCall.status,
-                  rawInput: update.rawInput ?? item.toolCall.rawInput,
-                  rawOutput: update.rawOutput ?? item.toolCall.rawOutput,
-                  content: update.content ?? item.toolCall.content,
-                  locations: update.locations ?? item.toolCall.locations,
-                },
-              };
-            }
-            return item;
-          });
-          return { ...prev, chatItems: newChatItems, updatedAt: Date.now() };
+        case "full_state": {
+          return update.state;
         }
 
-        case "plan": {
-          return { ...prev, plan: { entries: update.entries }, updatedAt: Date.now() };
+        case "dangerous_mode_updated": {
+          return { ...prev, dangerousMode: update.dangerousMode, updatedAt: Date.now() };
         }
 
-        case "available_commands_update": {
-          return { ...prev, availableCommands: update.availableCommands, updatedAt: Date.now() };
+        case "queued_prompts_updated": {
+          return { ...prev, queuedPrompts: update.queuedPrompts, updatedAt: Date.now() };
         }
 
-        case "current_mode_update": {
-          if (!prev.modes) return prev;
-          return {
-            ...prev,
-            modes: { ...prev.modes, currentModeId: update.currentModeId },
-            updatedAt: Date.now(),
-          };
+        case "pending_permission_updated": {
+          return { ...prev, pendingPermission: update.pendingPermission ?? null, updatedAt: Date.now() };
         }
 
+        case "noop":
         default:
           return prev;
       }
     });
   }, []);
 
+  /**
+   * Apply a numbered update once; repeats of updates already applied are skipped
+   */
+  const applySequencedUpdate = useCallback((update: SequencedStateUpdate) => {
+    // A full state always applies (a restarted server may number from an older snapshot)
+    if (update.updateType !== "full_state" && update.seq <= lastSeqRef.current) return;
+    lastSeqRef.current = update.seq;
+    applyStateUpdate(update);
+  }, [applyStateUpdate]);
+
   /**
    * Refresh session state from server
    */
@@ -308,6 +272,7 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
       const newState = await fetchSessionState(sessionId);
       // Only update if this is still the current session
       if (currentSessionRef.current === sessionId) {
+        lastSeqRef.current = newState.seq ?? 0;
         setState(newState);
       }
     } catch (err) {
@@ -385,16 +350,24 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
       return;
     }
 
-    // Fetch initial state (with autoResume=true since user explicitly selected this session)
+    const transport = getTransport() as WebSocketTransport;
+    lastSeqRef.current = 0;
+    pendingUpdatesRef.current = [];
+
+    // Subscribe for the initial state (with autoResume=true since user explicitly selected this session)
     const loadState = async () => {
       setIsLoading(true);
       setError(null);
 
       try {
         // Use autoResume=true for initial load - user explicitly selected this session
-        const newState = await fetchSessionState(sessionId, true);
+        const newState = await transport.subscribeSession(sessionId, true);
         if (currentSessionRef.current === sessionId) {
+          lastSeqRef.current = newState.seq ?? 0;
           setState(newState);
+          const pending = pendingUpdatesRef.current ?? [];
+          pendingUpdatesRef.current = null;
+          pending.forEach(applySequencedUpdate);
 
           // Check if there's a pending permission request for this session
           // If so, show the permission dialog
@@ -417,25 +390,17 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
 
     loadState();
 
-    // Setup update listeners using public transport API
-    const transport = getTransport() as WebSocketTransport;
-
-    // Listen for ACP session updates (real-time updates from agent during prompts)
-    const handleUpdate = (update: SessionUpdate) => {
-      if (currentSessionRef.current === sessionId) {
-        applyUpdate(update);
-      }
-    };
-
-    // Listen for backend state updates (cross-client syncing)
-    const handleStateUpdate = (update: SessionStateUpdate) => {
-      if (currentSessionRef.current === sessionId) {
-        applyStateUpdate(update);
+    // Listen for the subscription's updates (agent output and changes from any client)
+    const handleStateUpdate = (update: SequencedStateUpdate) => {
+      if (currentSessionRef.current !== sessionId) return;
+      if (pendingUpdatesRef.current) {
+        pendingUpdatesRef.current.push(update);
+      } else {
+        applySequencedUpdate(update);
       }
     };
 
     // Use public API methods - they return unsubscribe functions
-    const unsubscribeSessionUpdate = transport.onSessionUpdate(sessionId, handleUpdate);
     const unsubscribeStateUpdate = transport.onSessionStateUpdate(
       sessionId,
       handleStateUpdate as (update: unknown) => void
@@ -443,10 +408,10 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
 
     // Cleanup using returned unsubscribe functions
     return () => {
-      unsubscribeSessionUpdate();
       unsubscribeStateUpdate();
+      transport.unsubscribeSession(sessionId).catch(console.error);
     };
-  }, [sessionId, isConnected, fetchSessionState, applyUpdate, applyStateUpdate]);
+  }, [sessionId, isConnected, applySequencedUpdate]);
 
   // Listen for reconnection events
   useEffect(() => {
@@ -454,17 +419,25 @@ export function useSessionData(sessionId: SessionId | null): UseSessionDataResul
 
     const transport = getTransport() as WebSocketTransport;
 
-    // Re-fetch state when connection is restored
-    const handleReconnect = () => {
-      console.log("Connection restored, re-fetching session state...");
-      refresh();
+    // Catch up on the updates missed while disconnected
+    const handleReconnect = async () => {
+      console.log("Connection restored, resuming session subscription...");
+      try {
+        const { updates } = await transport.resumeSubscription(sessionId, lastSeqRef.current);
+        if (currentSessionRef.current === sessionId) {
+          updates.forEach(applySequencedUpdate);
+        }
+      } catch (err) {
+        console.error("Failed to resume session subscription:", err);
+        refresh();
+      }
     };
 
     // Use public onReconnect API - returns unsubscribe function
     const unsubscribe = transport.onReconnect(handleReconnect);
 
     return unsubscribe;
-  }, [sessionId, refresh]);
+  }, [sessionId, refresh, applySequencedUpdate]);
 
   return {
     state,
@@ -145,6 +145,11 @@ class AgentAPI {
         }
       });
 
+      // Show the agent's login methods; refused requests are retried after login
+      transport.onAuthRequired((request) => {
+        useAgentStore.getState().setAuthRequest(request);
+      });
+
       // Subscribe to session activation events from backend
       this.sessionActivatedUnsubscribe = transport.onSessionActivated((sessionId) => {
         console.log("Session activated from backend:", sessionId);
@@ -447,7 +452,7 @@ class AgentAPI {
   /**
    * Send a prompt to a session
    * Note: User message is added optimistically by the UI hook,
-   * and confirmed by server via session/update notification.
+   * and confirmed by server via session/state_update notification.
    * Permission handling is done via global handler (set on connect)
    * which broadcasts to all clients like messages.
    * @param sessionId - Session ID to send to
@@ -501,6 +506,25 @@ class AgentAPI {
     agentStore.setPendingPermission(null);
   }
 
+  /**
+   * Log in to the agent that asked with one of its auth methods
+   */
+  async authenticate(methodId: string): Promise<void> {
+    const transport = getTransport() as WebSocketTransport;
+    const authRequest = useAgentStore.getState().authRequest;
+    if (!authRequest) return;
+    await transport.authenticate(authRequest.agentId, methodId);
+  }
+
+  /**
+   * Decline to log in; requests waiting for it fail with the agent's error
+   */
+  dismissAuth(): void {
+    const transport = getTransport() as WebSocketTransport;
+    transport.rejectAuthBlockedRequests();
+    useAgentStore.getState().setAuthRequest(null);
+  }
+
   /**
    * Cancel current prompt in a session (only works if session is running)
    */
@@ -549,6 +573,14 @@ class AgentAPI {
     await transport.setSessionMode(sessionId, modeId);
   }
 
+  /**
+   * Set session model
+   */
+  async setSessionModel(sessionId: SessionId, modelId: string): Promise<void> {
+    const transport = getTransport();
+    await transport.setSessionModel(sessionId, modelId);
+  }
+
   /**
    * Add a project to recent projects (syncs with server)
    */
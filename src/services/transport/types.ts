@@ -3,9 +3,14 @@ import type {
   SessionInfo,
   ListSessionsResponse,
   NewSessionResponse,
+  RewindSessionResponse,
   PromptResponse,
+  QueuedPrompt,
+  QueuedPromptResponse,
+  SteerResponse,
   SessionUpdate,
   SessionState,
+  ResumeSubscriptionResponse,
   PermissionRequest,
   PermissionOutcome,
   Implementation,
@@ -34,6 +39,11 @@ export interface Transport {
   // Session management
   resumeSession(sessionId: string, cwd: string): Promise<NewSessionResponse>;
   forkSession(sessionId: string, cwd: string): Promise<NewSessionResponse>;
+  /**
+   * Branch a session at a user message and send an edited version of it; the original is kept.
+   * The agent still sees the replaced turns, see `replacedTurnsInAgentContext`.
+   */
+  rewindSession(sessionId: SessionId, messageId: string, newContent: string): Promise<RewindSessionResponse>;
   listSessions(cwd?: string, limit?: number, offset?: number): Promise<ListSessionsResponse>;
   getSessionInfo(sessionId: string): Promise<SessionInfo>;
 
@@ -45,16 +55,34 @@ export interface Transport {
       request: PermissionRequest
     ) => Promise<PermissionOutcome>,
     messageId?: string
-  ): Promise<PromptResponse>;
+  ): Promise<PromptResponse | QueuedPromptResponse>;
 
   cancelSession(sessionId: SessionId): Promise<void>;
 
+  /** Interrupt the running turn with a correction, sent as soon as the agent stops */
+  steerSession(
+    sessionId: SessionId,
+    content: string,
+    messageId?: string
+  ): Promise<SteerResponse | PromptResponse | QueuedPromptResponse>;
+
+  // Prompts sent while a turn is running wait in a per-session queue
+  updateQueuedPrompt(sessionId: SessionId, queuedPromptId: string, content: string): Promise<QueuedPrompt[]>;
+  reorderQueuedPrompts(sessionId: SessionId, queuedPromptIds: string[]): Promise<QueuedPrompt[]>;
+  cancelQueuedPrompt(sessionId: SessionId, queuedPromptId: string): Promise<QueuedPrompt[]>;
+
   setSessionMode(sessionId: SessionId, modeId: string): Promise<void>;
 
+  setSessionModel(sessionId: SessionId, modelId: string): Promise<void>;
+
   // Session state subscription methods
   subscribeSession(sessionId: SessionId): Promise<SessionState>;
+  /** Subscribe and catch up on the updates missed after `sinceSeq` */
+  resumeSubscription(sessionId: SessionId, sinceSeq: number): Promise<ResumeSubscriptionResponse>;
   unsubscribeSession(sessionId: SessionId): Promise<void>;
   getSessionState(sessionId: SessionId): Promise<SessionState>;
+  /** Rebuild a session from its ACP traffic recording (requires `agent.recordTraffic`) */
+  replayRecording(sessionId: SessionId): Promise<SessionState>;
 
   // Generic request for custom methods
   request<T>(method: string, params?: unknown): Promise<T>;
@@ -5,13 +5,20 @@ import type {
   ListSessionsResponse,
   NewSessionParams,
   NewSessionResponse,
+  RewindSessionResponse,
   PromptResponse,
+  QueuedPrompt,
+  QueuedPromptResponse,
+  SteerResponse,
   SessionUpdate,
   SessionState,
+  ResumeSubscriptionResponse,
   PermissionRequest,
   PermissionOutcome,
   MCPServer,
+  AuthRequest,
 } from "@/types/acp";
+import { AUTH_REQUIRED_ERROR_CODE } from "@/types/acp";
 import { useSessionStore } from "@/stores/sessionStore";
 import type {
   ListPluginsResponse,
@@ -41,6 +48,7 @@ interface JsonRpcNotification {
 }
 
 type PendingRequest = {
+  request: JsonRpcRequest;
   resolve: (value: unknown) => void;
   reject: (error: Error) => void;
 };
@@ -62,6 +70,12 @@ export class WebSocketTransport implements Transport {
   private sessionActivatedHandler: ((sessionId: string | null) => void) | null = null;
   private permissionResolvedHandler: ((requestId: unknown, sessionId: string | null) => void) | null = null;
   private reconnectHandlers = new Set<() => void>();
+  // Requests the agent refused until the user logs in, by ID with the refusal message;
+  // sent again once any client authenticates
+  private authBlockedRequests = new Map<number, string>();
+  private authRequiredHandler: ((request: AuthRequest | null) => void) | null = null;
+  // Subscribers of each session; the server subscription ends with the last one
+  private sessionSubscribers = new Map<SessionId, number>();
   // Heartbeat mechanism to detect connection loss
   private heartbeatInterval: number | null = null;
   private heartbeatTimeout: number | null = null;
@@ -156,6 +170,7 @@ export class WebSocketTransport implements Transport {
       pending.reject(new Error("WebSocket disconnected"));
       this.pendingRequests.delete(id);
     }
+    this.authBlockedRequests.clear();
 
     // Try to reconnect if we haven't exceeded max attempts
     if (this.reconnectAttempts < this.maxReconnectAttempts) {
@@ -278,6 +293,11 @@ export class WebSocketTransport implements Transport {
         const response = message as JsonRpcResponse;
         const pending = this.pendingRequests.get(response.id);
         if (pending) {
+          if (response.error?.code === AUTH_REQUIRED_ERROR_CODE) {
+            // Keep the caller waiting; the request is sent again after login
+            this.authBlockedRequests.set(response.id, response.error.message);
+            return;
+          }
           this.pendingRequests.delete(response.id);
           if (response.error) {
             pending.reject(new Error(response.error.message));
@@ -300,19 +320,6 @@ export class WebSocketTransport implements Transport {
     const { method, params } = notification;
 
     switch (method) {
-      case "session/update": {
-        const { sessionId, update } = params as {
-          sessionId: string;
-          update: SessionUpdate;
-        };
-        const handlers = this.eventHandlers.get(`session-update-${sessionId}`);
-        if (handlers) {
-          for (const handler of handlers) {
-            handler(update);
-          }
-        }
-        break;
-      }
       case "permission/request": {
         const request = params as PermissionRequest;
         if (this.globalPermissionHandler) {
@@ -371,6 +378,16 @@ export class WebSocketTransport implements Transport {
         }
         break;
       }
+      case "agent/auth_required": {
+        this.authRequiredHandler?.(params as AuthRequest);
+        break;
+      }
+      case "agent/authenticated": {
+        // Logged in (from this or another client): close the login prompt and retry
+        this.authRequiredHandler?.(null);
+        this.retryAuthBlockedRequests();
+        break;
+      }
       case "sessions/updated": {
         // Session list was updated (e.g., session stopped, status changed)
         const { sessions } = params as { sessions: SessionInfo[] };
@@ -401,6 +418,7 @@ export class WebSocketTransport implements Transport {
 
     return new Promise((resolve, reject) => {
       this.pendingRequests.set(id, {
+        request,
         resolve: resolve as (value: unknown) => void,
         reject,
       });
@@ -408,6 +426,39 @@ export class WebSocketTransport implements Transport {
     });
   }
 
+  /**
+   * Send the requests refused until login again, with their original IDs
+   */
+  private retryAuthBlockedRequests() {
+    const ids = [...this.authBlockedRequests.keys()];
+    this.authBlockedRequests.clear();
+    for (const id of ids) {
+      const pending = this.pendingRequests.get(id);
+      if (pending && this.ws?.readyState === WebSocket.OPEN) {
+        console.debug("[WS] >>> (after login)", pending.request.method, pending.request);
+        this.ws.send(JSON.stringify(pending.request));
+      }
+    }
+  }
+
+  /**
+   * Fail the requests refused until login, e.g. when the user declines to log in
+   */
+  rejectAuthBlockedRequests(): void {
+    for (const [id, message] of this.authBlockedRequests) {
+      this.pendingRequests.get(id)?.reject(new Error(message));
+      this.pendingRequests.delete(id);
+    }
+    this.authBlockedRequests.clear();
+  }
+
+  /**
+   * Log in to an agent; requests it refused are retried on `agent/authenticated`
+   */
+  async authenticate(agentId: string, methodId: string): Promise<void> {
+    await this.send("authenticate", { agentId, methodId });
+  }
+
   async disconnect(): Promise<void> {
     if (!this.ws) return;
 
@@ -425,6 +476,7 @@ export class WebSocketTransport implements Transport {
     this.connected = false;
     this.eventHandlers.clear();
     this.pendingRequests.clear();
+    this.authBlockedRequests.clear();
   }
 
   isConnected(): boolean {
@@ -454,6 +506,10 @@ export class WebSocketTransport implements Transport {
     return this.send<NewSessionResponse>("fork_session", { sessionId, cwd });
   }
 
+  async rewindSession(sessionId: SessionId, messageId: string, newContent: string): Promise<RewindSessionResponse> {
+    return this.send<RewindSessionResponse>("rewind_session", { sessionId, messageId, newContent });
+  }
+
   async listSessions(cwd?: string, limit?: number, offset?: number): Promise<ListSessionsResponse> {
     return this.send<ListSessionsResponse>("list_sessions", { cwd, limit, offset });
   }
@@ -469,46 +525,67 @@ export class WebSocketTransport implements Transport {
   async prompt(
     sessionId: SessionId,
     content: string,
-    onUpdate: (update: SessionUpdate) => void,
+    _onUpdate: (update: SessionUpdate) => void,
     _onPermissionRequest: (request: PermissionRequest) => Promise<PermissionOutcome>,
     messageId?: string
-  ): Promise<PromptResponse> {
-    // Set up event handler for session updates
-    const eventKey = `session-update-${sessionId}`;
-    if (!this.eventHandlers.has(eventKey)) {
-      this.eventHandlers.set(eventKey, new Set());
-    }
-    // Wrap the handler to match the generic signature
-    const wrappedHandler = (data: unknown) => onUpdate(data as SessionUpdate);
-    this.eventHandlers.get(eventKey)!.add(wrappedHandler);
-
-    // Note: Permission handling is now done via globalPermissionHandler
-    // which is set once on connect and handles all permission requests
-
-    try {
-      const response = await this.send<PromptResponse>("send_prompt", {
-        sessionId,
-        content,
-        messageId, // Pass messageId to backend for deduplication
-      });
-      return response;
-    } finally {
-      // Clean up event handler
-      this.eventHandlers.get(eventKey)?.delete(wrappedHandler);
-      if (this.eventHandlers.get(eventKey)?.size === 0) {
-        this.eventHandlers.delete(eventKey);
-      }
-    }
+  ): Promise<PromptResponse | QueuedPromptResponse> {
+    // Note: Updates reach subscribed sessions as session/state_update, and permission
+    // handling is done via globalPermissionHandler which is set once on connect
+    return this.send<PromptResponse | QueuedPromptResponse>("send_prompt", {
+      sessionId,
+      content,
+      messageId, // Pass messageId to backend for deduplication
+    });
   }
 
   async cancelSession(sessionId: SessionId): Promise<void> {
     await this.send("cancel_session", { sessionId });
   }
 
+  /**
+   * Interrupt the running turn with a correction; an idle session gets it as a prompt
+   */
+  async steerSession(
+    sessionId: SessionId,
+    content: string,
+    messageId?: string
+  ): Promise<SteerResponse | PromptResponse | QueuedPromptResponse> {
+    return this.send("steer_session", { sessionId, content, messageId });
+  }
+
+  async updateQueuedPrompt(sessionId: SessionId, queuedPromptId: string, content: string): Promise<QueuedPrompt[]> {
+    const result = await this.send<{ queuedPrompts: QueuedPrompt[] }>("update_queued_prompt", {
+      sessionId,
+      queuedPromptId,
+      content,
+    });
+    return result.queuedPrompts;
+  }
+
+  async reorderQueuedPrompts(sessionId: SessionId, queuedPromptIds: string[]): Promise<QueuedPrompt[]> {
+    const result = await this.send<{ queuedPrompts: QueuedPrompt[] }>("reorder_queued_prompts", {
+      sessionId,
+      queuedPromptIds,
+    });
+    return result.queuedPrompts;
+  }
+
+  async cancelQueuedPrompt(sessionId: SessionId, queuedPromptId: string): Promise<QueuedPrompt[]> {
+    const result = await this.send<{ queuedPrompts: QueuedPrompt[] }>("cancel_queued_prompt", {
+      sessionId,
+      queuedPromptId,
+    });
+    return result.queuedPrompts;
+  }
+
   async setSessionMode(sessionId: SessionId, modeId: string): Promise<void> {
     await this.send("set_session_mode", { sessionId, modeId });
   }
 
+  async setSessionModel(sessionId: SessionId, modelId: string): Promise<void> {
+    await this.send("set_session_model", { sessionId, modelId });
+  }
+
   // Terminal event subscription for external use
   onTerminalOutput(handler: (output: { terminalId: string; data: string }) => void): () => void {
     const eventKey = "terminal:output";
@@ -540,6 +617,13 @@ export class WebSocketTransport implements Transport {
     this.globalPermissionHandler = handler;
   }
 
+  /**
+   * Set handler for agent login requests; called with null once logged in
+   */
+  onAuthRequired(handler: ((request: AuthRequest | null) => void) | null): void {
+    this.authRequiredHandler = handler;
+  }
+
   /**
    * Set handler for permission resolved notifications
    * Called when another client responds to a permission request
@@ -576,13 +660,38 @@ export class WebSocketTransport implements Transport {
    * @param autoResume - If true, automatically resume historical sessions (default: true)
    */
   async subscribeSession(sessionId: SessionId, autoResume = true): Promise<SessionState> {
+    this.sessionSubscribers.set(sessionId, (this.sessionSubscribers.get(sessionId) ?? 0) + 1);
     return this.send<SessionState>("subscribe_session", { sessionId, autoResume });
   }
 
+  /**
+   * Subscribe to session state updates, catching up from a known sequence number
+   * Returns the updates after `sinceSeq`, or a single full_state if they are gone
+   * @param sessionId - Session ID to subscribe to
+   * @param sinceSeq - `seq` of the last update the client applied
+   * @param autoResume - If true, automatically resume historical sessions (default: true)
+   */
+  async resumeSubscription(
+    sessionId: SessionId,
+    sinceSeq: number,
+    autoResume = true
+  ): Promise<ResumeSubscriptionResponse> {
+    return this.send<ResumeSubscriptionResponse>("subscribe_session", { sessionId, sinceSeq, autoResume });
+  }
+
   /**
    * Unsubscribe from session state updates
+   * Updates keep coming while other subscribers of the session remain
    */
   async unsubscribeSession(sessionId: SessionId): Promise<void> {
+    const remaining = (this.sessionSubscribers.get(sessionId) ?? 1) - 1;
+    if (remaining > 0) {
+      this.sessionSubscribers.set(sessionId, remaining);
+      return;
+    }
+    this.sessionSubscribers.delete(sessionId);
+    // A closed connection already ended the subscription
+    if (!this.connected) return;
     await this.send("unsubscribe_session", { sessionId });
   }
 
@@ -596,37 +705,23 @@ export class WebSocketTransport implements Transport {
   }
 
   /**
-   * Generic request method for hooks
+   * Rebuild a session from its recorded ACP traffic, for reproducing bug reports
+   * @param sessionId - Session ID of the recording
    */
-  async request<T>(method: string, params?: unknown): Promise<T> {
-    return this.send<T>(method, params);
+  async replayRecording(sessionId: SessionId): Promise<SessionState> {
+    return this.send<SessionState>("replay_recording", { sessionId });
   }
 
   /**
-   * Subscribe to ACP session update notifications for a specific session
-   * These are real-time updates from the ACP agent during prompts
-   * @param sessionId - Session ID to listen for updates
-   * @param handler - Callback for session updates
-   * @returns Unsubscribe function
+   * Generic request method for hooks
    */
-  onSessionUpdate(sessionId: string, handler: (update: SessionUpdate) => void): () => void {
-    const eventKey = `session-update-${sessionId}`;
-    if (!this.eventHandlers.has(eventKey)) {
-      this.eventHandlers.set(eventKey, new Set());
-    }
-    this.eventHandlers.get(eventKey)!.add(handler as (data: unknown) => void);
-
-    return () => {
-      this.eventHandlers.get(eventKey)?.delete(handler as (data: unknown) => void);
-      if (this.eventHandlers.get(eventKey)?.size === 0) {
-        this.eventHandlers.delete(eventKey);
-      }
-    };
+  async request<T>(method: string, params?: unknown): Promise<T> {
+    return this.send<T>(method, params);
   }
 
   /**
    * Subscribe to session state update notifications for a specific session
-   * These are backend state sync updates (includes cross-client syncing)
+   * Only sessions this client subscribed to (subscribeSession) send them
    * @param sessionId - Session ID to listen for updates
    * @param handler - Callback for state updates
    * @returns Unsubscribe function
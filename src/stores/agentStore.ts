@@ -3,6 +3,7 @@ import { immer } from "zustand/middleware/immer";
 import type {
   AgentCapabilities,
   AuthMethod,
+  AuthRequest,
   Implementation,
   PermissionRequest,
 } from "@/types/acp";
@@ -19,6 +20,8 @@ interface AgentState {
   agentCapabilities: AgentCapabilities | null;
   authMethods: AuthMethod[];
   isAuthenticated: boolean;
+  // Agent login asked for by the server; refused requests wait for it
+  authRequest: AuthRequest | null;
   pendingPermission: PermissionRequest | null;
   error: string | null;
   // Detected local server URL when connection fails (desktop app only)
@@ -31,6 +34,7 @@ interface AgentActions {
   setAgentCapabilities: (capabilities: AgentCapabilities) => void;
   setAuthMethods: (methods: AuthMethod[]) => void;
   setAuthenticated: (authenticated: boolean) => void;
+  setAuthRequest: (request: AuthRequest | null) => void;
   setPendingPermission: (request: PermissionRequest | null) => void;
   setError: (error: string | null) => void;
   setDetectedLocalServer: (url: string | null) => void;
@@ -43,6 +47,7 @@ const initialState: AgentState = {
   agentCapabilities: null,
   authMethods: [],
   isAuthenticated: false,
+  authRequest: null,
   pendingPermission: null,
   error: null,
   detectedLocalServer: null,
@@ -87,6 +92,12 @@ export const useAgentStore = create<AgentState & AgentActions>()(
       });
     },
 
+    setAuthRequest: (request) => {
+      set((state) => {
+        state.authRequest = request;
+      });
+    },
+
     setPendingPermission: (request) => {
       set((state) => {
         state.pendingPermission = request;
@@ -57,6 +57,15 @@ export interface AuthMethod {
   description?: string;
 }
 
+/** Error code of requests an agent refuses until the user logs in */
+export const AUTH_REQUIRED_ERROR_CODE = -32000;
+
+/** `agent/auth_required`: an agent wants the user to log in with one of its methods */
+export interface AuthRequest {
+  agentId: string;
+  authMethods: AuthMethod[];
+}
+
 export interface SessionMode {
   id: SessionModeId;
   name: string;
@@ -169,6 +178,8 @@ export interface PermissionRequest {
   sessionId: SessionId;
   toolCall: ToolCallUpdate;
   options: PermissionOption[];
+  /** Agent instance that asked; the server sends the answer back to it */
+  agentId?: string;
 }
 
 export type PermissionOutcome =
@@ -234,7 +245,27 @@ export type SessionUpdate =
       sessionUpdate: "available_commands_update";
       availableCommands: AvailableCommand[];
     }
-  | { sessionUpdate: "current_mode_update"; currentModeId: SessionModeId };
+  | { sessionUpdate: "current_mode_update"; currentModeId: SessionModeId }
+  | { sessionUpdate: "session_info_update"; title?: string | null; updatedAt?: string | null }
+  | ({ sessionUpdate: "usage_update" } & UsageInfo)
+  | { sessionUpdate: "config_option_update"; configOptions: SessionConfigOption[] };
+
+/** Context window usage of a session */
+export interface UsageInfo {
+  used: number;
+  size: number;
+  cost?: { amount: number; currency: string };
+}
+
+/** A session setting exposed by the agent (e.g. reasoning effort) */
+export interface SessionConfigOption {
+  id: string;
+  name: string;
+  description?: string;
+  type?: string;
+  currentValue?: unknown;
+  [key: string]: unknown;
+}
 
 export interface SessionNotification {
   sessionId: SessionId;
@@ -252,10 +283,48 @@ export interface NewSessionResponse {
   models?: SessionModelState;
 }
 
+/** The session and user message a branch was made from */
+export interface BranchPoint {
+  sessionId: SessionId;
+  messageId: string;
+}
+
+/** A branch made by editing an earlier user message; its prompt runs after this returns */
+export interface RewindSessionResponse extends NewSessionResponse {
+  branchedFrom: BranchPoint;
+  /**
+   * The agent's own context still has the replaced message and the turns after it
+   * (ACP forks a whole session); the agent is only told to disregard them
+   */
+  replacedTurnsInAgentContext: boolean;
+}
+
 export interface PromptResponse {
   stopReason: StopReason;
 }
 
+/** `send_prompt` result when a turn was running and the prompt was queued */
+export interface QueuedPromptResponse {
+  queued: true;
+  queuedPromptId: string;
+}
+
+/** A prompt waiting for the running turn to finish */
+export interface QueuedPrompt {
+  id: string;
+  prompt: Array<TextContent | ImageContent | { type: string; [key: string]: unknown }>;
+  messageId?: string;
+  /** Correction that interrupts the running turn (`steer_session`) */
+  steer?: boolean;
+  queuedAt: number;
+}
+
+/** `steer_session` result when a running turn is being interrupted */
+export interface SteerResponse {
+  steered: true;
+  queuedPromptId: string;
+}
+
 export interface Message {
   id: string;
   role: "user" | "assistant";
@@ -265,10 +334,38 @@ export interface Message {
   sendFailed?: boolean;
 }
 
-// Unified chat item - can be a message or tool call
+/** Agent reasoning, kept apart from the assistant's answer */
+export interface Thought {
+  id: string;
+  content: string;
+  timestamp: number;
+}
+
+/** A correction that interrupted a turn (`steer_session`) */
+export interface Steering {
+  id: string;
+  correction: string;
+  /** What the interrupted turn had done, if it was cancelled mid-way */
+  summary?: string;
+  timestamp: number;
+}
+
+/** A prompt that failed with no client waiting for the answer, e.g. a queued prompt */
+export interface PromptFailure {
+  id: string;
+  /** The user message of the prompt, if it was added to the chat */
+  messageId?: string;
+  error: string;
+  timestamp: number;
+}
+
+// Unified chat item - can be a message, an agent thought, a tool call, a steering note or a failed prompt
 export type ChatItem =
   | { type: "message"; message: Message }
-  | { type: "tool_call"; toolCall: ToolCall };
+  | { type: "thought"; thought: Thought }
+  | { type: "tool_call"; toolCall: ToolCall }
+  | { type: "steering"; steering: Steering }
+  | { type: "prompt_failure"; failure: PromptFailure };
 
 export interface Session {
   id: SessionId;
@@ -288,7 +385,7 @@ export interface Session {
 }
 
 /** Session status for UI display */
-export type SessionStatus = "stopped" | "idle" | "running" | "pending";
+export type SessionStatus = "stopped" | "idle" | "running" | "pending" | "error";
 
 // Session info from backend (list_sessions response)
 export interface SessionInfo {
@@ -305,6 +402,10 @@ export interface SessionInfo {
   lastAssistantMessage?: string;
   /** Whether the session has any agent response (text or tool call) */
   hasAgentResponse: boolean;
+  /** Where this session was branched off by editing a message */
+  branchedFrom?: BranchPoint;
+  /** Sessions branched off this one */
+  branches?: SessionId[];
 }
 
 export interface ListSessionsResponse {
@@ -327,10 +428,20 @@ export interface SessionState {
   modes?: SessionModeState;
   models?: SessionModelState;
   availableCommands?: AvailableCommand[];
+  /** Title set by the agent */
+  title?: string;
+  /** Context window usage reported by the agent */
+  usage?: UsageInfo;
+  /** Session settings exposed by the agent */
+  configOptions?: SessionConfigOption[];
   /** Pending permission request waiting for user response */
   pendingPermission?: PermissionRequest | null;
   /** Dangerous mode - auto-approve all tool calls for this session */
   dangerousMode?: boolean;
+  /** Prompts waiting for the running turn, sent in order */
+  queuedPrompts?: QueuedPrompt[];
+  /** Sequence number of the last update, to resume a subscription from (`sinceSeq`) */
+  seq?: number;
   createdAt: number;
   updatedAt: number;
 }
@@ -342,11 +453,31 @@ export interface SessionState {
 export type SessionStateUpdate =
   | { updateType: "message_chunk"; content: string }
   | { updateType: "message_added"; message: Message }
+  | { updateType: "thought_chunk"; content: string }
+  | { updateType: "thought_added"; thought: Thought }
   | { updateType: "tool_call_added"; toolCall: ToolCall }
   | { updateType: "tool_call_updated"; toolCall: ToolCall }
   | { updateType: "plan_updated"; plan: Plan }
   | { updateType: "available_commands_updated"; commands: AvailableCommand[] }
   | { updateType: "current_mode_updated"; modeId: SessionModeId }
+  | { updateType: "current_model_updated"; modelId: string }
+  | { updateType: "session_info_updated"; title?: string | null }
+  | { updateType: "usage_updated"; usage: UsageInfo }
+  | { updateType: "config_options_updated"; configOptions: SessionConfigOption[] }
   | { updateType: "full_state"; state: SessionState }
   | { updateType: "dangerous_mode_updated"; dangerousMode: boolean }
+  | { updateType: "queued_prompts_updated"; queuedPrompts: QueuedPrompt[] }
+  | { updateType: "turn_steered"; steering: Steering }
+  | { updateType: "prompt_failed"; failure: PromptFailure }
+  | { updateType: "pending_permission_updated"; pendingPermission?: PermissionRequest | null }
   | { updateType: "noop" };
+
+/** A state update numbered in the order it was applied to the session */
+export type SequencedStateUpdate = SessionStateUpdate & { seq: number };
+
+/** `subscribe_session` result when resuming from `sinceSeq` */
+export interface ResumeSubscriptionResponse {
+  sessionId: SessionId;
+  /** Updates after `sinceSeq`, or a single `full_state` if they are no longer available */
+  updates: SequencedStateUpdate[];
+}